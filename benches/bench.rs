@@ -10,8 +10,8 @@ extern crate test;
 use emubayer::*;
 use test::Bencher;
 
-static SAMPLE_EVEN_SIZE: &'static str = "samples/input/even_size.png";
-static SAMPLE_ODD_SIZE: &'static str = "samples/input/odd_size.png";
+static SAMPLE_EVEN_SIZE: &str = "samples/input/even_size.png";
+static SAMPLE_ODD_SIZE: &str = "samples/input/odd_size.png";
 
 #[bench]
 fn bench_even_sample(b: &mut Bencher) {
@@ -19,7 +19,9 @@ fn bench_even_sample(b: &mut Bencher) {
         let rgb_image = RgbImage::from_file(SAMPLE_EVEN_SIZE).unwrap();
 
         let raw_image = rgb_image.to_raw(BayerPattern::RGGB);
-        raw_image.save_as_dng("samples/output/even_size.dng");
+        raw_image
+            .save_as_dng("samples/output/even_size.dng")
+            .unwrap();
     });
 }
 
@@ -29,6 +31,8 @@ fn bench_odd_sample(b: &mut Bencher) {
         let rgb_image = RgbImage::from_file(SAMPLE_ODD_SIZE).unwrap();
 
         let raw_image = rgb_image.to_raw(BayerPattern::RGGB);
-        raw_image.save_as_dng("samples/output/odd_size.dng");
+        raw_image
+            .save_as_dng("samples/output/odd_size.dng")
+            .unwrap();
     });
 }
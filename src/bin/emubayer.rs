@@ -2,30 +2,34 @@
 // Project: emubayer
 // License: GNU GPL Version 3 (https://www.gnu.org/licenses/gpl-3.0.en.html)
 
+#[macro_use]
 extern crate clap;
 extern crate emubayer;
 
-use clap::{App, Arg};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 use emubayer::*;
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process,
+};
 
 fn main() {
     let matches = App::new("emubayer")
                             .version("0.1")
                             .author("Cláudio Gomes (TofuLynx) <cfpgcp3@gmail.com>")
-                            .about("Bayer CFA camera emulator that takes a \"picture\" of a provided PNG image and saves the result as a DNG file.")
+                            .about("Bayer CFA camera emulator that takes a \"picture\" of a provided PNG, OpenEXR, Radiance HDR or PFM image and saves the result as a DNG file.")
                             .arg(Arg::with_name("INPUT_FILE")
-                                .help("Sets the input PNG file to use")
-                                .long_help("Sets the input PNG file to use. It must be a RGB image.")
+                                .help("Sets the input PNG, TIFF, PPM, JPEG, OpenEXR, Radiance HDR or PFM file to use")
+                                .long_help("Sets the input image to use, told apart by its first bytes: a PNG file, which must be a RGB image, a TIFF, PPM or JPEG file, or a float OpenEXR, Radiance HDR or PFM file of linear light, where 1 is the white level at 0 EV. Values of float images past white clip, unless brought down by --bracket.")
                                 .required(true)
                                 .index(1)
                                 )
                             .arg(Arg::with_name("BAYERPATTERN")
                                 .help("Sets the Bayer Pattern to use")
-                                .long_help("Sets the Bayter Pattern to use. Digital image sensors use a Color Filter Array with a specific pattern, usually called Bayer Filter Mosaic, which follows a pattern that is called Bayer Pattern here. There are 4 possible patterns: RGGB, BGGR, GRBG and GBRG; where R means Red, G means Green and B means Blue.")
+                                .long_help("Sets the Bayter Pattern to use. Digital image sensors use a Color Filter Array with a specific pattern, usually called Bayer Filter Mosaic, which follows a pattern that is called Bayer Pattern here. There are 4 possible patterns: RGGB, BGGR, GRBG and GBRG; where R means Red, G means Green and B means Blue. Fujifilm's 6x6 X-Trans pattern can also be emulated with XTRANS. The complementary CYGM and CMY patterns are also available by name. Any other pattern can be given as its rows separated by slashes, such as GGRGGB/GGBGGR/BRGRBG/GGBGGR/GGRGGB/RBGBRG, using the colors R, G, B, C (Cyan), M (Magenta), Y (Yellow) and W (White). Quad Bayer and Nona-Bayer patterns, where each color covers a 2x2 or 3x3 cell, are given as QUAD-RGGB or NONA-RGGB. Sensors without a CFA are emulated with MONOCHROME, whose photosites see the white weights, or with LINEARRAW, which keeps all three channels of every pixel as a perfectly demosaiced reference.")
                                 .required(true)
                                 .takes_value(true)
-                                .possible_values(&["RGGB", "BGGR", "GRBG", "GBRG"])
-                                .case_insensitive(true)
                                 .index(2)
                                 )
                             .arg(Arg::with_name("OUTPUT_FILE")
@@ -34,33 +38,1282 @@ fn main() {
                                 .takes_value(true)
                                 .index(3)
                                 )
+                            .arg(Arg::with_name("DEFOCUS")
+                                .help("Sets the radius, in pixels, of the defocus blur disc")
+                                .long("defocus")
+                                .takes_value(true)
+                                .value_name("RADIUS")
+                                )
+                            .arg(Arg::with_name("AIRY")
+                                .help("Sets the radius, in pixels, of the first dark ring of the Airy diffraction pattern")
+                                .long("airy")
+                                .takes_value(true)
+                                .value_name("RADIUS")
+                                )
+                            .arg(Arg::with_name("GAUSSIAN")
+                                .help("Sets the standard deviation, in pixels, of the Gaussian lens blur")
+                                .long("gaussian")
+                                .takes_value(true)
+                                .value_name("SIGMA")
+                                )
+                            .arg(Arg::with_name("OLPF")
+                                .help("Sets the dot separation, in pixels, of the optical low-pass filter")
+                                .long_help("Sets the dot separation, in pixels, of the optical low-pass filter. The filter splits every point of the image into four dots before it reaches the sensor, as the birefringent plates in front of most camera sensors do. Lens blurs are applied first, in the order: defocus, Airy, Gaussian.")
+                                .long("olpf")
+                                .takes_value(true)
+                                .value_name("SEPARATION")
+                                )
+                            .arg(Arg::with_name("SUPERSAMPLE")
+                                .help("Sets how many input pixels, on each axis, are integrated by every photosite")
+                                .long_help("Sets how many input pixels, on each axis, are integrated by every photosite. The input image must then be that many times larger than the emulated sensor. Optical filter distances are always given in photosites.")
+                                .long("supersample")
+                                .takes_value(true)
+                                .value_name("FACTOR")
+                                )
+                            .arg(Arg::with_name("FILL_FACTOR")
+                                .help("Sets the fraction of each photosite area that collects light when supersampling")
+                                .long("fill-factor")
+                                .takes_value(true)
+                                .value_name("FRACTION")
+                                .requires("SUPERSAMPLE")
+                                )
+                            .arg(Arg::with_name("BIN")
+                                .help("Sums every cell of a Quad or Nona-Bayer pattern into an ordinary Bayer image")
+                                .long("bin")
+                                )
+                            .arg(Arg::with_name("WHITE_WEIGHTS")
+                                .help("Sets the weights of the red, green and blue channels seen by white (clear) and monochrome photosites")
+                                .long_help("Sets the weights of the red, green and blue channels seen by white (clear) photosites, such as 0.2126,0.7152,0.0722 (the default, Rec. 709 luminance) or 1,1,1 for a filter that lets all light through. Values above the white level are clipped.")
+                                .long("white-weights")
+                                .takes_value(true)
+                                .value_name("R,G,B")
+                                .conflicts_with("WHITE_SPECTRUM")
+                                )
+                            .arg(Arg::with_name("WHITE_SPECTRUM")
+                                .help("Sets the transmission curve of white (clear) photosites from a CSV file")
+                                .long_help("Sets the transmission curve of white (clear) photosites from a CSV file, with one \"wavelength in nm,transmission from 0 to 1\" pair per line.")
+                                .long("white-spectrum")
+                                .takes_value(true)
+                                .value_name("FILE")
+                                )
+                            .arg(Arg::with_name("COMPLEMENTARY_GAIN")
+                                .help("Sets the gain of Cyan, Magenta and Yellow photosites over the sum of the primaries they transmit")
+                                .long_help("Sets the gain of Cyan, Magenta and Yellow photosites over the sum of the primaries they transmit. Cyan sees green and blue, Magenta sees red and blue, and Yellow sees red and green. The default, 0.5, keeps these samples within the white level; 1 emulates ideal filters, clipping bright areas.")
+                                .long("complementary-gain")
+                                .takes_value(true)
+                                .value_name("GAIN")
+                                )
+                            .args(&noise_args())
+                            .arg(readout_arg())
+                            .arg(Arg::with_name("ODD_SIZE")
+                                .help("Sets how images with an odd width or height are captured: crop, pad, mirror or error")
+                                .long_help("Sets how images with an odd width or height are fitted to the 2x2 grid of a CFA: crop drops the last row and column, pad repeats them, mirror reflects the image past its edge, and error refuses to capture it. Padded and mirrored captures record the original image area as the DNG default crop. Defaults to crop.")
+                                .long("odd-size")
+                                .takes_value(true)
+                                .value_name("POLICY")
+                                )
+                            .arg(Arg::with_name("WINDOW")
+                                .help("Reads out only a window of the sensor, as X,Y,WIDTH,HEIGHT in photosites")
+                                .long_help("Reads out only a window of the sensor, as X,Y,WIDTH,HEIGHT in photosites, after any supersampling. The CFA pattern of the raw data follows the window's origin, so an odd X turns RGGB into GRBG.")
+                                .long("window")
+                                .takes_value(true)
+                                .value_name("X,Y,WIDTH,HEIGHT")
+                                )
+                            .arg(Arg::with_name("ORIENTATION")
+                                .help("Sets how the camera is held, as an Orientation tag value from 1 to 8 or its name")
+                                .long_help("Sets how the camera is held, as the Orientation tag value that displays the input upright from the sensor's data: 1 normal, 2 flip-horizontal, 3 rotate-180, 4 flip-vertical, 5 transpose, 6 rotate-90, 7 transverse or 8 rotate-270. For instance, 6 emulates a camera turned to portrait. The DNG file records it in its Orientation tag, unless --bake-orientation is given. EXIF orientations of input images are always applied first.")
+                                .long("orientation")
+                                .takes_value(true)
+                                .value_name("ORIENTATION")
+                                )
+                            .arg(Arg::with_name("BAKE_ORIENTATION")
+                                .help("Rotates and flips the mosaic itself into the orientation, adjusting its CFA pattern")
+                                .long("bake-orientation")
+                                .requires("ORIENTATION")
+                                )
+                            .arg(Arg::with_name("SRGB_INPUT")
+                                .help("Decodes the sRGB transfer curve of the input, so the sensor sees linear light")
+                                .long("srgb-input")
+                                )
+                            .arg(Arg::with_name("BURST")
+                                .help("Captures every frame of an APNG file, or of the numbered image sequence starting at the input, into a CinemaDNG-style folder")
+                                .long_help("Captures every frame of an APNG file, or of the numbered image sequence starting at the input file, such as frame_0001.png, frame_0002.png and so on. Frames are saved as DNG files with time codes in a folder named after the output file, and draw independent noise.")
+                                .long("burst")
+                                .conflicts_with("GROUND_TRUTH")
+                                )
+                            .arg(Arg::with_name("FRAME_RATE")
+                                .help("Sets the frame rate of a burst, which defaults to the APNG delays or to 24 frames per second for sequences")
+                                .long("frame-rate")
+                                .takes_value(true)
+                                .value_name("FPS")
+                                .requires("BURST")
+                                )
+                            .arg(Arg::with_name("VIDEO_FRAME_RATE")
+                                .help("Records a burst at the given frame rate, instead of the burst's own")
+                                .long_help("Records a burst at the given frame rate, instead of the burst's own, for as long as the burst lasts. Bursts rendered at a higher frame rate than the camera's let the exposure time and rolling shutter blend several of their frames.")
+                                .long("video-frame-rate")
+                                .takes_value(true)
+                                .value_name("FPS")
+                                .requires("BURST")
+                                )
+                            .arg(Arg::with_name("EXPOSURE_TIME")
+                                .help("Sets the exposure time, in seconds or as a fraction such as 1/50, recorded in the DNG files")
+                                .long_help("Sets the exposure time, in seconds or as a fraction such as 1/50, recorded in the DNG files. Frames of a burst are exposed for that long, blurring motion, and brackets scale it by their exposure values, starting from 1/60 s when it isn't given.")
+                                .long("exposure-time")
+                                .takes_value(true)
+                                .value_name("SECONDS")
+                                )
+                            .arg(Arg::with_name("ROLLING_SHUTTER")
+                                .help("Reads the last row of the sensor the given time after the first, in seconds or as a fraction, skewing motion")
+                                .long("rolling-shutter")
+                                .takes_value(true)
+                                .value_name("SECONDS")
+                                .requires("BURST")
+                                )
+                            .arg(Arg::with_name("BRACKET")
+                                .help("Captures one DNG file per exposure value, such as -2,0,2, named after the output")
+                                .long_help("Captures one DNG file per exposure value, such as -2,0,2, each that many stops brighter than the input. Brighter frames clip their highlights and darker frames are noisier. The files end in their exposure value, such as _ev-2.dng, and record it as ExposureBiasValue along with their ExposureTime. With --ground-truth, a single ground truth at 0 EV is saved for the whole bracket.")
+                                .long("bracket")
+                                .takes_value(true)
+                                .value_name("EVS")
+                                .allow_hyphen_values(true)
+                                .conflicts_with("BURST")
+                                )
+                            .arg(Arg::with_name("HEADROOM")
+                                .help("Keeps the given number of stops of highlights past the white of the input")
+                                .long_help("Keeps the given number of stops of highlights past the white of the input, such as those of float images. The sensor is exposed that many stops lower and the DNG white level is lowered to the input's white, so that float DNG files hold the brighter highlights above 1. Integer DNG files clip them when rendered.")
+                                .long("headroom")
+                                .takes_value(true)
+                                .value_name("STOPS")
+                                .conflicts_with_all(&["BURST", "BRACKET", "GROUND_TRUTH"])
+                                )
+                            .arg(Arg::with_name("SAMPLE_FORMAT")
+                                .help("Sets how DNG samples are stored: integer, float16, float24 or float32")
+                                .long_help("Sets how DNG samples are stored: as 16-bit integers between the black and white levels, or as 16, 24 or 32-bit floats scaled to a black level of 0 and a white level of 1, which keep the values past white. Defaults to integer.")
+                                .long("sample-format")
+                                .takes_value(true)
+                                .value_name("FORMAT")
+                                )
+                            .arg(Arg::with_name("COMPRESSION")
+                                .help("Sets how DNG raw data is compressed: none or deflate")
+                                .long_help("Sets how DNG raw data is compressed: none, or deflate for Adobe Deflate after a predictor, which makes smaller files. Defaults to none.")
+                                .long("compression")
+                                .takes_value(true)
+                                .value_name("METHOD")
+                                )
+                            .arg(Arg::with_name("PREDICTOR")
+                                .help("Sets the predictor of deflated DNG files: none, horizontal, horizontal-x2 or horizontal-x4")
+                                .long_help("Sets the predictor of deflated DNG files: none, or differencing from the previous pixel, from the pixel two before or from the pixel four before. Integer samples are differenced horizontally, with Predictor 2, 34892 or 34893, and float samples byte by byte, with Predictor 34894, 34895 or 34896. Defaults to horizontal.")
+                                .long("predictor")
+                                .takes_value(true)
+                                .value_name("PREDICTOR")
+                                .requires("COMPRESSION")
+                                )
+                            .arg(Arg::with_name("BYTE_ORDER")
+                                .help("Sets the byte order of DNG files: little (II) or big (MM)")
+                                .long_help("Sets the byte order of DNG files: little, for \"II\" files, or big, for \"MM\" files. Defaults to little.")
+                                .long("byte-order")
+                                .takes_value(true)
+                                .value_name("ORDER")
+                                )
+                            .arg(Arg::with_name("BIG_TIFF")
+                                .help("Saves DNG files as BigTIFF")
+                                .long_help("Saves DNG files as BigTIFF, whose 8-byte offsets allow raw data over 4 GB, which classic TIFF files can't hold.")
+                                .long("bigtiff")
+                                )
+                            .arg(Arg::with_name("GROUND_TRUTH")
+                                .help("Also saves the image that reached the sensor and a JSON file describing the capture")
+                                .long_help("Also saves the image that reached the sensor, aligned with the raw data, displayed in its orientation and in its linear color space, as a 16-bit PNG file ending in .truth.png, and a JSON file describing every parameter of the capture. Demosaiced captures should be compared against this image.")
+                                .long("ground-truth")
+                                )
+                            .setting(AppSettings::SubcommandsNegateReqs)
+                            .subcommand(SubCommand::with_name("demosaic")
+                                .about("Demosaics a DNG file, or a \"picture\" taken of a PNG image, into a PNG image with a reference algorithm, displayed in the DNG file's orientation.")
+                                .arg(Arg::with_name("INPUT_FILE")
+                                    .help("Sets the input DNG or PNG file to use")
+                                    .required(true)
+                                    .index(1)
+                                    )
+                                .arg(Arg::with_name("OUTPUT_FILE")
+                                    .help("Sets the filename of the output PNG file")
+                                    .required(true)
+                                    .index(2)
+                                    )
+                                .arg(Arg::with_name("BAYERPATTERN")
+                                    .help("Sets the Bayer Pattern to take the \"picture\" of a PNG image with. DNG files carry their own")
+                                    .long("pattern")
+                                    .takes_value(true)
+                                    .value_name("PATTERN")
+                                    )
+                                .arg(Arg::with_name("ALGORITHM")
+                                    .help("Sets the demosaicing algorithm to use")
+                                    .long_help("Sets the demosaicing algorithm to use: nearest, bilinear, malvar (Malvar-He-Cutler), vng or ahd. Nearest and bilinear work on any pattern of red, green and blue filters, while the others need a 2x2 Bayer pattern.")
+                                    .long("algorithm")
+                                    .takes_value(true)
+                                    .value_name("ALGORITHM")
+                                    .default_value("bilinear")
+                                    )
+                                )
+                            .subcommand(SubCommand::with_name("compare")
+                                .about("Scores a reconstructed PNG image, such as a demosaiced one, against the original PNG image.")
+                                .arg(Arg::with_name("ORIGINAL_FILE")
+                                    .help("Sets the original PNG file")
+                                    .required(true)
+                                    .index(1)
+                                    )
+                                .arg(Arg::with_name("RECONSTRUCTED_FILE")
+                                    .help("Sets the reconstructed PNG file")
+                                    .required(true)
+                                    .index(2)
+                                    )
+                                .arg(Arg::with_name("GRID")
+                                    .help("Also scores every region of a grid with the given number of columns and rows")
+                                    .long("grid")
+                                    .takes_value(true)
+                                    .value_name("COLUMNSxROWS")
+                                    )
+                                .arg(Arg::with_name("REGION")
+                                    .help("Also scores the given region. Can be used several times")
+                                    .long("region")
+                                    .takes_value(true)
+                                    .multiple(true)
+                                    .number_of_values(1)
+                                    .value_name("X,Y,WIDTH,HEIGHT")
+                                    )
+                                .arg(Arg::with_name("JSON")
+                                    .help("Prints the metrics as a JSON array, with the whole image first")
+                                    .long("json")
+                                    )
+                                )
+                            .subcommand(SubCommand::with_name("bench-demosaic")
+                                .about("Takes a \"picture\" of every PNG image in a directory with every pattern, demosaics it and scores the result against the image that reached the sensor.")
+                                .arg(Arg::with_name("INPUT_DIRECTORY")
+                                    .help("Sets the directory of images to use, such as PNG, TIFF, PPM or JPEG files")
+                                    .required(true)
+                                    .index(1)
+                                    )
+                                .arg(Arg::with_name("PATTERN")
+                                    .help("Sets a Bayer Pattern to use. Can be used several times. Defaults to RGGB, BGGR, GRBG and GBRG")
+                                    .long("pattern")
+                                    .takes_value(true)
+                                    .multiple(true)
+                                    .number_of_values(1)
+                                    .value_name("PATTERN")
+                                    )
+                                .arg(Arg::with_name("ALGORITHM")
+                                    .help("Sets a built-in demosaicing algorithm to use. Can be used several times. Defaults to all of them, unless external demosaicers are given")
+                                    .long("algorithm")
+                                    .takes_value(true)
+                                    .multiple(true)
+                                    .number_of_values(1)
+                                    .value_name("ALGORITHM")
+                                    )
+                                .arg(Arg::with_name("EXTERNAL")
+                                    .help("Sets an external demosaicer to use. Can be used several times")
+                                    .long_help("Sets an external demosaicer to use, as a name and a command, such as \"dcraw=dcraw_wrapper {input} {output}\". The command is split on whitespace and run without a shell, with {input} replaced by the DNG file to read and {output} by the PNG file to write. Can be used several times.")
+                                    .long("external")
+                                    .takes_value(true)
+                                    .multiple(true)
+                                    .number_of_values(1)
+                                    .value_name("NAME=COMMAND")
+                                    )
+                                .arg(Arg::with_name("FORMAT")
+                                    .help("Sets the format of the results table")
+                                    .long("format")
+                                    .takes_value(true)
+                                    .possible_values(&["csv", "json"])
+                                    .default_value("csv")
+                                    )
+                                .arg(Arg::with_name("OUTPUT_FILE")
+                                    .help("Sets the file to write the results table to, instead of the standard output")
+                                    .long("output")
+                                    .takes_value(true)
+                                    .value_name("FILE")
+                                    )
+                                .args(&noise_args())
+                                .arg(readout_arg())
+                                )
                             .get_matches();
 
+    if let Some(matches) = matches.subcommand_matches("demosaic") {
+        demosaic(matches);
+        return;
+    }
+
+    if let Some(matches) = matches.subcommand_matches("compare") {
+        compare(matches);
+        return;
+    }
+
+    if let Some(matches) = matches.subcommand_matches("bench-demosaic") {
+        bench_demosaic(matches);
+        return;
+    }
+
     let input_path = matches.value_of("INPUT_FILE").unwrap();
 
-    let output_path = matches
-        .value_of("OUTPUT_FILE")
-        .unwrap_or_else(|| matches.value_of("INPUT_FILE").unwrap())
-        .trim_end_matches(".png")
-        .trim_end_matches(".dng")
-        .to_string()
-        + ".dng";
+    let output_path = match matches.value_of("OUTPUT_FILE") {
+        Some(output_path) => {
+            output_path
+                .trim_end_matches(".png")
+                .trim_end_matches(".dng")
+                .to_string()
+                + ".dng"
+        }
+        None => Path::new(input_path)
+            .with_extension("dng")
+            .to_string_lossy()
+            .into_owned(),
+    };
+
+    let layout: SensorLayout = matches
+        .value_of("BAYERPATTERN")
+        .unwrap()
+        .parse()
+        .unwrap_or_else(|err| exit_with_error(err));
+
+    let mut camera = Camera::new(layout.clone());
+
+    if matches.is_present("DEFOCUS") {
+        let radius = parse_filter_size(&matches, "DEFOCUS");
+        camera = camera.with_optical_filter(OpticalFilter::Defocus { radius });
+    }
+
+    if matches.is_present("AIRY") {
+        let radius = parse_filter_size(&matches, "AIRY");
+        camera = camera.with_optical_filter(OpticalFilter::Airy { radius });
+    }
+
+    if matches.is_present("GAUSSIAN") {
+        let sigma = parse_filter_size(&matches, "GAUSSIAN");
+        camera = camera.with_optical_filter(OpticalFilter::Gaussian { sigma });
+    }
+
+    if matches.is_present("OLPF") {
+        let separation = parse_filter_size(&matches, "OLPF");
+        camera = camera.with_optical_filter(OpticalFilter::Olpf { separation });
+    }
+
+    if matches.is_present("SUPERSAMPLE") {
+        let factor = value_t!(matches, "SUPERSAMPLE", u32).unwrap_or_else(|e| e.exit());
+        let aperture = if matches.is_present("FILL_FACTOR") {
+            Aperture::FillFactor(value_t!(matches, "FILL_FACTOR", f32).unwrap_or_else(|e| e.exit()))
+        } else {
+            Aperture::Box
+        };
+        camera = camera.with_supersampling(factor, aperture);
+    }
+
+    let mut filter_responses = FilterResponses::new();
+
+    if let Some(weights) = matches.value_of("WHITE_WEIGHTS") {
+        let weights = parse_weights(weights).unwrap_or_else(|err| exit_with_error(err));
+        filter_responses = filter_responses.with_response(CfaColor::White, weights);
+    }
+
+    if let Some(path) = matches.value_of("WHITE_SPECTRUM") {
+        let curve = read_spectral_curve(path).unwrap_or_else(|err| exit_with_error(err));
+        filter_responses = filter_responses
+            .with_spectral_response(CfaColor::White, &curve)
+            .unwrap_or_else(|err| exit_with_error(err));
+    }
+
+    if matches.is_present("COMPLEMENTARY_GAIN") {
+        let gain = value_t!(matches, "COMPLEMENTARY_GAIN", f32).unwrap_or_else(|e| e.exit());
+        filter_responses = filter_responses.with_complementary_gain(gain);
+    }
+
+    camera = camera.with_filter_responses(filter_responses);
 
-    let bayer_pattern = BayerPattern::from_str(matches.value_of("BAYERPATTERN").unwrap());
+    if matches.is_present("BIN") {
+        camera = camera.with_cell_binning();
+    }
+
+    if let Some(noise) = parse_noise(&matches) {
+        camera = camera.with_noise(noise);
+    }
+
+    if let Some(readout_mode) = parse_readout(&matches) {
+        camera = camera.with_readout_mode(readout_mode);
+    }
+
+    if let Some(policy) = matches.value_of("ODD_SIZE") {
+        let policy = policy
+            .parse::<OddSizePolicy>()
+            .unwrap_or_else(|err| exit_with_error(err));
+        camera = camera.with_odd_size_policy(policy);
+    }
+
+    if let Some(window) = matches.value_of("WINDOW") {
+        let window = parse_region(window).unwrap_or_else(|err| exit_with_error(err));
+        camera = camera.with_readout_window(window);
+    }
+
+    if let Some(orientation) = matches.value_of("ORIENTATION") {
+        let orientation = orientation
+            .parse::<Orientation>()
+            .unwrap_or_else(|err| exit_with_error(err));
+        camera = camera.with_orientation(orientation);
+    }
+
+    if matches.is_present("BAKE_ORIENTATION") {
+        camera = camera.with_baked_orientation();
+    }
 
     println!("Using input file: {}", input_path);
-    println!("Using Bayer Pattern: {}", bayer_pattern);
+    println!("Using Bayer Pattern: {}", layout);
+
+    if let Some(frame_rate) = matches.value_of("VIDEO_FRAME_RATE") {
+        camera = camera.with_frame_rate(parse_seconds(frame_rate));
+    }
 
-    let rgb_image = match RgbImage::from_file(input_path) {
-        Ok(rgb_image) => rgb_image,
-        Err(err) => {
-            eprintln!("Error: {}", err);
-            std::process::exit(1);
+    if let Some(exposure_time) = matches.value_of("EXPOSURE_TIME") {
+        camera = camera.with_exposure_time(parse_seconds(exposure_time));
+    }
+
+    if let Some(readout_time) = matches.value_of("ROLLING_SHUTTER") {
+        camera = camera.with_rolling_shutter(parse_seconds(readout_time));
+    }
+
+    if matches.is_present("BURST") {
+        capture_burst(&matches, &camera, &output_path);
+        return;
+    }
+
+    if let Some(exposure_values) = matches.value_of("BRACKET") {
+        capture_bracket(&matches, &camera, &output_path, exposure_values);
+        return;
+    }
+
+    let srgb_input = matches.is_present("SRGB_INPUT");
+    let sample_format = parse_sample_format(&matches);
+    let compression = parse_compression(&matches);
+    let endianness = parse_endianness(&matches);
+
+    if matches.is_present("HEADROOM") {
+        let headroom = value_t!(matches, "HEADROOM", f64).unwrap_or_else(|e| e.exit());
+        let scene = read_scene(input_path, srgb_input);
+        let mut raw_image = camera
+            .capture_scene(&scene, headroom)
+            .unwrap_or_else(|err| exit_with_error(err))
+            .with_sample_format(sample_format)
+            .with_compression(compression)
+            .with_endianness(endianness);
+        if matches.is_present("BIG_TIFF") {
+            raw_image = raw_image.with_big_tiff();
         }
+        raw_image
+            .save_as_dng(&output_path)
+            .unwrap_or_else(|err| exit_with_error(err));
+
+        println!(
+            "DNG file with {} stops of headroom successfully saved as \"{}\".",
+            headroom, output_path
+        );
+        return;
+    }
+
+    let rgb_image = read_input(input_path, srgb_input);
+    let input_size = (rgb_image.width(), rgb_image.height());
+
+    let ground_truth = if matches.is_present("GROUND_TRUTH") {
+        Some(
+            camera
+                .ground_truth(rgb_image.clone())
+                .unwrap_or_else(|err| exit_with_error(err)),
+        )
+    } else {
+        None
     };
 
-    let raw_image = rgb_image.to_raw(bayer_pattern);
-    raw_image.save_as_dng(&output_path);
+    let mut raw_image = camera
+        .capture(rgb_image)
+        .unwrap_or_else(|err| exit_with_error(err))
+        .with_sample_format(sample_format)
+        .with_compression(compression)
+        .with_endianness(endianness);
+    if matches.is_present("BIG_TIFF") {
+        raw_image = raw_image.with_big_tiff();
+    }
+    raw_image
+        .save_as_dng(&output_path)
+        .unwrap_or_else(|err| exit_with_error(err));
 
     println!("DNG file successfully saved as \"{}\".", output_path);
+
+    if let Some(ground_truth) = ground_truth {
+        let fields = format!(
+            "\"input\":{},\"input_size\":[{},{}],\"srgb_input\":{},\"raw\":{},\"raw_size\":[{},{}],\"black_level\":{},\"white_level\":{}",
+            json_string(input_path),
+            input_size.0,
+            input_size.1,
+            srgb_input,
+            json_string(&output_path),
+            raw_image.width(),
+            raw_image.height(),
+            raw_image.black_level(),
+            raw_image.white_level()
+        );
+        save_ground_truth(
+            ground_truth,
+            &raw_image,
+            output_path.trim_end_matches(".dng"),
+            &fields,
+            &camera,
+        );
+    }
+}
+
+/// Saves the ground truth of a capture as a 16-bit PNG file, and a JSON
+/// sidecar holding the given fields followed by those of the ground truth
+/// and the camera.
+fn save_ground_truth(
+    ground_truth: RgbImage,
+    raw_image: &RawImage,
+    base_path: &str,
+    fields: &str,
+    camera: &Camera,
+) {
+    // Demosaiced DNG files are displayed upright, and so is the ground truth.
+    let ground_truth = ground_truth.oriented(raw_image.orientation());
+    let truth_path = format!("{}.truth.png", base_path);
+    let sidecar_path = format!("{}.json", base_path);
+
+    ground_truth
+        .save_as_png(&truth_path)
+        .unwrap_or_else(|err| exit_with_error(err));
+
+    let sidecar = format!(
+        "{{{},\"ground_truth\":{},\"ground_truth_size\":[{},{}],\"camera\":{}}}\n",
+        fields,
+        json_string(&truth_path),
+        ground_truth.width(),
+        ground_truth.height(),
+        camera.to_json()
+    );
+    fs::write(&sidecar_path, sidecar)
+        .unwrap_or_else(|_| exit_with_error("JSON sidecar couldn't be written."));
+
+    println!(
+        "Ground truth successfully saved as \"{}\", described by \"{}\".",
+        truth_path, sidecar_path
+    );
+}
+
+fn capture_burst(matches: &ArgMatches, camera: &Camera, output_path: &str) {
+    let input_path = matches.value_of("INPUT_FILE").unwrap();
+    let mut burst = Burst::from_file(input_path).unwrap_or_else(|err| exit_with_error(err));
+
+    if let Some(frame_rate) = matches.value_of("FRAME_RATE") {
+        burst = burst
+            .with_frame_rate(parse_seconds(frame_rate))
+            .unwrap_or_else(|err| exit_with_error(err));
+    }
+
+    if matches.is_present("SRGB_INPUT") {
+        let frames = burst
+            .frames()
+            .iter()
+            .map(RgbImage::linearize_srgb)
+            .collect();
+        burst = Burst::new(frames, burst.frame_rate()).unwrap_or_else(|err| exit_with_error(err));
+    }
+
+    println!(
+        "Using {} frames at {} frames per second.",
+        burst.frames().len(),
+        burst.frame_rate()
+    );
+
+    let directory = output_path.trim_end_matches(".dng");
+    let paths = camera
+        .capture_burst(&burst)
+        .and_then(|raw_burst| {
+            let mut raw_burst = raw_burst
+                .with_sample_format(parse_sample_format(matches))
+                .with_compression(parse_compression(matches))
+                .with_endianness(parse_endianness(matches));
+            if matches.is_present("BIG_TIFF") {
+                raw_burst = raw_burst.with_big_tiff();
+            }
+            raw_burst.save_as_cinema_dng(directory)
+        })
+        .unwrap_or_else(|err| exit_with_error(err));
+
+    println!(
+        "{} DNG files successfully saved in \"{}\".",
+        paths.len(),
+        directory
+    );
+}
+
+fn capture_bracket(
+    matches: &ArgMatches,
+    camera: &Camera,
+    output_path: &str,
+    exposure_values: &str,
+) {
+    let exposure_values: Vec<f64> = exposure_values
+        .split(',')
+        .map(|ev| ev.trim().parse::<f64>())
+        .collect::<Result<_, _>>()
+        .unwrap_or_else(|_| exit_with_error("Exposure values must be numbers, such as -2,0,2."));
+
+    let input_path = matches.value_of("INPUT_FILE").unwrap();
+    let srgb_input = matches.is_present("SRGB_INPUT");
+    let scene = read_scene(input_path, srgb_input);
+
+    let ground_truth = if matches.is_present("GROUND_TRUTH") {
+        Some(
+            camera
+                .bracket_ground_truth(&scene)
+                .unwrap_or_else(|err| exit_with_error(err)),
+        )
+    } else {
+        None
+    };
+
+    let raw_images = camera
+        .capture_bracket(&scene, &exposure_values)
+        .unwrap_or_else(|err| exit_with_error(err));
+
+    let sample_format = parse_sample_format(matches);
+    let compression = parse_compression(matches);
+    let endianness = parse_endianness(matches);
+    let base_path = output_path.trim_end_matches(".dng");
+    let mut frames = Vec::with_capacity(raw_images.len());
+    for (raw_image, ev) in raw_images.into_iter().zip(&exposure_values) {
+        let path = format!("{}_ev{:+}.dng", base_path, ev);
+        let mut raw_image = raw_image
+            .with_sample_format(sample_format)
+            .with_compression(compression)
+            .with_endianness(endianness);
+        if matches.is_present("BIG_TIFF") {
+            raw_image = raw_image.with_big_tiff();
+        }
+        raw_image
+            .save_as_dng(&path)
+            .unwrap_or_else(|err| exit_with_error(err));
+        println!(
+            "DNG file at {:+} EV, exposed for {} s, successfully saved as \"{}\".",
+            ev,
+            raw_image.exposure_time().unwrap(),
+            path
+        );
+        frames.push((path, raw_image));
+    }
+
+    if let Some(ground_truth) = ground_truth {
+        let raw: Vec<String> = frames
+            .iter()
+            .map(|(path, raw_image)| {
+                format!(
+                    "{{\"path\":{},\"exposure_value\":{},\"exposure_time\":{}}}",
+                    json_string(path),
+                    raw_image.exposure_bias().unwrap(),
+                    raw_image.exposure_time().unwrap()
+                )
+            })
+            .collect();
+        let raw_image = &frames[0].1;
+        let fields = format!(
+            "\"input\":{},\"input_size\":[{},{}],\"srgb_input\":{},\"raw\":[{}],\"raw_size\":[{},{}],\"black_level\":{},\"white_level\":{}",
+            json_string(input_path),
+            scene.width(),
+            scene.height(),
+            srgb_input,
+            raw.join(","),
+            raw_image.width(),
+            raw_image.height(),
+            raw_image.black_level(),
+            raw_image.white_level()
+        );
+        save_ground_truth(ground_truth, raw_image, base_path, &fields, camera);
+    }
+}
+
+/// Reads the input as linear light. Float images hold it already, while
+/// other images are linearized with --srgb-input.
+fn read_scene(input_path: &str, srgb_input: bool) -> LinearImage {
+    if srgb_input {
+        LinearImage::from(read_input(input_path, true))
+    } else {
+        LinearImage::from_file(input_path).unwrap_or_else(|err| exit_with_error(err))
+    }
+}
+
+/// Reads the input image to capture. Float images are exposed at 0 EV,
+/// clipping whatever goes past white.
+fn read_input(input_path: &str, srgb_input: bool) -> RgbImage {
+    if srgb_input && LinearImage::is_float_file(input_path) {
+        exit_with_error("Float images hold linear light already, without an sRGB transfer curve.");
+    }
+
+    let rgb_image = RgbImage::from_file(input_path).unwrap_or_else(|err| exit_with_error(err));
+    if srgb_input {
+        rgb_image.linearize_srgb()
+    } else {
+        rgb_image
+    }
+}
+
+fn demosaic(matches: &ArgMatches) {
+    let input_path = matches.value_of("INPUT_FILE").unwrap();
+    let output_path = matches.value_of("OUTPUT_FILE").unwrap();
+
+    let algorithm: DemosaicAlgorithm = matches
+        .value_of("ALGORITHM")
+        .unwrap()
+        .parse()
+        .unwrap_or_else(|err| exit_with_error(err));
+
+    let raw_image = if RawImage::is_dng_file(input_path) {
+        RawImage::from_dng(input_path).unwrap_or_else(|err| exit_with_error(err))
+    } else {
+        let layout: SensorLayout = matches
+            .value_of("BAYERPATTERN")
+            .unwrap_or_else(|| exit_with_error("A pattern is needed to demosaic a PNG image."))
+            .parse()
+            .unwrap_or_else(|err| exit_with_error(err));
+
+        let rgb_image = RgbImage::from_file(input_path).unwrap_or_else(|err| exit_with_error(err));
+
+        Camera::new(layout)
+            .capture(rgb_image)
+            .unwrap_or_else(|err| exit_with_error(err))
+    };
+
+    println!("Using Bayer Pattern: {}", raw_image.layout());
+    println!("Using demosaicing algorithm: {}", algorithm);
+
+    raw_image
+        .demosaic(algorithm)
+        .and_then(|demosaiced| {
+            demosaiced
+                .oriented(raw_image.orientation())
+                .save_as_png(output_path)
+        })
+        .unwrap_or_else(|err| exit_with_error(err));
+
+    println!("PNG file successfully saved as \"{}\".", output_path);
+}
+
+fn compare(matches: &ArgMatches) {
+    let original = RgbImage::from_file(matches.value_of("ORIGINAL_FILE").unwrap())
+        .unwrap_or_else(|err| exit_with_error(err));
+    let reconstructed = RgbImage::from_file(matches.value_of("RECONSTRUCTED_FILE").unwrap())
+        .unwrap_or_else(|err| exit_with_error(err));
+
+    let mut regions = vec![Region::new(0, 0, original.width(), original.height())];
+
+    if let Some(grid) = matches.value_of("GRID") {
+        let (columns, rows) = parse_grid(grid).unwrap_or_else(|err| exit_with_error(err));
+        regions.extend(Region::grid(
+            original.width(),
+            original.height(),
+            columns,
+            rows,
+        ));
+    }
+
+    if let Some(values) = matches.values_of("REGION") {
+        for region in values {
+            regions.push(parse_region(region).unwrap_or_else(|err| exit_with_error(err)));
+        }
+    }
+
+    let metrics: Vec<Metrics> = regions
+        .into_iter()
+        .map(|region| {
+            original
+                .compare(&reconstructed, region)
+                .unwrap_or_else(|err| exit_with_error(err))
+        })
+        .collect();
+
+    if matches.is_present("JSON") {
+        let objects: Vec<String> = metrics.iter().map(Metrics::to_json).collect();
+        println!("[{}]", objects.join(","));
+        return;
+    }
+
+    for metrics in metrics {
+        let region = metrics.region;
+        println!(
+            "Region {}x{} at ({}, {}):",
+            region.width, region.height, region.x, region.y
+        );
+        println!("  PSNR: {:.3} dB", metrics.psnr);
+        println!("  SSIM: {:.5}", metrics.ssim);
+        for (name, channel) in ["Red", "Green", "Blue"].iter().zip(metrics.channels.iter()) {
+            println!(
+                "  {}: PSNR {:.3} dB, SSIM {:.5}",
+                name, channel.psnr, channel.ssim
+            );
+        }
+        println!(
+            "  CIEDE2000: mean {:.4}, max {:.4}",
+            metrics.ciede2000_mean, metrics.ciede2000_max
+        );
+        println!("  Zipper effect: {:.4}%", metrics.zipper * 100.0);
+        println!("  False color: {:.4}%", metrics.false_color * 100.0);
+    }
+}
+
+/// Demosaicer scored by `bench-demosaic`.
+enum Demosaicer {
+    BuiltIn(DemosaicAlgorithm),
+    External { name: String, command: Vec<String> },
+}
+
+impl Demosaicer {
+    fn name(&self) -> String {
+        match self {
+            Demosaicer::BuiltIn(algorithm) => algorithm.to_string(),
+            Demosaicer::External { name, .. } => name.clone(),
+        }
+    }
+
+    fn demosaic(&self, raw_image: &RawImage, id: usize) -> Result<RgbImage, String> {
+        let command = match self {
+            Demosaicer::BuiltIn(algorithm) => {
+                return raw_image.demosaic(*algorithm).map_err(String::from)
+            }
+            Demosaicer::External { command, .. } => command,
+        };
+
+        let directory = env::temp_dir();
+        let prefix = format!("emubayer-bench-{}-{}", process::id(), id);
+        let input_path = directory.join(format!("{}.dng", prefix));
+        let output_path = directory.join(format!("{}.png", prefix));
+
+        raw_image.save_as_dng(&input_path)?;
+
+        let arguments: Vec<String> = command
+            .iter()
+            .map(|argument| {
+                argument
+                    .replace("{input}", &input_path.to_string_lossy())
+                    .replace("{output}", &output_path.to_string_lossy())
+            })
+            .collect();
+
+        let status = process::Command::new(&arguments[0])
+            .args(&arguments[1..])
+            .stdout(process::Stdio::null())
+            .status();
+        let result = match status {
+            Ok(ref status) if status.success() => {
+                RgbImage::from_file(&output_path).map_err(String::from)
+            }
+            Ok(status) => Err(format!("\"{}\" failed with {}.", arguments[0], status)),
+            Err(err) => Err(format!("\"{}\" couldn't be run: {}.", arguments[0], err)),
+        };
+
+        let _ = fs::remove_file(&input_path);
+        let _ = fs::remove_file(&output_path);
+
+        result
+    }
+}
+
+/// Extensions of the images `bench-demosaic` picks from a directory. Their
+/// formats are still told apart by their first bytes.
+const IMAGE_EXTENSIONS: [&str; 11] = [
+    "png", "tif", "tiff", "ppm", "pgm", "pnm", "jpg", "jpeg", "exr", "hdr", "pfm",
+];
+
+fn bench_demosaic(matches: &ArgMatches) {
+    let directory = matches.value_of("INPUT_DIRECTORY").unwrap();
+
+    let mut input_paths: Vec<PathBuf> = fs::read_dir(directory)
+        .unwrap_or_else(|_| exit_with_error("Input directory couldn't be read."))
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension().map_or(false, |extension| {
+                IMAGE_EXTENSIONS
+                    .iter()
+                    .any(|image_extension| extension.eq_ignore_ascii_case(image_extension))
+            })
+        })
+        .collect();
+    input_paths.sort();
+
+    if input_paths.is_empty() {
+        exit_with_error("Input directory has no images.");
+    }
+
+    let layouts: Vec<SensorLayout> = match matches.values_of("PATTERN") {
+        Some(patterns) => patterns
+            .map(|pattern| pattern.parse().unwrap_or_else(|err| exit_with_error(err)))
+            .collect(),
+        None => [
+            BayerPattern::RGGB,
+            BayerPattern::BGGR,
+            BayerPattern::GRBG,
+            BayerPattern::GBRG,
+        ]
+        .iter()
+        .map(|&bayer_pattern| SensorLayout::from(bayer_pattern))
+        .collect(),
+    };
+
+    let mut demosaicers: Vec<Demosaicer> = Vec::new();
+
+    if let Some(algorithms) = matches.values_of("ALGORITHM") {
+        for algorithm in algorithms {
+            let algorithm = algorithm.parse().unwrap_or_else(|err| exit_with_error(err));
+            demosaicers.push(Demosaicer::BuiltIn(algorithm));
+        }
+    }
+
+    if let Some(externals) = matches.values_of("EXTERNAL") {
+        for external in externals {
+            let mut parts = external.splitn(2, '=');
+            let name = parts.next().unwrap().trim().to_string();
+            let command: Vec<String> = parts
+                .next()
+                .unwrap_or("")
+                .split_whitespace()
+                .map(String::from)
+                .collect();
+
+            if name.is_empty() || command.is_empty() {
+                exit_with_error("External demosaicers must be given as NAME=COMMAND.");
+            }
+
+            demosaicers.push(Demosaicer::External { name, command });
+        }
+    }
+
+    if demosaicers.is_empty() {
+        demosaicers = DemosaicAlgorithm::all()
+            .into_iter()
+            .map(Demosaicer::BuiltIn)
+            .collect();
+    }
+
+    let noise = parse_noise(matches);
+    let readout_mode = parse_readout(matches);
+    let mut rows = Vec::new();
+    let mut runs = 0;
+
+    for input_path in &input_paths {
+        let image_name = input_path
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .into_owned();
+        let rgb_image = match RgbImage::from_file(input_path) {
+            Ok(rgb_image) => rgb_image,
+            Err(err) => {
+                eprintln!("Skipping {}: {}", image_name, err);
+                continue;
+            }
+        };
+
+        for layout in &layouts {
+            let mut camera = Camera::new(layout.clone());
+            if let Some(noise) = noise {
+                camera = camera.with_noise(noise);
+            }
+            if let Some(readout_mode) = readout_mode {
+                camera = camera.with_readout_mode(readout_mode);
+            }
+
+            let captured = camera
+                .ground_truth(rgb_image.clone())
+                .and_then(|ground_truth| Ok((ground_truth, camera.capture(rgb_image.clone())?)));
+            let (ground_truth, raw_image) = match captured {
+                Ok(captured) => captured,
+                Err(err) => {
+                    eprintln!("Skipping {} on {}: {}", layout, image_name, err);
+                    continue;
+                }
+            };
+            let region = Region::new(0, 0, ground_truth.width(), ground_truth.height());
+
+            for demosaicer in &demosaicers {
+                let metrics = demosaicer
+                    .demosaic(&raw_image, runs)
+                    .and_then(|demosaiced| {
+                        ground_truth
+                            .compare(&demosaiced, region)
+                            .map_err(String::from)
+                    });
+
+                runs += 1;
+
+                match metrics {
+                    Ok(metrics) => rows.push((
+                        image_name.clone(),
+                        layout.to_string(),
+                        demosaicer.name(),
+                        metrics,
+                    )),
+                    Err(err) => eprintln!(
+                        "Skipping {} with {} on {}: {}",
+                        demosaicer.name(),
+                        layout,
+                        image_name,
+                        err
+                    ),
+                }
+            }
+        }
+    }
+
+    let table = if matches.value_of("FORMAT") == Some("json") {
+        let objects: Vec<String> = rows
+            .iter()
+            .map(|(image, pattern, demosaicer, metrics)| {
+                format!(
+                    "{{\"image\":{},\"pattern\":{},\"demosaicer\":{},\"metrics\":{}}}",
+                    json_string(image),
+                    json_string(pattern),
+                    json_string(demosaicer),
+                    metrics.to_json()
+                )
+            })
+            .collect();
+        format!("[{}]\n", objects.join(","))
+    } else {
+        let mut table = String::from(
+            "image,pattern,demosaicer,psnr,ssim,ciede2000_mean,ciede2000_max,zipper,false_color\n",
+        );
+        for (image, pattern, demosaicer, metrics) in &rows {
+            table += &format!(
+                "{},{},{},{},{},{},{},{},{}\n",
+                csv_field(image),
+                csv_field(pattern),
+                csv_field(demosaicer),
+                metrics.psnr,
+                metrics.ssim,
+                metrics.ciede2000_mean,
+                metrics.ciede2000_max,
+                metrics.zipper,
+                metrics.false_color
+            );
+        }
+        table
+    };
+
+    match matches.value_of("OUTPUT_FILE") {
+        Some(path) => fs::write(path, table)
+            .unwrap_or_else(|_| exit_with_error("Results couldn't be written.")),
+        None => print!("{}", table),
+    }
+}
+
+fn json_string(value: &str) -> String {
+    let mut escaped = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => escaped += "\\\"",
+            '\\' => escaped += "\\\\",
+            c if (c as u32) < 0x20 => escaped += &format!("\\u{:04x}", c as u32),
+            c => escaped.push(c),
+        }
+    }
+    escaped + "\""
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains(&[',', '"', '\n'][..]) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn noise_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("SHOT_NOISE")
+            .help("Adds photon shot noise, for photosites holding the given number of electrons at the white level")
+            .long("shot-noise")
+            .takes_value(true)
+            .value_name("FULL_WELL"),
+        Arg::with_name("READ_NOISE")
+            .help("Adds Gaussian read noise with the given standard deviation, in 16-bit digital numbers")
+            .long("read-noise")
+            .takes_value(true)
+            .value_name("SIGMA"),
+        Arg::with_name("SEED")
+            .help("Sets the seed of the noise generator")
+            .long("seed")
+            .takes_value(true)
+            .value_name("SEED"),
+    ]
+}
+
+fn parse_noise(matches: &ArgMatches) -> Option<Noise> {
+    if !matches.is_present("SHOT_NOISE") && !matches.is_present("READ_NOISE") {
+        return None;
+    }
+
+    let mut noise = Noise::new();
+
+    if matches.is_present("SHOT_NOISE") {
+        let full_well = value_t!(matches, "SHOT_NOISE", f32).unwrap_or_else(|e| e.exit());
+        if !(full_well > 0.0 && full_well.is_finite()) {
+            exit_with_error("Full well capacity must be a positive number.");
+        }
+        noise = noise.with_shot_noise(full_well);
+    }
+
+    if matches.is_present("READ_NOISE") {
+        let sigma = value_t!(matches, "READ_NOISE", f32).unwrap_or_else(|e| e.exit());
+        if !(sigma >= 0.0 && sigma.is_finite()) {
+            exit_with_error("Read noise must be a non-negative number.");
+        }
+        noise = noise.with_read_noise(sigma);
+    }
+
+    if matches.is_present("SEED") {
+        noise = noise.with_seed(value_t!(matches, "SEED", u64).unwrap_or_else(|e| e.exit()));
+    }
+
+    Some(noise)
+}
+
+fn readout_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("READOUT")
+        .help("Reads out a smaller frame, as video modes do: bin-NxN-sum, bin-NxN-average or skip-NxN")
+        .long_help("Reads out a smaller frame, as video modes do. bin-NxN-sum and bin-NxN-average combine N x N photosites of the same color from neighboring tiles of the CFA pattern, and skip-NxN reads one of every N columns and rows of tiles. Frames keep the CFA pattern, and the ground truth is downsampled to match them.")
+        .long("readout")
+        .takes_value(true)
+        .value_name("MODE")
+}
+
+fn parse_readout(matches: &ArgMatches) -> Option<ReadoutMode> {
+    matches.value_of("READOUT").map(|readout_mode| {
+        readout_mode
+            .parse()
+            .unwrap_or_else(|err| exit_with_error(err))
+    })
+}
+
+/// Parses the size of an optical filter, which must be a positive number.
+fn parse_filter_size(matches: &ArgMatches, name: &str) -> f32 {
+    let size = value_t!(matches, name, f32).unwrap_or_else(|e| e.exit());
+    if !(size.is_finite() && size > 0.0) {
+        exit_with_error("Optical filter sizes must be positive numbers.");
+    }
+    size
+}
+
+fn parse_sample_format(matches: &ArgMatches) -> SampleFormat {
+    matches
+        .value_of("SAMPLE_FORMAT")
+        .map_or(SampleFormat::Integer, |format| {
+            format
+                .parse::<SampleFormat>()
+                .unwrap_or_else(|err| exit_with_error(err))
+        })
+}
+
+fn parse_compression(matches: &ArgMatches) -> Compression {
+    let predictor = matches
+        .value_of("PREDICTOR")
+        .map_or(Predictor::Horizontal, |predictor| {
+            predictor
+                .parse::<Predictor>()
+                .unwrap_or_else(|err| exit_with_error(err))
+        });
+
+    match matches.value_of("COMPRESSION") {
+        None | Some("none") => Compression::Uncompressed,
+        Some("deflate") => Compression::Deflate(predictor),
+        Some(_) => exit_with_error("Unknown compression. Use none or deflate."),
+    }
+}
+
+fn parse_endianness(matches: &ArgMatches) -> Endianness {
+    matches
+        .value_of("BYTE_ORDER")
+        .map_or(Endianness::Little, |endianness| {
+            endianness
+                .parse::<Endianness>()
+                .unwrap_or_else(|err| exit_with_error(err))
+        })
+}
+
+/// Parses a positive number, which may be given as a fraction such as 1/50.
+fn parse_seconds(value: &str) -> f64 {
+    let mut parts = value.splitn(2, '/');
+    let numerator = parts.next().unwrap().trim().parse::<f64>();
+    let denominator = parts
+        .next()
+        .map_or(Ok(1.0), |part| part.trim().parse::<f64>());
+
+    let seconds = match (numerator, denominator) {
+        (Ok(numerator), Ok(denominator)) => numerator / denominator,
+        _ => f64::NAN,
+    };
+
+    if !(seconds > 0.0 && seconds.is_finite()) {
+        exit_with_error("Times and rates must be positive numbers or fractions such as 1/50.");
+    }
+    seconds
+}
+
+fn exit_with_error(err: &str) -> ! {
+    eprintln!("Error: {}", err);
+    std::process::exit(1);
+}
+
+fn parse_weights(weights: &str) -> Result<[f32; 3], &'static str> {
+    let weights = weights
+        .split(',')
+        .map(|weight| weight.trim().parse::<f32>())
+        .collect::<Result<Vec<f32>, _>>()
+        .map_err(|_| "Weights must be numbers.")?;
+
+    match weights.as_slice() {
+        &[r, g, b] => Ok([r, g, b]),
+        _ => Err("Exactly three weights must be given, for red, green and blue."),
+    }
+}
+
+fn read_spectral_curve(path: &str) -> Result<Vec<(f32, f32)>, &'static str> {
+    let contents = fs::read_to_string(path).map_err(|_| "Spectral curve couldn't be read.")?;
+
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let mut values = line.split(',').map(|value| value.trim().parse::<f32>());
+            match (values.next(), values.next(), values.next()) {
+                (Some(Ok(wavelength)), Some(Ok(transmission)), None) => {
+                    Ok((wavelength, transmission))
+                }
+                _ => Err("Spectral curve lines must be \"wavelength,transmission\" pairs."),
+            }
+        })
+        .collect()
+}
+
+fn parse_grid(grid: &str) -> Result<(u32, u32), &'static str> {
+    let mut values = grid
+        .split(&['x', 'X'][..])
+        .map(|value| value.trim().parse::<u32>());
+
+    match (values.next(), values.next(), values.next()) {
+        (Some(Ok(columns)), Some(Ok(rows)), None) if columns > 0 && rows > 0 => Ok((columns, rows)),
+        _ => Err("Grid must be given as COLUMNSxROWS, such as 4x3."),
+    }
+}
+
+fn parse_region(region: &str) -> Result<Region, &'static str> {
+    let values = region
+        .split(',')
+        .map(|value| value.trim().parse::<u32>())
+        .collect::<Result<Vec<u32>, _>>()
+        .map_err(|_| "Region values must be non-negative integers.")?;
+
+    match values.as_slice() {
+        &[x, y, width, height] => Ok(Region::new(x, y, width, height)),
+        _ => Err("Region must be given as X,Y,WIDTH,HEIGHT."),
+    }
 }
@@ -0,0 +1,57 @@
+// Author: Cláudio Gomes (TofuLynx)
+// Project: emubayer
+// License: GNU GPL Version 3 (https://www.gnu.org/licenses/gpl-3.0.en.html)
+
+use super::{RawImage, SensorLayout};
+
+impl RawImage {
+    /// Sums every cell of same-color photosites into a single photosite, as
+    /// Quad and Nona-Bayer sensors do in their binned mode. Black and white
+    /// levels add up, saturating at 16 bits, and the default crop shrinks
+    /// with the image.
+    ///
+    /// The result of binning a Quad or Nona-Bayer image is an ordinary Bayer
+    /// image. Trailing rows and columns that don't fill a whole cell are dropped.
+    pub fn bin_cells(&self) -> Result<RawImage, &'static str> {
+        let cfa_pattern = self
+            .cfa_pattern()
+            .ok_or("Only images taken through a CFA can be binned.")?;
+
+        let cell = cfa_pattern.cell_size();
+        if cell == 1 {
+            return Err("CFA pattern has no cells of same-color photosites to bin.");
+        }
+
+        let (width, height) = (self.width / cell, self.height / cell);
+        if width == 0 || height == 0 {
+            return Err("Image is smaller than a cell of the CFA pattern.");
+        }
+        let mut data = Vec::with_capacity((width * height) as usize);
+
+        for y in 0..height {
+            for x in 0..width {
+                let mut sum = 0u32;
+
+                for cell_y in 0..cell {
+                    let row = (y * cell + cell_y) * self.width;
+
+                    for cell_x in 0..cell {
+                        sum += u32::from(self.data[(row + x * cell + cell_x) as usize]);
+                    }
+                }
+
+                data.push(sum.min(u32::from(u16::MAX)) as u16);
+            }
+        }
+
+        Ok(RawImage {
+            layout: SensorLayout::Cfa(cfa_pattern.collapsed(cell)),
+            black_level: (u32::from(self.black_level) * cell * cell).min(u32::from(u16::MAX))
+                as u16,
+            white_level: (u32::from(self.white_level) * cell * cell).min(u32::from(u16::MAX))
+                as u16,
+            default_crop: self.default_crop.scaled_down(cell, width, height),
+            ..self.with_data(width, height, data)
+        })
+    }
+}
@@ -0,0 +1,85 @@
+// Author: Cláudio Gomes (TofuLynx)
+// Project: emubayer
+// License: GNU GPL Version 3 (https://www.gnu.org/licenses/gpl-3.0.en.html)
+
+use super::{Camera, LinearImage, RawImage, RgbImage};
+
+/// Exposure time of the 0 EV frame of a bracket, for cameras that don't set
+/// their own.
+const DEFAULT_EXPOSURE_TIME: f64 = 1.0 / 60.0;
+
+impl Camera {
+    /// Takes a "picture" of the scene that keeps `headroom` stops past its
+    /// white. The sensor is exposed that many stops lower, so that brighter
+    /// highlights reach saturation, and the white level is lowered to the
+    /// scene's white. Float DNG files keep these highlights above 1.
+    pub fn capture_scene(
+        &self,
+        scene: &LinearImage,
+        headroom: f64,
+    ) -> Result<RawImage, &'static str> {
+        if headroom < 0.0 || !headroom.is_finite() {
+            return Err("Headroom can't be negative.");
+        }
+
+        let mut raw_image = self.capture_with_noise(scene.exposed(-headroom), self.noise())?;
+
+        let range = f64::from(raw_image.white_level - raw_image.black_level) / 2f64.powf(headroom);
+        if range < 1.0 {
+            return Err("Headroom leaves no levels between black and white.");
+        }
+        raw_image.white_level = raw_image.black_level + range.round() as u16;
+
+        Ok(raw_image)
+    }
+
+    /// Takes one "picture" of the scene per exposure value, each `ev` stops
+    /// brighter than the scene's own exposure. Highlights clip at the white
+    /// level of brighter frames, while darker frames are noisier, and every
+    /// frame draws its own noise.
+    ///
+    /// Frames record their exposure value as ExposureBiasValue, and an
+    /// ExposureTime scaled from the camera's, or from 1/60 s.
+    pub fn capture_bracket(
+        &self,
+        scene: &LinearImage,
+        exposure_values: &[f64],
+    ) -> Result<Vec<RawImage>, &'static str> {
+        if exposure_values.is_empty() {
+            return Err("A bracket needs at least one exposure value.");
+        }
+
+        if exposure_values.iter().any(|ev| !ev.is_finite()) {
+            return Err("Exposure values must be finite numbers.");
+        }
+
+        let base_exposure_time = self.exposure_time().unwrap_or(DEFAULT_EXPOSURE_TIME);
+        if !(base_exposure_time > 0.0 && base_exposure_time.is_finite()) {
+            return Err("Exposure time of a bracket must be positive.");
+        }
+
+        exposure_values
+            .iter()
+            .enumerate()
+            .map(|(index, &ev)| {
+                let noise = self
+                    .noise()
+                    .map(|noise| noise.with_seed(noise.seed().wrapping_add(index as u64)));
+
+                Ok(self
+                    .capture_with_noise(scene.exposed(ev), noise.as_ref())?
+                    .with_exposure_time(base_exposure_time * 2f64.powf(ev))
+                    .with_exposure_bias(ev))
+            })
+            .collect()
+    }
+
+    /// Returns the scene that reaches the sensor at 0 EV, aligned with the
+    /// default crop of every frame of a bracket. Highlights past the scene's
+    /// white clip, as they do in `ground_truth`.
+    ///
+    /// Merged brackets should be compared against this image.
+    pub fn bracket_ground_truth(&self, scene: &LinearImage) -> Result<RgbImage, &'static str> {
+        self.ground_truth(scene.exposed(0.0))
+    }
+}
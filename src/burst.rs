@@ -0,0 +1,592 @@
+// Author: Cláudio Gomes (TofuLynx)
+// Project: emubayer
+// License: GNU GPL Version 3 (https://www.gnu.org/licenses/gpl-3.0.en.html)
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use byteorder::{BigEndian, ByteOrder};
+
+use super::{
+    BitDepth, Camera, ColorType, Compression, Endianness, RawImage, RgbImage, SampleFormat,
+    PNG_SIGNATURE,
+};
+use orientation;
+use tiff;
+
+const TAG_TIME_CODES: u16 = 0xC763;
+const TAG_FRAME_RATE: u16 = 0xC764;
+
+/// Frame rate of image sequences, which don't carry one.
+const DEFAULT_FRAME_RATE: f64 = 24.0;
+
+/// Most frames a camera records from a burst, over an hour at 24 frames
+/// per second.
+const MAX_RECORDED_FRAMES: f64 = 100_000.0;
+
+/// Sequence of frames of the same size, as seen by a camera shooting a
+/// burst or a video.
+#[derive(Clone)]
+pub struct Burst {
+    frames: Vec<RgbImage>,
+    frame_rate: f64,
+}
+
+impl Burst {
+    pub fn new(frames: Vec<RgbImage>, frame_rate: f64) -> Result<Burst, &'static str> {
+        let first = frames.first().ok_or("A burst needs at least one frame.")?;
+        if frames
+            .iter()
+            .any(|frame| (frame.width, frame.height) != (first.width, first.height))
+        {
+            return Err("Every frame of a burst must have the same size.");
+        }
+
+        if !(frame_rate > 0.0 && frame_rate.is_finite()) {
+            return Err("Frame rate must be positive.");
+        }
+
+        Ok(Burst { frames, frame_rate })
+    }
+
+    /// Reads every frame of an APNG file, at the frame rate its delays add
+    /// up to. Still images of any format are read as the first file of a
+    /// numbered sequence instead, such as `frame_0001.png`,
+    /// `frame_0002.png` and so on, at 24 frames per second.
+    ///
+    /// Frames are rotated and flipped upright if the file has an EXIF orientation.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Burst, &'static str> {
+        let png_file = fs::read(&path).map_err(|_| "Image couldn't be opened.")?;
+        if !png_file.starts_with(PNG_SIGNATURE) {
+            return Burst::from_sequence(path);
+        }
+
+        match apng_frames(&png_file)? {
+            Some((frames, frame_rate)) => {
+                let orientation = orientation::png_exif_orientation(&png_file).unwrap_or_default();
+                let frames = frames
+                    .into_iter()
+                    .map(|frame| frame.oriented(orientation))
+                    .collect();
+
+                Burst::new(frames, frame_rate)
+            }
+            None => Burst::from_sequence(path),
+        }
+    }
+
+    /// Reads the numbered sequence of images that starts at the given file,
+    /// up to the first missing number, at 24 frames per second. Every file
+    /// has the first one's extension.
+    pub fn from_sequence<P: AsRef<Path>>(first_path: P) -> Result<Burst, &'static str> {
+        let first_path = first_path.as_ref();
+        let stem = first_path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .ok_or("Image sequence file name is invalid.")?;
+
+        let prefix = stem.trim_end_matches(|c: char| c.is_ascii_digit());
+        let digits = &stem[prefix.len()..];
+        let extension = first_path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map_or(String::new(), |extension| format!(".{}", extension));
+        let first_number: u64 = digits
+            .parse()
+            .map_err(|_| "Image sequence file names must end with a frame number.")?;
+
+        let mut frames = Vec::new();
+        for number in first_number.. {
+            let path = first_path.with_file_name(format!(
+                "{}{:0width$}{}",
+                prefix,
+                number,
+                extension,
+                width = digits.len()
+            ));
+
+            if !path.is_file() {
+                break;
+            }
+
+            frames.push(RgbImage::from_file(path)?);
+        }
+
+        Burst::new(frames, DEFAULT_FRAME_RATE)
+    }
+
+    /// Plays the same frames at another frame rate.
+    pub fn with_frame_rate(self, frame_rate: f64) -> Result<Burst, &'static str> {
+        Burst::new(self.frames, frame_rate)
+    }
+
+    pub fn frames(&self) -> &[RgbImage] {
+        &self.frames
+    }
+
+    pub fn frame_rate(&self) -> f64 {
+        self.frame_rate
+    }
+}
+
+/// Raw frames captured from a burst.
+#[derive(Clone, Debug)]
+pub struct RawBurst {
+    frames: Vec<RawImage>,
+    frame_rate: f64,
+}
+
+impl RawBurst {
+    pub fn frames(&self) -> &[RawImage] {
+        &self.frames
+    }
+
+    pub fn frame_rate(&self) -> f64 {
+        self.frame_rate
+    }
+
+    /// Stores the samples of every frame in the given format.
+    pub fn with_sample_format(mut self, sample_format: SampleFormat) -> Self {
+        self.frames = self
+            .frames
+            .into_iter()
+            .map(|frame| frame.with_sample_format(sample_format))
+            .collect();
+        self
+    }
+
+    /// Compresses the raw data of every frame with the given method.
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.frames = self
+            .frames
+            .into_iter()
+            .map(|frame| frame.with_compression(compression))
+            .collect();
+        self
+    }
+
+    /// Saves the DNG files of every frame in the given byte order.
+    pub fn with_endianness(mut self, endianness: Endianness) -> Self {
+        self.frames = self
+            .frames
+            .into_iter()
+            .map(|frame| frame.with_endianness(endianness))
+            .collect();
+        self
+    }
+
+    /// Saves the DNG files of every frame as BigTIFF.
+    pub fn with_big_tiff(mut self) -> Self {
+        self.frames = self
+            .frames
+            .into_iter()
+            .map(RawImage::with_big_tiff)
+            .collect();
+        self
+    }
+
+    /// Saves every frame as a DNG file in a CinemaDNG-style folder, named
+    /// after the folder and numbered from 0, such as `clip/clip_000000.dng`.
+    /// Every file records the frame rate and the SMPTE time code of its frame.
+    pub fn save_as_cinema_dng<P: AsRef<Path>>(
+        &self,
+        directory: P,
+    ) -> Result<Vec<PathBuf>, &'static str> {
+        if !(self.frame_rate > 0.0 && self.frame_rate.is_finite()) {
+            return Err("Frame rate must be positive.");
+        }
+
+        let directory = directory.as_ref();
+        let name = directory
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or("CinemaDNG folder name is invalid.")?;
+        fs::create_dir_all(directory).map_err(|_| "CinemaDNG folder couldn't be created.")?;
+
+        let (numerator, denominator) = tiff::rational(self.frame_rate);
+        let mut paths = Vec::with_capacity(self.frames.len());
+
+        for (index, frame) in self.frames.iter().enumerate() {
+            let path = directory.join(format!("{}_{:06}.dng", name, index));
+            let ifd = frame
+                .dng_ifd()
+                .with_entry(
+                    TAG_TIME_CODES,
+                    tiff::Field::Bytes(time_code(index as u64, self.frame_rate).to_vec()),
+                )
+                .with_entry(
+                    TAG_FRAME_RATE,
+                    tiff::Field::SRationals(vec![(numerator, denominator)]),
+                );
+
+            ifd.write_to(
+                &path,
+                frame.endianness() == Endianness::Big,
+                frame.is_big_tiff(),
+            )?;
+            paths.push(path);
+        }
+
+        Ok(paths)
+    }
+}
+
+impl Camera {
+    /// Records a video of a burst, at the camera's frame rate or else the
+    /// burst's, for as long as the burst lasts. Every frame exposes the
+    /// burst's frames with the camera's exposure time and rolling shutter,
+    /// and draws independent noise, seeded by the noise seed plus the
+    /// frame's index.
+    ///
+    /// Without any of these, every frame of the burst is captured as is.
+    /// Recordings of more than 100000 frames are rejected.
+    pub fn capture_burst(&self, burst: &Burst) -> Result<RawBurst, &'static str> {
+        let frame_rate = self.frame_rate().unwrap_or(burst.frame_rate);
+        if [frame_rate, burst.frame_rate]
+            .iter()
+            .any(|&rate| !(rate > 0.0 && rate.is_finite()))
+        {
+            return Err("Frame rate must be positive.");
+        }
+
+        if self
+            .exposure_time()
+            .map_or(false, |time| time < 0.0 || !time.is_finite())
+        {
+            return Err("Exposure time must be a finite number, and can't be negative.");
+        }
+
+        if self
+            .rolling_shutter()
+            .map_or(false, |time| time < 0.0 || !time.is_finite())
+        {
+            return Err(
+                "Rolling shutter readout time must be a finite number, and can't be negative.",
+            );
+        }
+
+        let duration = burst.frames.len() as f64 / burst.frame_rate;
+        let count = (duration * frame_rate + 1e-9).floor();
+        if count > MAX_RECORDED_FRAMES {
+            return Err("Burst is too long to be recorded at this frame rate.");
+        }
+        let count = (count as usize).max(1);
+
+        let frames = (0..count)
+            .map(|index| {
+                let frame =
+                    self.exposed_frame(&burst.frames, burst.frame_rate, index as f64 / frame_rate);
+                let noise = self
+                    .noise()
+                    .map(|noise| noise.with_seed(noise.seed().wrapping_add(index as u64)));
+                self.capture_with_noise(frame, noise.as_ref())
+            })
+            .collect::<Result<Vec<RawImage>, _>>()?;
+
+        Ok(RawBurst { frames, frame_rate })
+    }
+}
+
+/// Returns the SMPTE time code of a frame, as stored in the CinemaDNG
+/// TimeCodes tag: frames, seconds, minutes and hours in BCD, without user bits.
+pub(crate) fn time_code(frame: u64, frame_rate: f64) -> [u8; 8] {
+    let frames_per_second = (frame_rate.round() as u64).max(1);
+    let seconds = frame / frames_per_second;
+    let bcd = |value: u64| (((value / 10) << 4) | (value % 10)) as u8;
+
+    [
+        bcd(frame % frames_per_second),
+        bcd(seconds % 60),
+        bcd(seconds / 60 % 60),
+        bcd(seconds / 3600 % 24),
+        0,
+        0,
+        0,
+        0,
+    ]
+}
+
+/// Frame of an APNG file, as described by its fcTL chunk.
+struct ApngFrame {
+    width: u32,
+    height: u32,
+    x: u32,
+    y: u32,
+    delay: f64,
+    dispose_op: u8,
+    blend_op: u8,
+    data: Vec<u8>,
+}
+
+const DISPOSE_OP_BACKGROUND: u8 = 1;
+const DISPOSE_OP_PREVIOUS: u8 = 2;
+const BLEND_OP_OVER: u8 = 1;
+
+/// Decodes and composites every frame of an APNG file, along with its frame
+/// rate. Returns `None` for still PNG files.
+fn apng_frames(png_file: &[u8]) -> Result<Option<(Vec<RgbImage>, f64)>, &'static str> {
+    let chunks = png_chunks(png_file)?;
+
+    let header = match chunks.first() {
+        Some(&(b"IHDR", data)) if data.len() == 13 => data,
+        _ => return Err("This PNG file appears to be corrupted."),
+    };
+
+    if !chunks.iter().any(|&(chunk_type, _)| chunk_type == b"acTL") {
+        return Ok(None);
+    }
+
+    let mut frames: Vec<ApngFrame> = Vec::new();
+
+    for &(chunk_type, data) in &chunks {
+        match chunk_type {
+            b"fcTL" => {
+                if data.len() != 26 {
+                    return Err("APNG frame control chunk is invalid.");
+                }
+
+                let (numerator, denominator) = (
+                    BigEndian::read_u16(&data[20..22]),
+                    BigEndian::read_u16(&data[22..24]),
+                );
+                // A denominator of 0 means hundredths of a second.
+                let denominator = if denominator == 0 { 100 } else { denominator };
+
+                frames.push(ApngFrame {
+                    width: BigEndian::read_u32(&data[4..8]),
+                    height: BigEndian::read_u32(&data[8..12]),
+                    x: BigEndian::read_u32(&data[12..16]),
+                    y: BigEndian::read_u32(&data[16..20]),
+                    delay: f64::from(numerator) / f64::from(denominator),
+                    dispose_op: data[24],
+                    blend_op: data[25],
+                    data: Vec::new(),
+                });
+            }
+            // The default image is only the first frame if a fcTL chunk precedes it.
+            b"IDAT" => {
+                if let Some(frame) = frames.last_mut() {
+                    frame.data.extend_from_slice(data);
+                }
+            }
+            b"fdAT" => {
+                let frame = frames
+                    .last_mut()
+                    .ok_or("APNG frame data comes before any frame control chunk.")?;
+                frame.data.extend_from_slice(data.get(4..).unwrap_or(&[]));
+            }
+            _ => {}
+        }
+    }
+
+    if frames.is_empty() || frames.iter().any(|frame| frame.data.is_empty()) {
+        return Err("APNG file has frames without data.");
+    }
+
+    let (canvas_width, canvas_height) = (
+        BigEndian::read_u32(&header[0..4]),
+        BigEndian::read_u32(&header[4..8]),
+    );
+    let mut canvas: Option<RgbImage> = None;
+    let mut images = Vec::with_capacity(frames.len());
+
+    for (index, frame) in frames.iter().enumerate() {
+        if frame.width == 0
+            || frame.height == 0
+            || frame.x + frame.width > canvas_width
+            || frame.y + frame.height > canvas_height
+        {
+            return Err("APNG frame lies outside of the image.");
+        }
+
+        // Every frame is decoded as a PNG file of its own.
+        let mut frame_header = header.to_vec();
+        BigEndian::write_u32(&mut frame_header[0..4], frame.width);
+        BigEndian::write_u32(&mut frame_header[4..8], frame.height);
+
+        let mut frame_file = PNG_SIGNATURE.to_vec();
+        write_chunk(&mut frame_file, b"IHDR", &frame_header);
+        write_chunk(&mut frame_file, b"IDAT", &frame.data);
+        write_chunk(&mut frame_file, b"IEND", &[]);
+        let image = RgbImage::from_png(&frame_file)?;
+
+        let canvas = canvas.get_or_insert_with(|| RgbImage {
+            width: canvas_width,
+            height: canvas_height,
+            data: vec![0; canvas_width as usize * canvas_height as usize * image.bytes_per_pixel()],
+            color_type: image.color_type,
+            bit_depth: image.bit_depth,
+        });
+
+        let previous = canvas.clone();
+        canvas.blend(&image, frame.x, frame.y, frame.blend_op == BLEND_OP_OVER);
+        images.push(canvas.clone());
+
+        // The first frame has nothing to go back to.
+        match frame.dispose_op {
+            DISPOSE_OP_PREVIOUS if index > 0 => *canvas = previous,
+            DISPOSE_OP_BACKGROUND | DISPOSE_OP_PREVIOUS => {
+                canvas.clear(frame.x, frame.y, frame.width, frame.height)
+            }
+            _ => {}
+        }
+    }
+
+    let duration: f64 = frames.iter().map(|frame| frame.delay).sum();
+    let frame_rate = if duration > 0.0 {
+        frames.len() as f64 / duration
+    } else {
+        DEFAULT_FRAME_RATE
+    };
+
+    Ok(Some((images, frame_rate)))
+}
+
+/// Type and data of a PNG chunk.
+pub(crate) type Chunk<'a> = (&'a [u8], &'a [u8]);
+
+/// Splits a PNG file into the types and data of its chunks, checking their CRCs.
+pub(crate) fn png_chunks(png_file: &[u8]) -> Result<Vec<Chunk<'_>>, &'static str> {
+    if !png_file.starts_with(PNG_SIGNATURE) {
+        return Err("This file isn't a PNG file.");
+    }
+
+    let mut chunks = Vec::new();
+    let mut position = PNG_SIGNATURE.len();
+
+    while position < png_file.len() {
+        let length = png_file
+            .get(position..position + 4)
+            .map(BigEndian::read_u32)
+            .ok_or("This PNG file appears to be corrupted.")? as usize;
+        let end = position + 12 + length;
+        if end > png_file.len() {
+            return Err("This PNG file appears to be corrupted.");
+        }
+
+        let chunk_type = &png_file[position + 4..position + 8];
+        let data = &png_file[position + 8..end - 4];
+        if crc32(&png_file[position + 4..end - 4]) != BigEndian::read_u32(&png_file[end - 4..end]) {
+            return Err("This PNG file appears to be corrupted.");
+        }
+
+        chunks.push((chunk_type, data));
+        position = end;
+
+        if chunk_type == b"IEND" {
+            break;
+        }
+    }
+
+    Ok(chunks)
+}
+
+fn write_chunk(png_file: &mut Vec<u8>, chunk_type: &[u8], data: &[u8]) {
+    let mut length = [0; 4];
+    BigEndian::write_u32(&mut length, data.len() as u32);
+    png_file.extend_from_slice(&length);
+
+    let start = png_file.len();
+    png_file.extend_from_slice(chunk_type);
+    png_file.extend_from_slice(data);
+
+    let mut crc = [0; 4];
+    BigEndian::write_u32(&mut crc, crc32(&png_file[start..]));
+    png_file.extend_from_slice(&crc);
+}
+
+/// CRC-32 of PNG chunks, as defined by ISO 3309.
+pub(crate) fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+
+    for &byte in bytes {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+
+    !crc
+}
+
+impl RgbImage {
+    fn bytes_per_pixel(&self) -> usize {
+        self.channels() * self.bit_depth.to_u32() as usize / 8
+    }
+
+    /// Draws an image of the same color type at the given position, either
+    /// replacing the pixels below it or compositing over them with its alpha.
+    fn blend(&mut self, image: &RgbImage, x: u32, y: u32, over: bool) {
+        let bytes_per_pixel = self.bytes_per_pixel();
+        let channels = self.channels();
+        let sixteen_bit = matches!(self.bit_depth, BitDepth::Sixteen);
+        let over = over && matches!(self.color_type, ColorType::RGBA);
+
+        let read = |data: &[u8], index: usize| {
+            if sixteen_bit {
+                f64::from(BigEndian::read_u16(&data[index * 2..]))
+            } else {
+                f64::from(data[index])
+            }
+        };
+        let max = if sixteen_bit { 65535.0 } else { 255.0 };
+
+        for row in 0..image.height {
+            for column in 0..image.width {
+                let source = (row * image.width + column) as usize;
+                let destination = ((y + row) * self.width + x + column) as usize;
+
+                if !over {
+                    self.data[destination * bytes_per_pixel..(destination + 1) * bytes_per_pixel]
+                        .copy_from_slice(
+                            &image.data[source * bytes_per_pixel..(source + 1) * bytes_per_pixel],
+                        );
+                    continue;
+                }
+
+                let source_alpha = read(&image.data, source * channels + 3) / max;
+                let destination_alpha = read(&self.data, destination * channels + 3) / max;
+                let alpha = source_alpha + destination_alpha * (1.0 - source_alpha);
+
+                for channel in 0..channels {
+                    let value = if channel == 3 {
+                        alpha * max
+                    } else if alpha > 0.0 {
+                        (read(&image.data, source * channels + channel) * source_alpha
+                            + read(&self.data, destination * channels + channel)
+                                * destination_alpha
+                                * (1.0 - source_alpha))
+                            / alpha
+                    } else {
+                        0.0
+                    };
+
+                    let index = destination * channels + channel;
+                    if sixteen_bit {
+                        BigEndian::write_u16(&mut self.data[index * 2..], value.round() as u16);
+                    } else {
+                        self.data[index] = value.round() as u8;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Makes a region fully transparent black.
+    fn clear(&mut self, x: u32, y: u32, width: u32, height: u32) {
+        let bytes_per_pixel = self.bytes_per_pixel();
+
+        for row in y..y + height {
+            let start = (row * self.width + x) as usize * bytes_per_pixel;
+            for byte in &mut self.data[start..start + width as usize * bytes_per_pixel] {
+                *byte = 0;
+            }
+        }
+    }
+}
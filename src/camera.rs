@@ -0,0 +1,458 @@
+// Author: Cláudio Gomes (TofuLynx)
+// Project: emubayer
+// License: GNU GPL Version 3 (https://www.gnu.org/licenses/gpl-3.0.en.html)
+
+use super::{RawImage, Region, RgbImage, SensorLayout};
+use cfa::CfaColor;
+use cfa::FilterResponses;
+use metrics::json_number;
+use noise::Noise;
+use odd_size::OddSizePolicy;
+use optics::OpticalFilter;
+use orientation::Orientation;
+use readout::ReadoutMode;
+use sampling::Aperture;
+
+/// Emulated camera model, describing every stage between the scene and the
+/// raw data.
+pub struct Camera {
+    layout: SensorLayout,
+    filter_responses: FilterResponses,
+    optical_filters: Vec<OpticalFilter>,
+    supersampling: Option<(u32, Aperture)>,
+    cell_binning: bool,
+    noise: Option<Noise>,
+    odd_size_policy: OddSizePolicy,
+    readout_window: Option<Region>,
+    orientation: Orientation,
+    baked_orientation: bool,
+    readout_mode: Option<ReadoutMode>,
+    frame_rate: Option<f64>,
+    exposure_time: Option<f64>,
+    rolling_shutter: Option<f64>,
+}
+
+impl Camera {
+    /// Creates a camera with the given sensor layout, or with the given CFA pattern.
+    pub fn new<L: Into<SensorLayout>>(layout: L) -> Camera {
+        Camera {
+            layout: layout.into(),
+            filter_responses: FilterResponses::default(),
+            optical_filters: Vec::new(),
+            supersampling: None,
+            cell_binning: false,
+            noise: None,
+            odd_size_policy: OddSizePolicy::default(),
+            readout_window: None,
+            orientation: Orientation::Normal,
+            baked_orientation: false,
+            readout_mode: None,
+            frame_rate: None,
+            exposure_time: None,
+            rolling_shutter: None,
+        }
+    }
+
+    /// Sets how filter colors respond to the input. Monochrome sensors use
+    /// the response of White filters.
+    pub fn with_filter_responses(mut self, filter_responses: FilterResponses) -> Self {
+        self.filter_responses = filter_responses;
+        self
+    }
+
+    /// Adds an optical filter in front of the sensor. Filters are applied in
+    /// the order they are added.
+    pub fn with_optical_filter(mut self, filter: OpticalFilter) -> Self {
+        self.optical_filters.push(filter);
+        self
+    }
+
+    /// Makes every photosite integrate a `factor` x `factor` block of input
+    /// pixels through the given aperture, instead of sampling a single one.
+    pub fn with_supersampling(mut self, factor: u32, aperture: Aperture) -> Self {
+        self.supersampling = Some((factor, aperture));
+        self
+    }
+
+    /// Makes the sensor sum every cell of same-color photosites of a Quad or
+    /// Nona-Bayer pattern, producing an ordinary Bayer image.
+    pub fn with_cell_binning(mut self) -> Self {
+        self.cell_binning = true;
+        self
+    }
+
+    /// Adds sensor noise to every photosite, before any binning.
+    pub fn with_noise(mut self, noise: Noise) -> Self {
+        self.noise = Some(noise);
+        self
+    }
+
+    /// Reads out a smaller frame with the given mode, after any noise and
+    /// cell binning, as sensors do in video modes.
+    pub fn with_readout_mode(mut self, readout_mode: ReadoutMode) -> Self {
+        self.readout_mode = Some(readout_mode);
+        self
+    }
+
+    /// Records bursts at the given frame rate, instead of the burst's own.
+    pub fn with_frame_rate(mut self, frame_rate: f64) -> Self {
+        self.frame_rate = Some(frame_rate);
+        self
+    }
+
+    /// Exposes every frame of a burst for the given time, in seconds,
+    /// blurring motion across the burst's frames.
+    pub fn with_exposure_time(mut self, exposure_time: f64) -> Self {
+        self.exposure_time = Some(exposure_time);
+        self
+    }
+
+    /// Reads the rows of the sensor out one after the other, the last one
+    /// `readout_time` seconds after the first, skewing motion across the
+    /// burst's frames.
+    pub fn with_rolling_shutter(mut self, readout_time: f64) -> Self {
+        self.rolling_shutter = Some(readout_time);
+        self
+    }
+
+    /// Sets how CFA captures fit images with an odd width or height.
+    /// Images are cropped by default.
+    pub fn with_odd_size_policy(mut self, policy: OddSizePolicy) -> Self {
+        self.odd_size_policy = policy;
+        self
+    }
+
+    /// Reads out only the photosites inside the given window, in sensor
+    /// coordinates after any supersampling. The CFA pattern of the raw data
+    /// follows the window's origin.
+    pub fn with_readout_window(mut self, window: Region) -> Self {
+        self.readout_window = Some(window);
+        self
+    }
+
+    /// Holds the camera so that the input is displayed upright from raw data
+    /// stored with the given orientation, such as `Rotate90` for a camera
+    /// turned to portrait. The raw data keeps the sensor's layout and
+    /// records the orientation in the DNG Orientation tag.
+    pub fn with_orientation(mut self, orientation: Orientation) -> Self {
+        self.orientation = orientation;
+        self
+    }
+
+    /// Rotates and flips the mosaic itself into the orientation, adjusting
+    /// its CFA pattern, instead of recording it in the Orientation tag.
+    pub fn with_baked_orientation(mut self) -> Self {
+        self.baked_orientation = true;
+        self
+    }
+
+    pub fn layout(&self) -> &SensorLayout {
+        &self.layout
+    }
+
+    pub fn filter_responses(&self) -> &FilterResponses {
+        &self.filter_responses
+    }
+
+    pub fn optical_filters(&self) -> &[OpticalFilter] {
+        &self.optical_filters
+    }
+
+    pub fn supersampling(&self) -> Option<(u32, Aperture)> {
+        self.supersampling
+    }
+
+    pub fn cell_binning(&self) -> bool {
+        self.cell_binning
+    }
+
+    pub fn noise(&self) -> Option<&Noise> {
+        self.noise.as_ref()
+    }
+
+    pub fn readout_mode(&self) -> Option<ReadoutMode> {
+        self.readout_mode
+    }
+
+    pub fn frame_rate(&self) -> Option<f64> {
+        self.frame_rate
+    }
+
+    pub fn exposure_time(&self) -> Option<f64> {
+        self.exposure_time
+    }
+
+    pub fn rolling_shutter(&self) -> Option<f64> {
+        self.rolling_shutter
+    }
+
+    pub fn odd_size_policy(&self) -> OddSizePolicy {
+        self.odd_size_policy
+    }
+
+    pub fn readout_window(&self) -> Option<Region> {
+        self.readout_window
+    }
+
+    pub fn orientation(&self) -> Orientation {
+        self.orientation
+    }
+
+    pub fn baked_orientation(&self) -> bool {
+        self.baked_orientation
+    }
+
+    /// Describes every parameter of the camera model as a JSON object.
+    pub fn to_json(&self) -> String {
+        let colors = [
+            CfaColor::Red,
+            CfaColor::Green,
+            CfaColor::Blue,
+            CfaColor::Cyan,
+            CfaColor::Magenta,
+            CfaColor::Yellow,
+            CfaColor::White,
+        ];
+        let filter_responses: Vec<String> = colors
+            .iter()
+            .map(|&color| {
+                let weights = self.filter_responses.response(color);
+                format!(
+                    "\"{}\":[{},{},{}]",
+                    color.letter(),
+                    json_number(weights[0]),
+                    json_number(weights[1]),
+                    json_number(weights[2])
+                )
+            })
+            .collect();
+
+        let optical_filters: Vec<String> = self
+            .optical_filters
+            .iter()
+            .map(|filter| match filter {
+                OpticalFilter::Olpf { separation } => {
+                    format!(
+                        "{{\"type\":\"olpf\",\"separation\":{}}}",
+                        json_number(*separation)
+                    )
+                }
+                OpticalFilter::Gaussian { sigma } => {
+                    format!(
+                        "{{\"type\":\"gaussian\",\"sigma\":{}}}",
+                        json_number(*sigma)
+                    )
+                }
+                OpticalFilter::Airy { radius } => {
+                    format!("{{\"type\":\"airy\",\"radius\":{}}}", json_number(*radius))
+                }
+                OpticalFilter::Defocus { radius } => {
+                    format!(
+                        "{{\"type\":\"defocus\",\"radius\":{}}}",
+                        json_number(*radius)
+                    )
+                }
+            })
+            .collect();
+
+        let supersampling = match self.supersampling {
+            Some((factor, Aperture::Box)) => {
+                format!("{{\"factor\":{},\"fill_factor\":1}}", factor)
+            }
+            Some((factor, Aperture::FillFactor(fill_factor))) => {
+                format!(
+                    "{{\"factor\":{},\"fill_factor\":{}}}",
+                    factor,
+                    json_number(fill_factor)
+                )
+            }
+            None => "null".to_string(),
+        };
+
+        let noise = match self.noise {
+            Some(ref noise) => format!(
+                "{{\"full_well\":{},\"read_noise\":{},\"seed\":{}}}",
+                noise.full_well().map_or("null".to_string(), json_number),
+                json_number(noise.read_noise()),
+                noise.seed()
+            ),
+            None => "null".to_string(),
+        };
+
+        let readout_mode = self
+            .readout_mode
+            .map_or("null".to_string(), |mode| format!("\"{}\"", mode));
+
+        let readout_window = match self.readout_window {
+            Some(window) => format!(
+                "{{\"x\":{},\"y\":{},\"width\":{},\"height\":{}}}",
+                window.x, window.y, window.width, window.height
+            ),
+            None => "null".to_string(),
+        };
+
+        format!(
+            "{{\"layout\":\"{}\",\"filter_responses\":{{{}}},\"optical_filters\":[{}],\"supersampling\":{},\"cell_binning\":{},\"noise\":{},\"readout_mode\":{},\"frame_rate\":{},\"exposure_time\":{},\"rolling_shutter\":{},\"odd_size_policy\":\"{}\",\"readout_window\":{},\"orientation\":\"{}\",\"baked_orientation\":{}}}",
+            self.layout,
+            filter_responses.join(","),
+            optical_filters.join(","),
+            supersampling,
+            self.cell_binning,
+            noise,
+            readout_mode,
+            optional(self.frame_rate),
+            optional(self.exposure_time),
+            optional(self.rolling_shutter),
+            self.odd_size_policy,
+            readout_window,
+            self.orientation,
+            self.baked_orientation
+        )
+    }
+
+    /// Returns the image that reaches the sensor, aligned with the default
+    /// crop of the raw data.
+    ///
+    /// Demosaiced captures should be compared against this image.
+    pub fn ground_truth(&self, rgb_image: RgbImage) -> Result<RgbImage, &'static str> {
+        let rgb_image = self.sensor_image(rgb_image)?;
+
+        let rgb_image = match self.layout {
+            SensorLayout::Cfa(ref cfa_pattern) => {
+                let (rgb_image, visible_area) = rgb_image.to_even_size(self.odd_size_policy)?;
+
+                if self.cell_binning {
+                    rgb_image.downsample(cfa_pattern.cell_size(), Aperture::Box)?
+                } else {
+                    rgb_image.cropped(visible_area)
+                }
+            }
+            _ => rgb_image,
+        };
+
+        // Reduced readouts see the image at a lower resolution, cropped like
+        // their frames to whole groups of tiles.
+        let rgb_image = match self.readout_mode {
+            Some(mode) => {
+                let (columns, rows) = match self.layout {
+                    SensorLayout::Cfa(ref cfa_pattern) if self.cell_binning => {
+                        SensorLayout::Cfa(cfa_pattern.collapsed(cfa_pattern.cell_size()))
+                            .tile_size()
+                    }
+                    ref layout => layout.tile_size(),
+                };
+                let factor = mode.factor();
+                let (width, height) = (
+                    rgb_image.width / (columns * factor) * columns,
+                    rgb_image.height / (rows * factor) * rows,
+                );
+
+                rgb_image
+                    .downsample(factor, Aperture::Box)?
+                    .crop(Region::new(0, 0, width, height))?
+            }
+            None => rgb_image,
+        };
+
+        if self.baked_orientation {
+            Ok(rgb_image.oriented(self.orientation))
+        } else {
+            Ok(rgb_image)
+        }
+    }
+
+    /// Takes a "picture" of the given image.
+    pub fn capture(&self, rgb_image: RgbImage) -> Result<RawImage, &'static str> {
+        self.capture_with_noise(rgb_image, self.noise.as_ref())
+    }
+
+    /// Same as `capture`, with the given noise instead of the camera's.
+    pub(crate) fn capture_with_noise(
+        &self,
+        rgb_image: RgbImage,
+        noise: Option<&Noise>,
+    ) -> Result<RawImage, &'static str> {
+        let rgb_image = self.sensor_image(rgb_image)?;
+
+        let raw_image = match self.layout {
+            SensorLayout::Cfa(ref cfa_pattern) => {
+                let (rgb_image, visible_area) = rgb_image.to_even_size(self.odd_size_policy)?;
+                let cfa_pattern = match self.readout_window {
+                    Some(window) => cfa_pattern.shifted(window.x, window.y),
+                    None => cfa_pattern.clone(),
+                };
+
+                let mut raw_image =
+                    rgb_image.to_raw_with_responses(cfa_pattern, &self.filter_responses);
+                raw_image.default_crop = visible_area;
+                raw_image
+            }
+            SensorLayout::Monochrome => {
+                rgb_image.to_monochrome(self.filter_responses.response(CfaColor::White))
+            }
+            SensorLayout::LinearRaw => rgb_image.to_linear_raw(),
+        };
+
+        let raw_image = match noise {
+            Some(noise) => raw_image.apply_noise(noise)?,
+            None => raw_image,
+        };
+
+        let raw_image = if self.cell_binning {
+            raw_image.bin_cells()?
+        } else {
+            raw_image
+        };
+
+        let raw_image = match self.readout_mode {
+            Some(mode) => raw_image.read_out(mode)?,
+            None => raw_image,
+        };
+
+        let raw_image = match self.exposure_time {
+            Some(exposure_time) => raw_image.with_exposure_time(exposure_time),
+            None => raw_image,
+        };
+
+        let raw_image = raw_image.with_orientation(self.orientation);
+        if self.baked_orientation {
+            Ok(raw_image.apply_orientation())
+        } else {
+            Ok(raw_image)
+        }
+    }
+
+    /// Returns the image that reaches the sensor, one pixel per photosite
+    /// of the readout window.
+    fn sensor_image(&self, rgb_image: RgbImage) -> Result<RgbImage, &'static str> {
+        // The sensor sees the upright input turned the other way.
+        let mut rgb_image = rgb_image.oriented(self.orientation.inverse());
+        let factor = self.supersampling.map_or(1, |(factor, _)| factor);
+
+        for filter in &self.optical_filters {
+            rgb_image = rgb_image.apply_optical_filter(&filter.scaled(factor as f32))?;
+        }
+
+        if let Some((factor, aperture)) = self.supersampling {
+            rgb_image = rgb_image.downsample(factor, aperture)?;
+        }
+
+        if let Some(window) = self.readout_window {
+            if let SensorLayout::Cfa(ref cfa_pattern) = self.layout {
+                let cell = cfa_pattern.cell_size();
+                if self.cell_binning && (window.x % cell != 0 || window.y % cell != 0) {
+                    return Err("Readout window must start on a cell boundary to bin cells.");
+                }
+            }
+
+            rgb_image = rgb_image.crop(window)?;
+        }
+
+        Ok(rgb_image)
+    }
+}
+
+/// Formats an optional number as JSON.
+fn optional(value: Option<f64>) -> String {
+    value.map_or("null".to_string(), json_number)
+}
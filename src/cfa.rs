@@ -0,0 +1,438 @@
+// Author: Cláudio Gomes (TofuLynx)
+// Project: emubayer
+// License: GNU GPL Version 3 (https://www.gnu.org/licenses/gpl-3.0.en.html)
+
+use std::{fmt, str::FromStr};
+
+use super::BayerPattern;
+
+/// Color of a filter in the color filter array.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CfaColor {
+    Red,
+    Green,
+    Blue,
+    Cyan,
+    Magenta,
+    Yellow,
+    White,
+}
+
+impl CfaColor {
+    pub fn from_letter(letter: char) -> Option<CfaColor> {
+        match letter.to_ascii_uppercase() {
+            'R' => Some(CfaColor::Red),
+            'G' => Some(CfaColor::Green),
+            'B' => Some(CfaColor::Blue),
+            'C' => Some(CfaColor::Cyan),
+            'M' => Some(CfaColor::Magenta),
+            'Y' => Some(CfaColor::Yellow),
+            'W' => Some(CfaColor::White),
+            _ => None,
+        }
+    }
+
+    pub fn letter(&self) -> char {
+        match self {
+            CfaColor::Red => 'R',
+            CfaColor::Green => 'G',
+            CfaColor::Blue => 'B',
+            CfaColor::Cyan => 'C',
+            CfaColor::Magenta => 'M',
+            CfaColor::Yellow => 'Y',
+            CfaColor::White => 'W',
+        }
+    }
+
+    /// Returns the color identified by a value of the DNG CFAPlaneColor tag.
+    pub fn from_plane_color(plane_color: u8) -> Option<CfaColor> {
+        match plane_color {
+            0 => Some(CfaColor::Red),
+            1 => Some(CfaColor::Green),
+            2 => Some(CfaColor::Blue),
+            3 => Some(CfaColor::Cyan),
+            4 => Some(CfaColor::Magenta),
+            5 => Some(CfaColor::Yellow),
+            6 => Some(CfaColor::White),
+            _ => None,
+        }
+    }
+
+    /// Returns the value that identifies this color in the DNG CFAPlaneColor tag.
+    pub fn plane_color(&self) -> u8 {
+        match self {
+            CfaColor::Red => 0,
+            CfaColor::Green => 1,
+            CfaColor::Blue => 2,
+            CfaColor::Cyan => 3,
+            CfaColor::Magenta => 4,
+            CfaColor::Yellow => 5,
+            CfaColor::White => 6,
+        }
+    }
+}
+
+/// Rec. 709 luminance weights of the red, green and blue channels.
+pub const REC709_LUMA: [f32; 3] = [0.2126, 0.7152, 0.0722];
+
+/// Peak wavelength and standard deviation, in nanometers, of the Gaussian
+/// curves that approximate the spectral sensitivity of the red, green and
+/// blue input channels.
+const RGB_SENSITIVITIES: [(f32, f32); 3] = [(600.0, 40.0), (540.0, 40.0), (450.0, 30.0)];
+
+/// How each filter color responds to the red, green and blue channels of the
+/// input image, which is all a filter other than R, G or B can be synthesized from.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FilterResponses {
+    weights: [[f32; 3]; 7],
+}
+
+impl Default for FilterResponses {
+    fn default() -> FilterResponses {
+        FilterResponses {
+            weights: [
+                [1.0, 0.0, 0.0],
+                [0.0, 1.0, 0.0],
+                [0.0, 0.0, 1.0],
+                [0.0, 0.5, 0.5],
+                [0.5, 0.0, 0.5],
+                [0.5, 0.5, 0.0],
+                REC709_LUMA,
+            ],
+        }
+    }
+}
+
+impl FilterResponses {
+    pub fn new() -> FilterResponses {
+        FilterResponses::default()
+    }
+
+    /// Sets the weights of the red, green and blue channels seen through
+    /// filters of the given color. Samples whose weighted sum exceeds the
+    /// white level are clipped, like an overexposed photosite.
+    pub fn with_response(mut self, color: CfaColor, weights: [f32; 3]) -> Self {
+        self.weights[color.plane_color() as usize] = weights;
+        self
+    }
+
+    /// Sets the response of the Cyan, Magenta and Yellow filters to `gain`
+    /// times the sum of the two primaries each of them transmits. A gain of
+    /// 0.5, the default, keeps complementary samples within the white level.
+    pub fn with_complementary_gain(self, gain: f32) -> Self {
+        self.with_response(CfaColor::Cyan, [0.0, gain, gain])
+            .with_response(CfaColor::Magenta, [gain, 0.0, gain])
+            .with_response(CfaColor::Yellow, [gain, gain, 0.0])
+    }
+
+    /// Derives the response of a filter color from its transmission curve,
+    /// given as (wavelength in nanometers, transmission from 0 to 1) points.
+    ///
+    /// Each channel weight is the curve's average transmission over that
+    /// channel's approximate spectral sensitivity, so a filter that fully
+    /// transmits the visible spectrum responds to each channel with weight 1.
+    pub fn with_spectral_response(
+        self,
+        color: CfaColor,
+        curve: &[(f32, f32)],
+    ) -> Result<Self, &'static str> {
+        if curve.len() < 2 || curve.windows(2).any(|pair| pair[0].0 >= pair[1].0) {
+            return Err("Spectral curve needs at least two points with increasing wavelengths.");
+        }
+
+        let transmission = |wavelength: f32| match curve
+            .windows(2)
+            .find(|pair| wavelength >= pair[0].0 && wavelength <= pair[1].0)
+        {
+            Some(pair) => {
+                let t = (wavelength - pair[0].0) / (pair[1].0 - pair[0].0);
+                pair[0].1 + (pair[1].1 - pair[0].1) * t
+            }
+            None => 0.0,
+        };
+
+        let mut weights = [0.0; 3];
+        for (channel, &(peak, deviation)) in RGB_SENSITIVITIES.iter().enumerate() {
+            let (mut transmitted, mut total) = (0.0, 0.0);
+
+            for wavelength in 380..=780 {
+                let wavelength = wavelength as f32;
+                let sensitivity = (-((wavelength - peak) / deviation).powi(2) / 2.0).exp();
+                transmitted += sensitivity * transmission(wavelength);
+                total += sensitivity;
+            }
+
+            weights[channel] = transmitted / total;
+        }
+
+        Ok(self.with_response(color, weights))
+    }
+
+    pub fn response(&self, color: CfaColor) -> [f32; 3] {
+        self.weights[color.plane_color() as usize]
+    }
+}
+
+/// Color filter array laid over the sensor, made of a tile of filters that
+/// repeats over the whole image.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CfaPattern {
+    rows: u32,
+    columns: u32,
+    colors: Vec<CfaColor>,
+}
+
+impl CfaPattern {
+    /// Builds a pattern from the colors of its repeating tile, row by row.
+    pub fn new(rows: u32, columns: u32, colors: Vec<CfaColor>) -> Result<CfaPattern, &'static str> {
+        if rows == 0 || columns == 0 || rows > 255 || columns > 255 {
+            return Err("CFA pattern dimensions must be between 1 and 255.");
+        }
+
+        if colors.len() != (rows * columns) as usize {
+            return Err("CFA pattern needs exactly one color per site.");
+        }
+
+        Ok(CfaPattern {
+            rows,
+            columns,
+            colors,
+        })
+    }
+
+    pub fn bayer(bayer_pattern: BayerPattern) -> CfaPattern {
+        CfaPattern {
+            rows: 2,
+            columns: 2,
+            colors: bayer_pattern
+                .color_offsets()
+                .iter()
+                .map(|&offset| [CfaColor::Red, CfaColor::Green, CfaColor::Blue][offset as usize])
+                .collect(),
+        }
+    }
+
+    /// Quad Bayer (Tetracell) pattern, where every filter of the Bayer pattern
+    /// covers a 2x2 cell of photosites.
+    pub fn quad_bayer(bayer_pattern: BayerPattern) -> CfaPattern {
+        CfaPattern::bayer(bayer_pattern).expanded(2)
+    }
+
+    /// Nona-Bayer (Nonacell) pattern, where every filter of the Bayer pattern
+    /// covers a 3x3 cell of photosites.
+    pub fn nona_bayer(bayer_pattern: BayerPattern) -> CfaPattern {
+        CfaPattern::bayer(bayer_pattern).expanded(3)
+    }
+
+    /// Automotive pattern with a red, a blue and two clear photosites.
+    pub fn rccb() -> CfaPattern {
+        "RW/WB".parse().unwrap()
+    }
+
+    /// Automotive pattern with a red and three clear photosites.
+    pub fn rccc() -> CfaPattern {
+        "RW/WW".parse().unwrap()
+    }
+
+    /// Complementary pattern with Cyan, Yellow, Green and Magenta filters,
+    /// alternating the Green and Magenta sites every other line pair.
+    pub fn cygm() -> CfaPattern {
+        "CY/GM/CY/MG".parse().unwrap()
+    }
+
+    /// Complementary pattern with Cyan, Magenta and two Yellow filters.
+    pub fn cmy() -> CfaPattern {
+        "CY/YM".parse().unwrap()
+    }
+
+    /// Fujifilm's 6x6 X-Trans pattern.
+    pub fn xtrans() -> CfaPattern {
+        "GGRGGB/GGBGGR/BRGRBG/GGBGGR/GGRGGB/RBGBRG".parse().unwrap()
+    }
+
+    /// Returns the number of rows and columns of the repeating tile.
+    pub fn repeat_dim(&self) -> (u32, u32) {
+        (self.rows, self.columns)
+    }
+
+    pub fn color_at(&self, x: u32, y: u32) -> CfaColor {
+        self.colors[((y % self.rows) * self.columns + x % self.columns) as usize]
+    }
+
+    /// Returns the pattern seen by a readout window whose origin lies at
+    /// (`x`, `y`) on the full sensor.
+    pub fn shifted(&self, x: u32, y: u32) -> CfaPattern {
+        CfaPattern {
+            rows: self.rows,
+            columns: self.columns,
+            colors: (0..self.rows)
+                .flat_map(|row| (0..self.columns).map(move |column| (column, row)))
+                .map(|(column, row)| self.color_at(column + x, row + y))
+                .collect(),
+        }
+    }
+
+    /// Returns the side of the largest square cells of same-color sites that
+    /// the pattern is made of, which is 1 for ordinary patterns.
+    pub fn cell_size(&self) -> u32 {
+        (1..=self.rows.min(self.columns))
+            .rev()
+            .find(|&cell| {
+                self.rows % cell == 0
+                    && self.columns % cell == 0
+                    && (0..self.rows).all(|y| {
+                        (0..self.columns).all(|x| {
+                            self.color_at(x, y) == self.color_at(x - x % cell, y - y % cell)
+                        })
+                    })
+            })
+            .unwrap_or(1)
+    }
+
+    /// Returns this pattern with every site grown into a `cell` x `cell` block.
+    fn expanded(&self, cell: u32) -> CfaPattern {
+        let (rows, columns) = (self.rows * cell, self.columns * cell);
+
+        CfaPattern {
+            rows,
+            columns,
+            colors: (0..rows)
+                .flat_map(|y| (0..columns).map(move |x| (x, y)))
+                .map(|(x, y)| self.color_at(x / cell, y / cell))
+                .collect(),
+        }
+    }
+
+    /// Returns this pattern with every `cell` x `cell` block shrunk into a
+    /// single site. The inverse of `expanded`.
+    pub(crate) fn collapsed(&self, cell: u32) -> CfaPattern {
+        let (rows, columns) = (self.rows / cell, self.columns / cell);
+
+        CfaPattern {
+            rows,
+            columns,
+            colors: (0..rows)
+                .flat_map(|y| (0..columns).map(move |x| (x, y)))
+                .map(|(x, y)| self.color_at(x * cell, y * cell))
+                .collect(),
+        }
+    }
+
+    /// Returns the colors of the planes that the pattern's sites refer to.
+    ///
+    /// Patterns made only of red, green and blue filters always have those
+    /// three planes, in that order.
+    pub fn plane_colors(&self) -> Vec<CfaColor> {
+        let mut plane_colors = self.colors.clone();
+        if plane_colors.iter().all(|&color| color <= CfaColor::Blue) {
+            plane_colors = vec![CfaColor::Red, CfaColor::Green, CfaColor::Blue];
+        }
+
+        plane_colors.sort();
+        plane_colors.dedup();
+        plane_colors
+    }
+
+    /// Returns the plane index of every site in the repeating tile, row by
+    /// row, as stored in the DNG CFAPattern tag.
+    pub fn plane_indices(&self) -> Vec<u8> {
+        let plane_colors = self.plane_colors();
+
+        self.colors
+            .iter()
+            .map(|color| {
+                plane_colors
+                    .iter()
+                    .position(|plane| plane == color)
+                    .unwrap() as u8
+            })
+            .collect()
+    }
+}
+
+impl From<BayerPattern> for CfaPattern {
+    fn from(bayer_pattern: BayerPattern) -> CfaPattern {
+        CfaPattern::bayer(bayer_pattern)
+    }
+}
+
+impl fmt::Display for CfaPattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for row in 0..self.rows {
+            if row > 0 && (self.rows, self.columns) != (2, 2) {
+                write!(f, "/")?;
+            }
+
+            for column in 0..self.columns {
+                write!(f, "{}", self.color_at(column, row).letter())?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for CfaPattern {
+    type Err = &'static str;
+
+    /// Parses a pattern either by name ("XTRANS", "RCCB", "RCCC", "CYGM",
+    /// "CMY", "QUAD-RGGB", "NONA-RGGB"), as a square tile written row by row ("RGGB"), or as rows
+    /// separated by slashes ("RGB/GBR/BRG").
+    ///
+    /// In the automotive names, C stands for a clear (White) filter rather
+    /// than a Cyan one.
+    fn from_str(cfa_pattern: &str) -> Result<CfaPattern, &'static str> {
+        let cfa_pattern = cfa_pattern.trim().to_uppercase();
+
+        match cfa_pattern.as_str() {
+            "XTRANS" | "X-TRANS" => return Ok(CfaPattern::xtrans()),
+            "RCCB" => return Ok(CfaPattern::rccb()),
+            "RCCC" => return Ok(CfaPattern::rccc()),
+            "CYGM" => return Ok(CfaPattern::cygm()),
+            "CMY" => return Ok(CfaPattern::cmy()),
+            _ => {}
+        }
+
+        for &(prefix, cell) in &[("QUAD-", 2), ("TETRA-", 2), ("NONA-", 3)] {
+            if let Some(bayer) = cfa_pattern.strip_prefix(prefix) {
+                let bayer = bayer.parse::<CfaPattern>()?;
+                if bayer.repeat_dim() != (2, 2) {
+                    return Err("Quad and Nona-Bayer patterns must be based on a 2x2 pattern.");
+                }
+
+                return Ok(bayer.expanded(cell));
+            }
+        }
+
+        if cfa_pattern.is_empty() || !cfa_pattern.is_ascii() {
+            return Err("CFA pattern may only contain the colors R, G, B, C, M, Y and W.");
+        }
+
+        let rows: Vec<&str> = if cfa_pattern.contains('/') {
+            cfa_pattern.split('/').collect()
+        } else {
+            let side = (cfa_pattern.len() as f64).sqrt() as usize;
+            if side * side != cfa_pattern.len() {
+                return Err("CFA pattern without row separators must be a square tile.");
+            }
+
+            (0..side)
+                .map(|row| &cfa_pattern[row * side..(row + 1) * side])
+                .collect()
+        };
+
+        let columns = rows[0].len();
+        if rows.iter().any(|row| row.len() != columns) {
+            return Err("Every row of a CFA pattern must have the same length.");
+        }
+
+        let colors = rows
+            .iter()
+            .flat_map(|row| row.chars())
+            .map(CfaColor::from_letter)
+            .collect::<Option<Vec<CfaColor>>>()
+            .ok_or("CFA pattern may only contain the colors R, G, B, C, M, Y and W.")?;
+
+        CfaPattern::new(rows.len() as u32, columns as u32, colors)
+    }
+}
@@ -0,0 +1,197 @@
+// Author: Cláudio Gomes (TofuLynx)
+// Project: emubayer
+// License: GNU GPL Version 3 (https://www.gnu.org/licenses/gpl-3.0.en.html)
+
+use std::{fmt, str::FromStr};
+
+use byteorder::{BigEndian, LittleEndian, WriteBytesExt};
+use deflate;
+
+use super::{Endianness, RawImage};
+
+pub(crate) const COMPRESSION_NONE: u16 = 1;
+pub(crate) const COMPRESSION_DEFLATE: u16 = 8;
+
+/// How the raw data of a DNG file is compressed.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Compression {
+    #[default]
+    Uncompressed,
+    /// Adobe Deflate, a zlib stream, after the given predictor.
+    Deflate(Predictor),
+}
+
+/// How samples are predicted from the previous ones of their row before
+/// compression, storing only the differences.
+///
+/// Integer samples use horizontal differencing, and float samples use the
+/// floating point predictor, which differences their bytes.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Predictor {
+    #[default]
+    None,
+    /// Differences from the previous pixel.
+    Horizontal,
+    /// Differences from the pixel two before, as for pairs of photosites.
+    HorizontalX2,
+    /// Differences from the pixel four before.
+    HorizontalX4,
+}
+
+impl Predictor {
+    /// Returns how many pixels before a sample its prediction comes from.
+    pub(crate) fn distance(self) -> usize {
+        match self {
+            Predictor::None => 0,
+            Predictor::Horizontal => 1,
+            Predictor::HorizontalX2 => 2,
+            Predictor::HorizontalX4 => 4,
+        }
+    }
+
+    /// Returns the value of the Predictor tag, for integer or float samples.
+    pub(crate) fn tag(self, float: bool) -> u16 {
+        match (self, float) {
+            (Predictor::None, _) => 1,
+            (Predictor::Horizontal, false) => 2,
+            (Predictor::HorizontalX2, false) => 34892,
+            (Predictor::HorizontalX4, false) => 34893,
+            (Predictor::Horizontal, true) => 34894,
+            (Predictor::HorizontalX2, true) => 34895,
+            (Predictor::HorizontalX4, true) => 34896,
+        }
+    }
+
+    /// Returns the predictor of a Predictor tag value, and whether it is a
+    /// floating point one.
+    pub(crate) fn from_tag(tag: u32) -> Option<(Predictor, bool)> {
+        match tag {
+            1 => Some((Predictor::None, false)),
+            2 => Some((Predictor::Horizontal, false)),
+            34892 => Some((Predictor::HorizontalX2, false)),
+            34893 => Some((Predictor::HorizontalX4, false)),
+            3 | 34894 => Some((Predictor::Horizontal, true)),
+            34895 => Some((Predictor::HorizontalX2, true)),
+            34896 => Some((Predictor::HorizontalX4, true)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Predictor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Predictor::None => "none",
+                Predictor::Horizontal => "horizontal",
+                Predictor::HorizontalX2 => "horizontal-x2",
+                Predictor::HorizontalX4 => "horizontal-x4",
+            }
+        )
+    }
+}
+
+impl FromStr for Predictor {
+    type Err = &'static str;
+
+    fn from_str(predictor: &str) -> Result<Predictor, &'static str> {
+        match predictor.trim().to_lowercase().as_str() {
+            "none" => Ok(Predictor::None),
+            "horizontal" => Ok(Predictor::Horizontal),
+            "horizontal-x2" => Ok(Predictor::HorizontalX2),
+            "horizontal-x4" => Ok(Predictor::HorizontalX4),
+            _ => Err("Unknown predictor. Use none, horizontal, horizontal-x2 or horizontal-x4."),
+        }
+    }
+}
+
+impl RawImage {
+    /// Returns how the raw data is compressed when saved as DNG.
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+
+    /// Compresses the raw data with the given method when saved as DNG.
+    pub fn with_compression(mut self, compression: Compression) -> RawImage {
+        self.compression = compression;
+        self
+    }
+
+    /// Returns the raw data as stored in a DNG file, in its sample format and
+    /// byte order, predicted and compressed row by row.
+    pub(crate) fn image_bytes(&self) -> Vec<u8> {
+        let samples_per_pixel = self.layout.samples_per_pixel() as usize;
+        let row_length = self.width as usize * samples_per_pixel;
+        let predictor = match self.compression {
+            Compression::Uncompressed => Predictor::None,
+            Compression::Deflate(predictor) => predictor,
+        };
+        let distance = predictor.distance() * samples_per_pixel;
+        let big_endian = self.endianness == Endianness::Big;
+
+        let mut bytes = Vec::new();
+
+        if self.sample_format.is_float() {
+            let black_level = f32::from(self.black_level);
+            let range = f32::from(self.white_level) - black_level;
+            let sample_bytes = usize::from(self.sample_format.bits_per_sample() / 8);
+
+            for row in self.data.chunks(row_length) {
+                let values = row.iter().map(|&value| {
+                    self.sample_format
+                        .float_bytes((f32::from(value) - black_level) / range)
+                });
+
+                if predictor == Predictor::None {
+                    values.for_each(|value| {
+                        if big_endian {
+                            bytes.extend(value.iter().rev());
+                        } else {
+                            bytes.extend(value);
+                        }
+                    });
+                    continue;
+                }
+
+                // The bytes of every sample are spread across the row, from
+                // the most significant ones of every sample to the least,
+                // then differenced, whatever the byte order of the file.
+                let mut row_bytes = vec![0u8; row.len() * sample_bytes];
+                for (index, value) in values.enumerate() {
+                    for (byte, &value) in value.iter().rev().enumerate() {
+                        row_bytes[byte * row.len() + index] = value;
+                    }
+                }
+
+                for index in (distance..row_bytes.len()).rev() {
+                    row_bytes[index] = row_bytes[index].wrapping_sub(row_bytes[index - distance]);
+                }
+
+                bytes.extend(row_bytes);
+            }
+        } else {
+            for row in self.data.chunks(row_length) {
+                for index in 0..row.len() {
+                    let value = if predictor != Predictor::None && index >= distance {
+                        row[index].wrapping_sub(row[index - distance])
+                    } else {
+                        row[index]
+                    };
+
+                    if big_endian {
+                        bytes.write_u16::<BigEndian>(value).unwrap();
+                    } else {
+                        bytes.write_u16::<LittleEndian>(value).unwrap();
+                    }
+                }
+            }
+        }
+
+        match self.compression {
+            Compression::Uncompressed => bytes,
+            Compression::Deflate(_) => deflate::deflate_bytes_zlib(&bytes),
+        }
+    }
+}
@@ -0,0 +1,592 @@
+// Author: Cláudio Gomes (TofuLynx)
+// Project: emubayer
+// License: GNU GPL Version 3 (https://www.gnu.org/licenses/gpl-3.0.en.html)
+
+use std::{fmt, str::FromStr};
+
+use super::metrics::linear_to_lab;
+use super::{CfaColor, CfaPattern, RawImage, RgbImage, SensorLayout};
+
+/// Reference demosaicing algorithm, to compare other implementations against.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DemosaicAlgorithm {
+    /// Copies the closest photosite of each color.
+    Nearest,
+    /// Averages the closest photosites of each color.
+    Bilinear,
+    /// Gradient-corrected bilinear interpolation, by Malvar, He and Cutler.
+    MalvarHeCutler,
+    /// Variable Number of Gradients, by Chang, Cheung and Pang.
+    Vng,
+    /// Adaptive Homogeneity-Directed interpolation, by Hirakawa and Parks.
+    Ahd,
+}
+
+impl fmt::Display for DemosaicAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                DemosaicAlgorithm::Nearest => "nearest",
+                DemosaicAlgorithm::Bilinear => "bilinear",
+                DemosaicAlgorithm::MalvarHeCutler => "malvar",
+                DemosaicAlgorithm::Vng => "vng",
+                DemosaicAlgorithm::Ahd => "ahd",
+            }
+        )
+    }
+}
+
+impl FromStr for DemosaicAlgorithm {
+    type Err = &'static str;
+
+    fn from_str(algorithm: &str) -> Result<DemosaicAlgorithm, &'static str> {
+        match algorithm.trim().to_lowercase().as_str() {
+            "nearest" => Ok(DemosaicAlgorithm::Nearest),
+            "bilinear" => Ok(DemosaicAlgorithm::Bilinear),
+            "malvar" | "mhc" | "malvar-he-cutler" => Ok(DemosaicAlgorithm::MalvarHeCutler),
+            "vng" => Ok(DemosaicAlgorithm::Vng),
+            "ahd" => Ok(DemosaicAlgorithm::Ahd),
+            _ => Err("Unknown demosaicing algorithm. Use nearest, bilinear, malvar, vng or ahd."),
+        }
+    }
+}
+
+impl DemosaicAlgorithm {
+    pub fn all() -> Vec<DemosaicAlgorithm> {
+        vec![
+            DemosaicAlgorithm::Nearest,
+            DemosaicAlgorithm::Bilinear,
+            DemosaicAlgorithm::MalvarHeCutler,
+            DemosaicAlgorithm::Vng,
+            DemosaicAlgorithm::Ahd,
+        ]
+    }
+}
+
+impl RawImage {
+    /// Reconstructs the full color image from the raw data.
+    ///
+    /// Nearest and bilinear interpolation work on any pattern of red, green
+    /// and blue filters, while the other algorithms need a 2x2 Bayer pattern.
+    /// Monochrome images come out gray, and LinearRaw images come out as they are.
+    /// Samples are scaled from the black level to the white level, and only
+    /// the default crop of the image is returned.
+    pub fn demosaic(&self, algorithm: DemosaicAlgorithm) -> Result<RgbImage, &'static str> {
+        if self.white_level <= self.black_level {
+            return Err("White level must be above the black level to demosaic.");
+        }
+
+        let pixels = match self.layout {
+            SensorLayout::Cfa(ref cfa_pattern) => {
+                let mosaic = Mosaic::new(self, cfa_pattern)?;
+
+                match algorithm {
+                    DemosaicAlgorithm::Nearest => mosaic.nearest(),
+                    DemosaicAlgorithm::Bilinear => mosaic.bilinear(),
+                    DemosaicAlgorithm::MalvarHeCutler => mosaic.malvar_he_cutler()?,
+                    DemosaicAlgorithm::Vng => mosaic.vng()?,
+                    DemosaicAlgorithm::Ahd => mosaic.ahd()?,
+                }
+            }
+            SensorLayout::Monochrome => self
+                .data
+                .iter()
+                .map(|&value| [f32::from(value); 3])
+                .collect(),
+            SensorLayout::LinearRaw => self
+                .data
+                .chunks(3)
+                .map(|pixel| {
+                    [
+                        f32::from(pixel[0]),
+                        f32::from(pixel[1]),
+                        f32::from(pixel[2]),
+                    ]
+                })
+                .collect(),
+        };
+
+        let black_level = f32::from(self.black_level);
+        let range = f32::from(self.white_level) - black_level;
+        let pixels: Vec<[f32; 3]> = pixels
+            .iter()
+            .map(|pixel| {
+                [
+                    (pixel[0] - black_level) / range,
+                    (pixel[1] - black_level) / range,
+                    (pixel[2] - black_level) / range,
+                ]
+            })
+            .collect();
+
+        Ok(RgbImage::from_float(self.width, self.height, &pixels).cropped(self.default_crop))
+    }
+}
+
+/// Raw data of a red, green and blue CFA, with mirrored borders.
+struct Mosaic<'a> {
+    width: i32,
+    height: i32,
+    data: &'a [u16],
+    cfa_pattern: &'a CfaPattern,
+}
+
+impl<'a> Mosaic<'a> {
+    fn new(
+        raw_image: &'a RawImage,
+        cfa_pattern: &'a CfaPattern,
+    ) -> Result<Mosaic<'a>, &'static str> {
+        if cfa_pattern.plane_colors() != [CfaColor::Red, CfaColor::Green, CfaColor::Blue] {
+            return Err("Only red, green and blue CFA patterns can be demosaiced.");
+        }
+
+        // Every color has to be sampled somewhere in the tile to be
+        // interpolated, which all-RGB plane colors don't guarantee.
+        let (rows, columns) = cfa_pattern.repeat_dim();
+        let samples =
+            |color| (0..rows).any(|y| (0..columns).any(|x| cfa_pattern.color_at(x, y) == color));
+        if !samples(CfaColor::Red) || !samples(CfaColor::Green) || !samples(CfaColor::Blue) {
+            return Err("CFA pattern has no red, green or blue filter to demosaic.");
+        }
+
+        // Smaller images lack some of the tile's photosites, and with them
+        // maybe a whole color.
+        if raw_image.width < columns.max(2) || raw_image.height < rows.max(2) {
+            return Err("Image is too small to be demosaiced.");
+        }
+
+        Ok(Mosaic {
+            width: raw_image.width as i32,
+            height: raw_image.height as i32,
+            data: &raw_image.data,
+            cfa_pattern,
+        })
+    }
+
+    /// Mirrors coordinates that fall outside of the image.
+    fn mirror(&self, x: i32, y: i32) -> (u32, u32) {
+        let mirror = |value: i32, size: i32| {
+            let value = value.abs();
+            if value >= size {
+                (2 * (size - 1) - value).max(0)
+            } else {
+                value
+            }
+        };
+
+        (mirror(x, self.width) as u32, mirror(y, self.height) as u32)
+    }
+
+    fn value(&self, x: i32, y: i32) -> f32 {
+        let (x, y) = self.mirror(x, y);
+        f32::from(self.data[(y * self.width as u32 + x) as usize])
+    }
+
+    /// Returns the channel (0 for red, 1 for green, 2 for blue) sampled at
+    /// the given photosite.
+    fn channel(&self, x: i32, y: i32) -> usize {
+        let (x, y) = self.mirror(x, y);
+        self.cfa_pattern.color_at(x, y).plane_color() as usize
+    }
+
+    fn pixels(&self) -> impl Iterator<Item = (i32, i32)> {
+        let (width, height) = (self.width, self.height);
+        (0..height).flat_map(move |y| (0..width).map(move |x| (x, y)))
+    }
+
+    /// Checks that the pattern is one of the four 2x2 Bayer patterns.
+    fn check_bayer(&self) -> Result<(), &'static str> {
+        let channels: Vec<usize> = [(0, 0), (1, 0), (0, 1), (1, 1)]
+            .iter()
+            .map(|&(x, y)| self.channel(x, y))
+            .collect();
+
+        // Green on one diagonal, and red and blue on the other.
+        let diagonal = |a: usize, b: usize| channels[a] == 1 && channels[b] == 1;
+        let opposite = |a: usize, b: usize| channels[a] != 1 && channels[a] + channels[b] == 2;
+
+        let is_bayer = self.cfa_pattern.repeat_dim() == (2, 2)
+            && (diagonal(0, 3) && opposite(1, 2) || diagonal(1, 2) && opposite(0, 3));
+
+        if is_bayer {
+            Ok(())
+        } else {
+            Err("This demosaicing algorithm only supports 2x2 Bayer patterns.")
+        }
+    }
+
+    fn nearest(&self) -> Vec<[f32; 3]> {
+        let (rows, columns) = self.cfa_pattern.repeat_dim();
+        let radius = rows.max(columns) as i32;
+
+        let mut offsets: Vec<(i32, i32)> = (-radius..=radius)
+            .flat_map(|dy| (-radius..=radius).map(move |dx| (dx, dy)))
+            .collect();
+        offsets.sort_by_key(|&(dx, dy)| (dx * dx + dy * dy, dy, dx));
+
+        self.pixels()
+            .map(|(x, y)| {
+                let mut pixel = [0.0; 3];
+
+                for (channel, value) in pixel.iter_mut().enumerate() {
+                    // Offsets are inside the image, so no mirrored photosite is used.
+                    if let Some(&(dx, dy)) = offsets.iter().find(|&&(dx, dy)| {
+                        let (sx, sy) = (x + dx, y + dy);
+                        sx >= 0
+                            && sy >= 0
+                            && sx < self.width
+                            && sy < self.height
+                            && self.channel(sx, sy) == channel
+                    }) {
+                        *value = self.value(x + dx, y + dy);
+                    }
+                }
+
+                pixel
+            })
+            .collect()
+    }
+
+    fn bilinear(&self) -> Vec<[f32; 3]> {
+        let (rows, columns) = self.cfa_pattern.repeat_dim();
+        let max_radius = rows.max(columns) as i32;
+
+        self.pixels()
+            .map(|(x, y)| {
+                let own = self.channel(x, y);
+                let mut pixel = [0.0; 3];
+                pixel[own] = self.value(x, y);
+
+                for channel in (0..3).filter(|&channel| channel != own) {
+                    // Widen the window until it holds a photosite of this
+                    // channel, which a window as wide as the tile always does.
+                    for radius in 1..=max_radius {
+                        let (mut sum, mut count) = (0.0, 0);
+
+                        for dy in -radius..=radius {
+                            for dx in -radius..=radius {
+                                if self.channel(x + dx, y + dy) == channel {
+                                    sum += self.value(x + dx, y + dy);
+                                    count += 1;
+                                }
+                            }
+                        }
+
+                        if count > 0 {
+                            pixel[channel] = sum / count as f32;
+                            break;
+                        }
+                    }
+                }
+
+                pixel
+            })
+            .collect()
+    }
+
+    fn malvar_he_cutler(&self) -> Result<Vec<[f32; 3]>, &'static str> {
+        self.check_bayer()?;
+
+        // 5x5 filters, in eighths, from "High-Quality Linear Interpolation for
+        // Demosaicing of Bayer-Patterned Color Images".
+        const GREEN_AT_RED_OR_BLUE: [[f32; 5]; 5] = [
+            [0.0, 0.0, -1.0, 0.0, 0.0],
+            [0.0, 0.0, 2.0, 0.0, 0.0],
+            [-1.0, 2.0, 4.0, 2.0, -1.0],
+            [0.0, 0.0, 2.0, 0.0, 0.0],
+            [0.0, 0.0, -1.0, 0.0, 0.0],
+        ];
+        const AT_GREEN_FROM_ROW: [[f32; 5]; 5] = [
+            [0.0, 0.0, 0.5, 0.0, 0.0],
+            [0.0, -1.0, 0.0, -1.0, 0.0],
+            [-1.0, 4.0, 5.0, 4.0, -1.0],
+            [0.0, -1.0, 0.0, -1.0, 0.0],
+            [0.0, 0.0, 0.5, 0.0, 0.0],
+        ];
+        const AT_GREEN_FROM_COLUMN: [[f32; 5]; 5] = [
+            [0.0, 0.0, -1.0, 0.0, 0.0],
+            [0.0, -1.0, 4.0, -1.0, 0.0],
+            [0.5, 0.0, 5.0, 0.0, 0.5],
+            [0.0, -1.0, 4.0, -1.0, 0.0],
+            [0.0, 0.0, -1.0, 0.0, 0.0],
+        ];
+        const RED_AT_BLUE_OR_BLUE_AT_RED: [[f32; 5]; 5] = [
+            [0.0, 0.0, -1.5, 0.0, 0.0],
+            [0.0, 2.0, 0.0, 2.0, 0.0],
+            [-1.5, 0.0, 6.0, 0.0, -1.5],
+            [0.0, 2.0, 0.0, 2.0, 0.0],
+            [0.0, 0.0, -1.5, 0.0, 0.0],
+        ];
+
+        let filter = |x: i32, y: i32, kernel: &[[f32; 5]; 5]| {
+            let mut sum = 0.0;
+            for (ky, row) in kernel.iter().enumerate() {
+                for (kx, &weight) in row.iter().enumerate() {
+                    if weight != 0.0 {
+                        sum += weight * self.value(x + kx as i32 - 2, y + ky as i32 - 2);
+                    }
+                }
+            }
+
+            (sum / 8.0).clamp(0.0, 65535.0)
+        };
+
+        Ok(self
+            .pixels()
+            .map(|(x, y)| {
+                let own = self.channel(x, y);
+                let mut pixel = [0.0; 3];
+                pixel[own] = self.value(x, y);
+
+                if own == 1 {
+                    let row_channel = self.channel(x + 1, y);
+                    pixel[row_channel] = filter(x, y, &AT_GREEN_FROM_ROW);
+                    pixel[2 - row_channel] = filter(x, y, &AT_GREEN_FROM_COLUMN);
+                } else {
+                    pixel[1] = filter(x, y, &GREEN_AT_RED_OR_BLUE);
+                    pixel[2 - own] = filter(x, y, &RED_AT_BLUE_OR_BLUE_AT_RED);
+                }
+
+                pixel
+            })
+            .collect())
+    }
+
+    fn vng(&self) -> Result<Vec<[f32; 3]>, &'static str> {
+        self.check_bayer()?;
+
+        const DIRECTIONS: [(i32, i32); 8] = [
+            (0, -1),
+            (1, -1),
+            (1, 0),
+            (1, 1),
+            (0, 1),
+            (-1, 1),
+            (-1, 0),
+            (-1, -1),
+        ];
+
+        Ok(self
+            .pixels()
+            .map(|(x, y)| {
+                let own = self.channel(x, y);
+                let center = self.value(x, y);
+
+                // Gradients only compare photosites of the same color, which
+                // are 2 photosites apart along every direction.
+                let gradients: Vec<f32> = DIRECTIONS
+                    .iter()
+                    .map(|&(dx, dy)| {
+                        let (px, py) = (-dy, dx);
+                        let difference = |ox: i32, oy: i32, step: i32| {
+                            (self.value(x + ox + step * dx, y + oy + step * dy)
+                                - self.value(x + ox, y + oy))
+                            .abs()
+                        };
+
+                        difference(0, 0, 2)
+                            + difference(-dx, -dy, 2)
+                            + (difference(px, py, 2)
+                                + difference(-px, -py, 2)
+                                + difference(px - dx, py - dy, 2)
+                                + difference(-px - dx, -py - dy, 2))
+                                / 2.0
+                    })
+                    .collect();
+
+                let min = gradients.iter().cloned().fold(f32::MAX, f32::min);
+                let max = gradients.iter().cloned().fold(0.0, f32::max);
+                let threshold = 1.5 * min + 0.5 * (max - min);
+
+                // Average every channel over the 3x3 block next to the pixel,
+                // in each direction whose gradient is below the threshold.
+                let mut sums = [0.0; 3];
+                let mut directions = 0;
+                for (&(dx, dy), &gradient) in DIRECTIONS.iter().zip(gradients.iter()) {
+                    if gradient > threshold {
+                        continue;
+                    }
+
+                    let mut block = [(0.0, 0); 3];
+                    for by in -1..=1 {
+                        for bx in -1..=1 {
+                            let (sx, sy) = (x + dx + bx, y + dy + by);
+                            let channel = self.channel(sx, sy);
+                            block[channel].0 += self.value(sx, sy);
+                            block[channel].1 += 1;
+                        }
+                    }
+
+                    for channel in 0..3 {
+                        sums[channel] += block[channel].0 / block[channel].1 as f32;
+                    }
+                    directions += 1;
+                }
+
+                let mut pixel = [0.0; 3];
+                for (channel, value) in pixel.iter_mut().enumerate() {
+                    *value = if channel == own {
+                        center
+                    } else {
+                        (center + (sums[channel] - sums[own]) / directions as f32)
+                            .clamp(0.0, 65535.0)
+                    };
+                }
+
+                pixel
+            })
+            .collect())
+    }
+
+    fn ahd(&self) -> Result<Vec<[f32; 3]>, &'static str> {
+        self.check_bayer()?;
+
+        let (width, height) = (self.width, self.height);
+        let index = |x: i32, y: i32| {
+            let (x, y) = self.mirror(x, y);
+            (y * width as u32 + x) as usize
+        };
+
+        // Interpolate green along each direction, clamped between its neighbours.
+        let green = |dx: i32, dy: i32| -> Vec<f32> {
+            self.pixels()
+                .map(|(x, y)| {
+                    if self.channel(x, y) == 1 {
+                        return self.value(x, y);
+                    }
+
+                    let (before, after) = (self.value(x - dx, y - dy), self.value(x + dx, y + dy));
+                    let estimate = (before + after) / 2.0
+                        + (2.0 * self.value(x, y)
+                            - self.value(x - 2 * dx, y - 2 * dy)
+                            - self.value(x + 2 * dx, y + 2 * dy))
+                            / 4.0;
+
+                    estimate.clamp(before.min(after), before.max(after))
+                })
+                .collect()
+        };
+
+        // Interpolate red and blue from their differences to the green plane.
+        let interpolate = |green: &[f32]| -> Vec<[f32; 3]> {
+            let difference = |x: i32, y: i32| self.value(x, y) - green[index(x, y)];
+
+            self.pixels()
+                .map(|(x, y)| {
+                    let own = self.channel(x, y);
+                    let mut pixel = [0.0; 3];
+                    pixel[1] = green[index(x, y)];
+
+                    if own == 1 {
+                        let row_channel = self.channel(x + 1, y);
+                        pixel[row_channel] =
+                            pixel[1] + (difference(x - 1, y) + difference(x + 1, y)) / 2.0;
+                        pixel[2 - row_channel] =
+                            pixel[1] + (difference(x, y - 1) + difference(x, y + 1)) / 2.0;
+                    } else {
+                        pixel[own] = self.value(x, y);
+                        pixel[2 - own] = pixel[1]
+                            + (difference(x - 1, y - 1)
+                                + difference(x + 1, y - 1)
+                                + difference(x - 1, y + 1)
+                                + difference(x + 1, y + 1))
+                                / 4.0;
+                    }
+
+                    for value in pixel.iter_mut() {
+                        *value = value.clamp(0.0, 65535.0);
+                    }
+
+                    pixel
+                })
+                .collect()
+        };
+
+        let horizontal = interpolate(&green(1, 0));
+        let vertical = interpolate(&green(0, 1));
+        let to_lab = |pixel: &[f32; 3]| {
+            let lab = linear_to_lab([
+                f64::from(pixel[0]) / 65535.0,
+                f64::from(pixel[1]) / 65535.0,
+                f64::from(pixel[2]) / 65535.0,
+            ]);
+            [lab[0] as f32, lab[1] as f32, lab[2] as f32]
+        };
+        let horizontal_lab: Vec<[f32; 3]> = horizontal.iter().map(to_lab).collect();
+        let vertical_lab: Vec<[f32; 3]> = vertical.iter().map(to_lab).collect();
+
+        let luminance_distance =
+            |lab: &[[f32; 3]], a: usize, b: usize| (lab[a][0] - lab[b][0]).abs();
+        let chrominance_distance = |lab: &[[f32; 3]], a: usize, b: usize| {
+            ((lab[a][1] - lab[b][1]).powi(2) + (lab[a][2] - lab[b][2]).powi(2)).sqrt()
+        };
+
+        // Count, for every pixel and direction, how many of its neighbours
+        // are close enough in luminance and chrominance.
+        let mut homogeneity = vec![[0u8; 2]; (width * height) as usize];
+        for (x, y) in self.pixels() {
+            let center = index(x, y);
+            let (left, right) = (index(x - 1, y), index(x + 1, y));
+            let (up, down) = (index(x, y - 1), index(x, y + 1));
+
+            let luminance_epsilon = luminance_distance(&horizontal_lab, center, left)
+                .max(luminance_distance(&horizontal_lab, center, right))
+                .min(
+                    luminance_distance(&vertical_lab, center, up).max(luminance_distance(
+                        &vertical_lab,
+                        center,
+                        down,
+                    )),
+                );
+            let chrominance_epsilon = chrominance_distance(&horizontal_lab, center, left)
+                .max(chrominance_distance(&horizontal_lab, center, right))
+                .min(
+                    chrominance_distance(&vertical_lab, center, up).max(chrominance_distance(
+                        &vertical_lab,
+                        center,
+                        down,
+                    )),
+                );
+
+            for (direction, lab) in [&horizontal_lab, &vertical_lab].iter().enumerate() {
+                homogeneity[center][direction] = [left, right, up, down]
+                    .iter()
+                    .filter(|&&neighbour| {
+                        luminance_distance(lab, center, neighbour) <= luminance_epsilon
+                            && chrominance_distance(lab, center, neighbour) <= chrominance_epsilon
+                    })
+                    .count() as u8;
+            }
+        }
+
+        Ok(self
+            .pixels()
+            .map(|(x, y)| {
+                let mut scores = [0u32; 2];
+                for dy in -1..=1 {
+                    for dx in -1..=1 {
+                        let neighbour = homogeneity[index(x + dx, y + dy)];
+                        scores[0] += u32::from(neighbour[0]);
+                        scores[1] += u32::from(neighbour[1]);
+                    }
+                }
+
+                let center = index(x, y);
+                if scores[0] > scores[1] {
+                    horizontal[center]
+                } else if scores[1] > scores[0] {
+                    vertical[center]
+                } else {
+                    let mut pixel = [0.0; 3];
+                    for channel in 0..3 {
+                        pixel[channel] =
+                            (horizontal[center][channel] + vertical[center][channel]) / 2.0;
+                    }
+                    pixel
+                }
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,407 @@
+// Author: Cláudio Gomes (TofuLynx)
+// Project: emubayer
+// License: GNU GPL Version 3 (https://www.gnu.org/licenses/gpl-3.0.en.html)
+
+use std::{fmt, fs, path::Path, str::FromStr};
+
+use super::{
+    CfaColor, CfaPattern, Compression, FilterResponses, Orientation, Predictor, RawImage, Region,
+    SensorLayout,
+};
+use compression::COMPRESSION_DEFLATE;
+use tiff::*;
+
+pub(crate) const TAG_CFA_REPEAT_PATTERN_DIM: u16 = 0x828D;
+pub(crate) const TAG_CFA_PATTERN: u16 = 0x828E;
+pub(crate) const TAG_DNG_VERSION: u16 = 0xC612;
+pub(crate) const TAG_CFA_PLANE_COLOR: u16 = 0xC616;
+pub(crate) const TAG_CFA_LAYOUT: u16 = 0xC617;
+pub(crate) const TAG_BLACK_LEVEL: u16 = 0xC61A;
+pub(crate) const TAG_WHITE_LEVEL: u16 = 0xC61D;
+pub(crate) const TAG_DEFAULT_CROP_ORIGIN: u16 = 0xC61F;
+pub(crate) const TAG_DEFAULT_CROP_SIZE: u16 = 0xC620;
+pub(crate) const TAG_ACTIVE_AREA: u16 = 0xC68D;
+pub(crate) const TAG_EXPOSURE_TIME: u16 = 0x829A;
+pub(crate) const TAG_EXPOSURE_BIAS_VALUE: u16 = 0x9204;
+
+const SAMPLE_FORMAT_FLOAT: u32 = 3;
+
+const PHOTOMETRIC_BLACK_IS_ZERO: u32 = 1;
+const PHOTOMETRIC_CFA: u32 = 32803;
+const PHOTOMETRIC_LINEAR_RAW: u32 = 34892;
+
+/// How samples are stored in a DNG file.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum SampleFormat {
+    /// Unsigned 16-bit integers, between the black and white levels.
+    #[default]
+    Integer,
+    /// Half precision floats, with a black level of 0 and a white level of 1.
+    Float16,
+    /// 24-bit floats, with a black level of 0 and a white level of 1.
+    Float24,
+    /// Single precision floats, with a black level of 0 and a white level of 1.
+    Float32,
+}
+
+impl SampleFormat {
+    pub fn bits_per_sample(self) -> u16 {
+        match self {
+            SampleFormat::Integer | SampleFormat::Float16 => 16,
+            SampleFormat::Float24 => 24,
+            SampleFormat::Float32 => 32,
+        }
+    }
+
+    pub fn is_float(self) -> bool {
+        self != SampleFormat::Integer
+    }
+
+    /// Returns the little-endian bytes of a sample, in this format.
+    pub(crate) fn float_bytes(self, value: f32) -> Vec<u8> {
+        let bits = match self {
+            SampleFormat::Float16 => float_to_bits(value, 5, 10),
+            SampleFormat::Float24 => float_to_bits(value, 7, 16),
+            _ => value.to_bits(),
+        };
+
+        bits.to_le_bytes()[..usize::from(self.bits_per_sample() / 8)].to_vec()
+    }
+}
+
+impl fmt::Display for SampleFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                SampleFormat::Integer => "integer",
+                SampleFormat::Float16 => "float16",
+                SampleFormat::Float24 => "float24",
+                SampleFormat::Float32 => "float32",
+            }
+        )
+    }
+}
+
+impl FromStr for SampleFormat {
+    type Err = &'static str;
+
+    fn from_str(format: &str) -> Result<SampleFormat, &'static str> {
+        match format.trim().to_lowercase().as_str() {
+            "integer" => Ok(SampleFormat::Integer),
+            "float16" => Ok(SampleFormat::Float16),
+            "float24" => Ok(SampleFormat::Float24),
+            "float32" => Ok(SampleFormat::Float32),
+            _ => Err("Unknown sample format. Use integer, float16, float24 or float32."),
+        }
+    }
+}
+
+/// Byte order of a DNG file.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Endianness {
+    /// Least significant byte first, as in "II" files.
+    #[default]
+    Little,
+    /// Most significant byte first, as in "MM" files.
+    Big,
+}
+
+impl fmt::Display for Endianness {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Endianness::Little => "little",
+                Endianness::Big => "big",
+            }
+        )
+    }
+}
+
+impl FromStr for Endianness {
+    type Err = &'static str;
+
+    fn from_str(endianness: &str) -> Result<Endianness, &'static str> {
+        match endianness.trim().to_lowercase().as_str() {
+            "little" | "ii" => Ok(Endianness::Little),
+            "big" | "mm" => Ok(Endianness::Big),
+            _ => Err("Unknown byte order. Use little (II) or big (MM)."),
+        }
+    }
+}
+
+impl RawImage {
+    /// Returns whether the file is a DNG file, judging by its TIFF or
+    /// BigTIFF header and the DNGVersion tag of its first IFD rather than
+    /// its extension.
+    pub fn is_dng_file<P: AsRef<Path>>(path: P) -> bool {
+        fs::read(path)
+            .ok()
+            .and_then(|data| TiffReader::new(data).ok())
+            .and_then(|tiff| tiff.ifds().ok())
+            .map_or(false, |ifds| is_dng(&ifds))
+    }
+
+    /// Reads the raw data of an uncompressed DNG file, such as the ones
+    /// written by `save_as_dng` or by a camera.
+    ///
+    /// Samples are kept as stored, along with the file's black and white
+    /// levels. Filter responses can't be recovered, so the default ones are used.
+    ///
+    /// Float samples are scaled to 16 bits, with their brightest one at the
+    /// top of the range, or white if none is brighter. Values past white are
+    /// kept by lowering the white level to match.
+    pub fn from_dng<P: AsRef<Path>>(path: P) -> Result<RawImage, &'static str> {
+        let data = fs::read(path).map_err(|_| "DNG file couldn't be opened.")?;
+        let tiff = TiffReader::new(data)?;
+
+        let ifds = tiff.ifds()?;
+        if !is_dng(&ifds) {
+            return Err("This TIFF file isn't a DNG file.");
+        }
+
+        // DNG files store the orientation of the raw image in IFD0.
+        let orientation = tiff
+            .u32_value(&ifds[0], TAG_ORIENTATION)
+            .and_then(|value| Orientation::from_tag(value as u16))
+            .unwrap_or_default();
+
+        // So are the EXIF tags describing the exposure.
+        let exif_value = |tag| {
+            tiff.f64_values(&ifds[0], tag)
+                .and_then(|values| values.first().cloned())
+                .filter(|value| value.is_finite())
+        };
+        let exposure_time = exif_value(TAG_EXPOSURE_TIME);
+        let exposure_bias = exif_value(TAG_EXPOSURE_BIAS_VALUE);
+
+        // The raw image may be the main image or one of its SubIFDs, next to
+        // previews. Monochrome raw images can only be told apart from
+        // previews by being full resolution.
+        let mut candidates = Vec::new();
+        for ifd in ifds {
+            let sub_ifds = tiff.sub_ifds(&ifd)?;
+            candidates.push(ifd);
+            candidates.extend(sub_ifds);
+        }
+
+        let full_resolution: Vec<_> = candidates
+            .iter()
+            .filter(|ifd| tiff.u32_value(ifd, TAG_NEW_SUBFILE_TYPE).unwrap_or(0) == 0)
+            .collect();
+        let raw_ifd = full_resolution
+            .iter()
+            .find(|ifd| {
+                let photometric = tiff.u32_value(ifd, TAG_PHOTOMETRIC_INTERPRETATION);
+                photometric == Some(PHOTOMETRIC_CFA) || photometric == Some(PHOTOMETRIC_LINEAR_RAW)
+            })
+            .or_else(|| {
+                full_resolution.iter().find(|ifd| {
+                    tiff.u32_value(ifd, TAG_PHOTOMETRIC_INTERPRETATION)
+                        == Some(PHOTOMETRIC_BLACK_IS_ZERO)
+                })
+            })
+            .ok_or("DNG file has no raw image.")?;
+
+        let samples_per_pixel = tiff.u32_value(raw_ifd, TAG_SAMPLES_PER_PIXEL).unwrap_or(1);
+        let layout = match tiff.u32_value(raw_ifd, TAG_PHOTOMETRIC_INTERPRETATION) {
+            Some(PHOTOMETRIC_CFA) if samples_per_pixel == 1 => {
+                SensorLayout::Cfa(read_cfa_pattern(&tiff, raw_ifd)?)
+            }
+            Some(PHOTOMETRIC_LINEAR_RAW) if samples_per_pixel == 3 => SensorLayout::LinearRaw,
+            Some(PHOTOMETRIC_BLACK_IS_ZERO) if samples_per_pixel == 1 => SensorLayout::Monochrome,
+            _ => return Err("DNG raw image has an unsupported number of samples per pixel."),
+        };
+
+        let width = tiff.u32_value(raw_ifd, TAG_IMAGE_WIDTH).unwrap_or(0);
+        let height = tiff.u32_value(raw_ifd, TAG_IMAGE_LENGTH).unwrap_or(0);
+        let sample_format = match tiff.u32_value(raw_ifd, TAG_SAMPLE_FORMAT) {
+            Some(SAMPLE_FORMAT_FLOAT) => match tiff.u32_value(raw_ifd, TAG_BITS_PER_SAMPLE) {
+                Some(16) => SampleFormat::Float16,
+                Some(24) => SampleFormat::Float24,
+                Some(32) => SampleFormat::Float32,
+                _ => return Err("DNG float samples must have 16, 24 or 32 bits."),
+            },
+            _ => SampleFormat::Integer,
+        };
+
+        // Deflated files are written back the same way, with the same
+        // predictor.
+        let compression = match tiff.u32_value(raw_ifd, TAG_COMPRESSION) {
+            Some(compression) if compression == u32::from(COMPRESSION_DEFLATE) => {
+                Compression::Deflate(
+                    tiff.u32_value(raw_ifd, TAG_PREDICTOR)
+                        .and_then(Predictor::from_tag)
+                        .map_or(Predictor::None, |(predictor, _)| predictor),
+                )
+            }
+            _ => Compression::Uncompressed,
+        };
+
+        // So are big-endian and BigTIFF files.
+        let endianness = if tiff.is_big_endian() {
+            Endianness::Big
+        } else {
+            Endianness::Little
+        };
+        let big_tiff = tiff.is_big_tiff();
+
+        let (data, float_white_level) = if sample_format.is_float() {
+            let samples = tiff.float_samples(raw_ifd)?;
+            let brightest = samples
+                .iter()
+                .cloned()
+                .filter(|value| value.is_finite())
+                .fold(1.0f32, f32::max);
+            let scale = f32::from(u16::MAX) / brightest;
+            let data = samples
+                .iter()
+                .map(|&value| (value * scale).round().clamp(0.0, f32::from(u16::MAX)) as u16)
+                .collect();
+
+            (data, Some(f64::from(scale)))
+        } else {
+            (tiff.image_samples(raw_ifd)?, None)
+        };
+
+        // Photosites outside of the active area, such as masked ones, are dropped.
+        let active_area = match tiff.u32_values(raw_ifd, TAG_ACTIVE_AREA).as_deref() {
+            Some(&[top, left, bottom, right])
+                if top < bottom && left < right && bottom <= height && right <= width =>
+            {
+                Region::new(left, top, right - left, bottom - top)
+            }
+            Some(_) => return Err("DNG active area is invalid."),
+            None => Region::new(0, 0, width, height),
+        };
+
+        let samples_per_pixel = samples_per_pixel as usize;
+        let mut active_data = Vec::with_capacity(
+            (active_area.width * active_area.height) as usize * samples_per_pixel,
+        );
+        for row in data
+            .chunks(width as usize * samples_per_pixel)
+            .skip(active_area.y as usize)
+            .take(active_area.height as usize)
+        {
+            let start = active_area.x as usize * samples_per_pixel;
+            active_data.extend_from_slice(
+                &row[start..start + active_area.width as usize * samples_per_pixel],
+            );
+        }
+
+        // The default crop is relative to the active area, and defaults to all of it.
+        let crop_origin = tiff.f64_values(raw_ifd, TAG_DEFAULT_CROP_ORIGIN);
+        let crop_size = tiff.f64_values(raw_ifd, TAG_DEFAULT_CROP_SIZE);
+        let default_crop = match (crop_origin.as_deref(), crop_size.as_deref()) {
+            (origin, Some(&[crop_width, crop_height])) => {
+                let (x, y) = match origin {
+                    Some(&[x, y]) => (x.round() as u32, y.round() as u32),
+                    _ => (0, 0),
+                };
+                let (crop_width, crop_height) =
+                    (crop_width.round() as u32, crop_height.round() as u32);
+
+                if crop_width == 0
+                    || crop_height == 0
+                    || x.checked_add(crop_width)
+                        .map_or(true, |end| end > active_area.width)
+                    || y.checked_add(crop_height)
+                        .map_or(true, |end| end > active_area.height)
+                {
+                    return Err("DNG default crop lies outside of the image.");
+                }
+
+                Region::new(x, y, crop_width, crop_height)
+            }
+            _ => Region::new(0, 0, active_area.width, active_area.height),
+        };
+
+        // Levels given per sample or per CFA position are averaged. Float
+        // samples were scaled to a black level of 0.
+        let mean = |values: Vec<f64>| values.iter().sum::<f64>() / values.len() as f64;
+        let (black_level, white_level) = match float_white_level {
+            Some(white_level) => (0.0, white_level),
+            None => {
+                let bits = tiff.u32_value(raw_ifd, TAG_BITS_PER_SAMPLE).unwrap_or(16);
+                let default_white_level = ((1u32 << bits) - 1) as f64;
+
+                (
+                    tiff.f64_values(raw_ifd, TAG_BLACK_LEVEL)
+                        .filter(|values| !values.is_empty())
+                        .map_or(0.0, mean),
+                    tiff.f64_values(raw_ifd, TAG_WHITE_LEVEL)
+                        .filter(|values| !values.is_empty())
+                        .map_or(default_white_level, mean),
+                )
+            }
+        };
+
+        if white_level <= black_level {
+            return Err("DNG white level must be above its black level.");
+        }
+
+        Ok(RawImage {
+            black_level: black_level.round().clamp(0.0, 65535.0) as u16,
+            white_level: white_level.round().clamp(0.0, 65535.0) as u16,
+            default_crop,
+            orientation,
+            exposure_time,
+            exposure_bias,
+            sample_format,
+            compression,
+            endianness,
+            big_tiff,
+            ..RawImage::new(
+                active_area.width,
+                active_area.height,
+                active_data,
+                layout,
+                FilterResponses::default(),
+            )
+        })
+    }
+}
+
+fn read_cfa_pattern(tiff: &TiffReader, ifd: &Ifd) -> Result<CfaPattern, &'static str> {
+    if tiff.u32_value(ifd, TAG_CFA_LAYOUT).unwrap_or(1) != 1 {
+        return Err("Only DNG files with rectangular CFA layouts are supported.");
+    }
+
+    let dimensions = tiff
+        .u32_values(ifd, TAG_CFA_REPEAT_PATTERN_DIM)
+        .ok_or("DNG file has no CFA pattern dimensions.")?;
+    let (rows, columns) = match dimensions.as_slice() {
+        &[rows, columns] => (rows, columns),
+        _ => return Err("DNG CFA pattern dimensions are invalid."),
+    };
+
+    let plane_colors = tiff
+        .bytes(ifd, TAG_CFA_PLANE_COLOR)
+        .map_or(vec![0, 1, 2], <[u8]>::to_vec);
+    let pattern = tiff
+        .bytes(ifd, TAG_CFA_PATTERN)
+        .ok_or("DNG file has no CFA pattern.")?;
+
+    let colors = pattern
+        .iter()
+        .map(|&plane| {
+            plane_colors
+                .get(plane as usize)
+                .and_then(|&color| CfaColor::from_plane_color(color))
+                .ok_or("DNG CFA pattern has an unknown color.")
+        })
+        .collect::<Result<Vec<CfaColor>, _>>()?;
+
+    CfaPattern::new(rows, columns, colors)
+}
+
+/// Returns whether the IFDs of a TIFF file are those of a DNG file, whose
+/// first IFD holds the DNGVersion tag.
+fn is_dng(ifds: &[Ifd]) -> bool {
+    ifds.first()
+        .map_or(false, |ifd| ifd.contains(TAG_DNG_VERSION))
+}
@@ -0,0 +1,333 @@
+// Author: Cláudio Gomes (TofuLynx)
+// Project: emubayer
+// License: GNU GPL Version 3 (https://www.gnu.org/licenses/gpl-3.0.en.html)
+
+use std::convert::TryFrom;
+
+use byteorder::{ByteOrder, LittleEndian};
+use inflate;
+
+use super::LinearImage;
+
+const MAGIC: [u8; 4] = [0x76, 0x2F, 0x31, 0x01];
+
+const FLAG_TILED: u32 = 0x200;
+const FLAG_DEEP: u32 = 0x800;
+const FLAG_MULTI_PART: u32 = 0x1000;
+
+const PIXEL_TYPE_UINT: u32 = 0;
+const PIXEL_TYPE_HALF: u32 = 1;
+const PIXEL_TYPE_FLOAT: u32 = 2;
+
+const TRUNCATED: &str = "OpenEXR file is truncated.";
+const TOO_LARGE: &str = "OpenEXR data window is too large.";
+
+/// Most bytes that a single byte of zlib data can expand to, which bounds
+/// the size of the pixels that a file can hold.
+const MAX_EXPANSION: usize = 1032;
+
+/// Compression of the pixel data of an OpenEXR file. The others, such as PIZ
+/// or B44, aren't supported.
+#[derive(Clone, Copy, PartialEq)]
+enum Compression {
+    None,
+    Rle,
+    Zips,
+    Zip,
+}
+
+impl Compression {
+    fn from_value(value: u8) -> Result<Compression, &'static str> {
+        match value {
+            0 => Ok(Compression::None),
+            1 => Ok(Compression::Rle),
+            2 => Ok(Compression::Zips),
+            3 => Ok(Compression::Zip),
+            _ => Err("Only uncompressed, RLE, ZIPS and ZIP OpenEXR files are supported."),
+        }
+    }
+
+    fn lines_per_block(self) -> u32 {
+        match self {
+            Compression::Zip => 16,
+            _ => 1,
+        }
+    }
+}
+
+struct Channel {
+    name: String,
+    pixel_type: u32,
+}
+
+impl Channel {
+    fn sample_size(&self) -> usize {
+        if self.pixel_type == PIXEL_TYPE_HALF {
+            2
+        } else {
+            4
+        }
+    }
+}
+
+pub(crate) fn is_exr(data: &[u8]) -> bool {
+    data.starts_with(&MAGIC)
+}
+
+/// Reads a single-part scanline OpenEXR image, which is uncompressed or
+/// compressed with RLE, ZIPS or ZIP. Its R, G and B channels, or its Y
+/// channel, are read as linear light over its data window.
+pub(crate) fn read_exr(data: &[u8]) -> Result<LinearImage, &'static str> {
+    if !is_exr(data) {
+        return Err("This file isn't an OpenEXR file.");
+    }
+
+    let version = LittleEndian::read_u32(bytes(data, 4, 4)?);
+    if version & 0xFF != 2 {
+        return Err("Only version 2 OpenEXR files are supported.");
+    }
+    if version & FLAG_TILED != 0 {
+        return Err("Tiled OpenEXR files aren't supported.");
+    }
+    if version & (FLAG_DEEP | FLAG_MULTI_PART) != 0 {
+        return Err("Deep and multi-part OpenEXR files aren't supported.");
+    }
+
+    let mut channels = None;
+    let mut compression = None;
+    let mut data_window = None;
+    let mut position = 8;
+
+    loop {
+        let name = string(data, &mut position)?;
+        if name.is_empty() {
+            break;
+        }
+        let _attribute_type = string(data, &mut position)?;
+        let size = LittleEndian::read_i32(bytes(data, position, 4)?);
+        if size < 0 {
+            return Err("OpenEXR header is corrupted.");
+        }
+        let value = bytes(data, position + 4, size as usize)?;
+        position += 4 + size as usize;
+
+        match name.as_str() {
+            "channels" => channels = Some(read_channels(value)?),
+            "compression" => {
+                compression = Some(Compression::from_value(*value.first().ok_or(TRUNCATED)?)?)
+            }
+            "dataWindow" if value.len() >= 16 => {
+                let mut window = [0; 4];
+                LittleEndian::read_i32_into(&value[..16], &mut window);
+                data_window = Some(window);
+            }
+            _ => {}
+        }
+    }
+
+    let channels = channels.ok_or("OpenEXR file has no channel list.")?;
+    let compression = compression.ok_or("OpenEXR file has no compression.")?;
+    let [x_min, y_min, x_max, y_max] = data_window.ok_or("OpenEXR file has no data window.")?;
+    if x_max < x_min || y_max < y_min {
+        return Err("OpenEXR data window is empty.");
+    }
+    let width = u32::try_from(i64::from(x_max) - i64::from(x_min) + 1).map_err(|_| TOO_LARGE)?;
+    let height = u32::try_from(i64::from(y_max) - i64::from(y_min) + 1).map_err(|_| TOO_LARGE)?;
+
+    // Channels are stored in alphabetical order, next to each other in
+    // every scanline.
+    let find = |name: &str| channels.iter().position(|channel| channel.name == name);
+    let planes = match (find("R"), find("G"), find("B"), find("Y")) {
+        (Some(red), Some(green), Some(blue), _) => [red, green, blue],
+        (_, _, _, Some(luminance)) => [luminance; 3],
+        _ => return Err("OpenEXR file has no R, G and B channels, nor a Y channel."),
+    };
+
+    let offsets: Vec<usize> = channels
+        .iter()
+        .scan(0, |offset, channel| {
+            let start = *offset;
+            *offset += channel.sample_size() * width as usize;
+            Some(start)
+        })
+        .collect();
+    let line_size: usize = channels
+        .iter()
+        .map(|channel| channel.sample_size() * width as usize)
+        .sum();
+
+    let lines_per_block = compression.lines_per_block();
+    let block_count = height / lines_per_block + u32::from(height % lines_per_block != 0);
+
+    // The block offsets and the pixels they hold must fit in the file
+    // before anything is allocated for them.
+    bytes(data, position, block_count as usize * 8)?;
+    let pixel_count = (width as usize)
+        .checked_mul(height as usize)
+        .ok_or(TOO_LARGE)?;
+    if line_size
+        .checked_mul(height as usize)
+        .map_or(true, |size| size / MAX_EXPANSION > data.len())
+    {
+        return Err(TOO_LARGE);
+    }
+    let mut pixels = vec![[0.0f32; 3]; pixel_count];
+
+    for block in 0..block_count {
+        let offset = LittleEndian::read_u64(bytes(data, position + block as usize * 8, 8)?);
+        let offset = offset as usize;
+
+        let first_line = LittleEndian::read_i32(bytes(data, offset, 4)?);
+        let size = LittleEndian::read_i32(bytes(data, offset + 4, 4)?);
+        if first_line < y_min || first_line > y_max || size < 0 {
+            return Err("OpenEXR file has a corrupted block.");
+        }
+        let first_line = (first_line - y_min) as u32;
+        let lines = lines_per_block.min(height - first_line);
+        let expected_size = line_size * lines as usize;
+
+        // Blocks that compression would have grown are stored as they are.
+        let stored = bytes(data, offset + 8, size as usize)?;
+        let block_data = if stored.len() == expected_size {
+            stored.to_vec()
+        } else {
+            match compression {
+                Compression::None => return Err("OpenEXR file has a corrupted block."),
+                Compression::Rle => reconstruct(decode_rle(stored)?),
+                Compression::Zips | Compression::Zip => reconstruct(
+                    inflate::inflate_bytes_zlib(stored)
+                        .map_err(|_| "OpenEXR file has a corrupted ZIP block.")?,
+                ),
+            }
+        };
+        if block_data.len() != expected_size {
+            return Err("OpenEXR file has a corrupted block.");
+        }
+
+        for (line, line_data) in block_data.chunks(line_size).enumerate() {
+            let y = first_line + line as u32;
+
+            for x in 0..width as usize {
+                let pixel = &mut pixels[(y * width) as usize + x];
+
+                for (value, &plane) in pixel.iter_mut().zip(planes.iter()) {
+                    let channel = &channels[plane];
+                    let start = offsets[plane] + x * channel.sample_size();
+                    let sample = &line_data[start..start + channel.sample_size()];
+
+                    *value = match channel.pixel_type {
+                        PIXEL_TYPE_HALF => half_to_f32(LittleEndian::read_u16(sample)),
+                        PIXEL_TYPE_UINT => LittleEndian::read_u32(sample) as f32,
+                        _ => LittleEndian::read_f32(sample),
+                    };
+                }
+            }
+        }
+    }
+
+    LinearImage::sanitized(width, height, pixels)
+}
+
+fn read_channels(value: &[u8]) -> Result<Vec<Channel>, &'static str> {
+    let mut channels = Vec::new();
+    let mut position = 0;
+
+    loop {
+        let name = string(value, &mut position)?;
+        if name.is_empty() {
+            return Ok(channels);
+        }
+
+        // Pixel type, linearity, three reserved bytes and sampling.
+        let pixel_type = LittleEndian::read_u32(bytes(value, position, 4)?);
+        let x_sampling = LittleEndian::read_i32(bytes(value, position + 8, 4)?);
+        let y_sampling = LittleEndian::read_i32(bytes(value, position + 12, 4)?);
+        position += 16;
+
+        if pixel_type > PIXEL_TYPE_FLOAT {
+            return Err("OpenEXR file has an unknown pixel type.");
+        }
+        if x_sampling != 1 || y_sampling != 1 {
+            return Err("Subsampled OpenEXR channels aren't supported.");
+        }
+
+        channels.push(Channel { name, pixel_type });
+    }
+}
+
+/// Reads a null-terminated string, moving past it.
+fn string(data: &[u8], position: &mut usize) -> Result<String, &'static str> {
+    let rest = data.get(*position..).ok_or(TRUNCATED)?;
+    let length = rest.iter().position(|&byte| byte == 0).ok_or(TRUNCATED)?;
+    *position += length + 1;
+
+    String::from_utf8(rest[..length].to_vec()).map_err(|_| "OpenEXR header is corrupted.")
+}
+
+fn bytes(data: &[u8], position: usize, length: usize) -> Result<&[u8], &'static str> {
+    data.get(position..position.checked_add(length).ok_or(TRUNCATED)?)
+        .ok_or(TRUNCATED)
+}
+
+/// Expands runs of bytes, given as a negative count followed by as many
+/// literal bytes, or a count followed by a byte repeated one more time.
+fn decode_rle(data: &[u8]) -> Result<Vec<u8>, &'static str> {
+    let mut decoded = Vec::with_capacity(data.len() * 2);
+    let mut position = 0;
+
+    while position < data.len() {
+        let count = data[position] as i8;
+        position += 1;
+
+        if count < 0 {
+            let length = -i32::from(count) as usize;
+            decoded.extend_from_slice(bytes(data, position, length)?);
+            position += length;
+        } else {
+            let value = *data.get(position).ok_or(TRUNCATED)?;
+            decoded.extend(std::iter::repeat(value).take(count as usize + 1));
+            position += 1;
+        }
+    }
+
+    Ok(decoded)
+}
+
+/// Undoes the byte predictor and the splitting of every value's bytes into
+/// two halves, which precede RLE and ZIP compression.
+fn reconstruct(mut data: Vec<u8>) -> Vec<u8> {
+    for i in 1..data.len() {
+        data[i] = data[i - 1].wrapping_add(data[i]).wrapping_sub(128);
+    }
+
+    let half = (data.len() + 1) / 2;
+    let mut interleaved = Vec::with_capacity(data.len());
+    for i in 0..half {
+        interleaved.push(data[i]);
+        if let Some(&byte) = data.get(half + i) {
+            interleaved.push(byte);
+        }
+    }
+
+    interleaved
+}
+
+pub(crate) fn half_to_f32(bits: u16) -> f32 {
+    let negative = bits & 0x8000 != 0;
+    let exponent = u32::from((bits >> 10) & 0x1F);
+    let mantissa = u32::from(bits & 0x3FF);
+
+    let magnitude = match exponent {
+        // Zeros and subnormals.
+        0 => mantissa as f32 / (1 << 24) as f32,
+        0x1F if mantissa == 0 => f32::INFINITY,
+        0x1F => f32::NAN,
+        _ => f32::from_bits(((exponent + 112) << 23) | (mantissa << 13)),
+    };
+
+    if negative {
+        -magnitude
+    } else {
+        magnitude
+    }
+}
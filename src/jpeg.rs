@@ -0,0 +1,61 @@
+// Author: Cláudio Gomes (TofuLynx)
+// Project: emubayer
+// License: GNU GPL Version 3 (https://www.gnu.org/licenses/gpl-3.0.en.html)
+
+use jpeg_decoder::{Decoder, PixelFormat};
+
+use super::{BitDepth, RgbImage};
+use orientation;
+
+pub(crate) fn is_jpeg(data: &[u8]) -> bool {
+    data.starts_with(&[0xFF, 0xD8, 0xFF])
+}
+
+/// Decodes a grayscale or YCbCr JPEG image, rotating and flipping it
+/// upright if it has an EXIF orientation. Lossless 16-bit images keep their
+/// depth.
+pub(crate) fn read_jpeg(data: &[u8]) -> Result<RgbImage, &'static str> {
+    let mut decoder = Decoder::new(data);
+    let pixels = decoder
+        .decode()
+        .map_err(|_| "This JPEG file appears to be corrupted.")?;
+    let info = decoder
+        .info()
+        .ok_or("This JPEG file appears to be corrupted.")?;
+
+    let (channels, bit_depth, samples): (usize, BitDepth, Vec<u16>) = match info.pixel_format {
+        PixelFormat::L8 => (
+            1,
+            BitDepth::Eight,
+            pixels.iter().map(|&v| v.into()).collect(),
+        ),
+        PixelFormat::RGB24 => (
+            3,
+            BitDepth::Eight,
+            pixels.iter().map(|&v| v.into()).collect(),
+        ),
+        PixelFormat::L16 => (
+            1,
+            BitDepth::Sixteen,
+            pixels
+                .chunks(2)
+                .map(|bytes| u16::from_ne_bytes([bytes[0], bytes[1]]))
+                .collect(),
+        ),
+        PixelFormat::CMYK32 => return Err("CMYK JPEG images aren't supported."),
+    };
+
+    let orientation = decoder
+        .exif_data()
+        .and_then(orientation::exif_orientation)
+        .unwrap_or_default();
+
+    Ok(RgbImage::from_samples(
+        u32::from(info.width),
+        u32::from(info.height),
+        channels,
+        bit_depth,
+        &samples,
+    )?
+    .oriented(orientation))
+}
@@ -3,21 +3,81 @@
 // License: GNU GPL Version 3 (https://www.gnu.org/licenses/gpl-3.0.en.html)
 
 extern crate byteorder;
+extern crate deflate;
+extern crate inflate;
+#[cfg(feature = "jpeg")]
+extern crate jpeg_decoder;
 extern crate png;
 
 #[macro_use]
 extern crate tiff_encoder;
 
-use std::{fmt, fs::File, path::Path};
+use std::{fmt, fs, fs::File, io::BufWriter, path::Path, str::FromStr};
 
-use byteorder::{LittleEndian, WriteBytesExt};
+use byteorder::{BigEndian, WriteBytesExt};
+use png::HasParameters;
 use tiff_encoder::ifd::tags;
-use tiff_encoder::ifd::types::BYTE;
-use tiff_encoder::prelude::*;
+
+use tiff::{Directory, Field};
+
+mod binning;
+mod bracketing;
+mod burst;
+mod camera;
+mod cfa;
+mod compression;
+mod demosaic;
+mod dng;
+mod exr;
+#[cfg(feature = "jpeg")]
+mod jpeg;
+mod linear;
+mod metrics;
+mod noise;
+mod odd_size;
+mod optics;
+mod orientation;
+mod pfm;
+#[cfg(feature = "ppm")]
+mod ppm;
+mod radiance;
+mod readout;
+mod region;
+mod sampling;
+mod temporal;
+mod tiff;
+mod window;
+
+pub use burst::{Burst, RawBurst};
+pub use camera::Camera;
+pub use cfa::{CfaColor, CfaPattern, FilterResponses, REC709_LUMA};
+pub use compression::{Compression, Predictor};
+pub use demosaic::DemosaicAlgorithm;
+pub use dng::{Endianness, SampleFormat};
+pub use linear::LinearImage;
+pub use metrics::{ChannelMetrics, Metrics};
+pub use noise::Noise;
+pub use odd_size::OddSizePolicy;
+pub use optics::OpticalFilter;
+pub use orientation::Orientation;
+pub use readout::{Combination, ReadoutMode};
+pub use region::Region;
+pub use sampling::Aperture;
 
 #[cfg(test)]
 mod tests;
 
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// Matrix written as ColorMatrix1 for red, green and blue planes, in units
+/// of 1/10000000.
+const COLOR_MATRIX: [[i32; 3]; 3] = [
+    [4124564, 3575761, 1804375],
+    [2126729, 7151522, 721750],
+    [193339, 1191920, 9503041],
+];
+
+#[derive(Clone, Copy)]
 enum BitDepth {
     One,
     Two,
@@ -27,7 +87,7 @@ enum BitDepth {
 }
 
 impl BitDepth {
-    pub fn to_u32(&self) -> u32 {
+    pub fn to_u32(self) -> u32 {
         match self {
             BitDepth::One => 1,
             BitDepth::Two => 2,
@@ -38,11 +98,14 @@ impl BitDepth {
     }
 }
 
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Copy)]
 enum ColorType {
     RGB,
     RGBA,
 }
 
+#[derive(Clone)]
 pub struct RgbImage {
     width: u32,
     height: u32,
@@ -52,10 +115,57 @@ pub struct RgbImage {
 }
 
 impl RgbImage {
+    /// Reads a PNG image, or a TIFF, PPM or JPEG image when built with the
+    /// feature of the same name, rotating and flipping it upright if it has
+    /// an orientation. Formats are told apart by their first bytes.
+    ///
+    /// Float images, such as OpenEXR ones, are exposed at 0 EV, clipping
+    /// whatever goes past white.
     pub fn from_file<P: AsRef<Path>>(path: P) -> Result<RgbImage, &'static str> {
-        let png_file = File::open(path).map_err(|_| "PNG image couldn't be opened.")?;
+        let data = fs::read(path).map_err(|_| "Image couldn't be opened.")?;
+        RgbImage::decode(&data)
+    }
+
+    /// Decodes an image of any supported format.
+    fn decode(data: &[u8]) -> Result<RgbImage, &'static str> {
+        if data.starts_with(PNG_SIGNATURE) {
+            let orientation = orientation::png_exif_orientation(data).unwrap_or_default();
+            return Ok(RgbImage::from_png(data)?.oriented(orientation));
+        }
+
+        #[cfg(feature = "tiff")]
+        {
+            if tiff::is_tiff(data) {
+                return tiff::read_tiff(data.to_vec());
+            }
+        }
+
+        #[cfg(feature = "ppm")]
+        {
+            if ppm::is_ppm(data) {
+                return ppm::read_ppm(data);
+            }
+        }
 
-        let decoder = png::Decoder::new(png_file);
+        #[cfg(feature = "jpeg")]
+        {
+            if jpeg::is_jpeg(data) {
+                return jpeg::read_jpeg(data);
+            }
+        }
+
+        if linear::is_float(data) {
+            return Ok(LinearImage::decode(data)?.exposed(0.0));
+        }
+
+        Err("This image's format isn't supported.")
+    }
+
+    /// Decodes the first frame of a PNG file, as stored.
+    fn from_png(png_file: &[u8]) -> Result<RgbImage, &'static str> {
+        // Keep 16-bit samples, which the decoder strips to 8 bits by default.
+        let mut decoder = png::Decoder::new(png_file);
+        decoder.set(png::Transformations::IDENTITY);
         let (info, mut reader) = decoder
             .read_info()
             .map_err(|_| "This PNG file appears to be corrupted.")?;
@@ -83,12 +193,93 @@ impl RgbImage {
         Ok(RgbImage {
             width: info.width,
             height: info.height,
-            color_type: color_type,
-            data: data,
-            bit_depth: bit_depth,
+            color_type,
+            data,
+            bit_depth,
+        })
+    }
+
+    /// Creates an 8 or 16-bit image from interleaved samples of 1 to 4
+    /// channels: gray, gray and alpha, RGB or RGBA. Gray is spread to red,
+    /// green and blue.
+    #[cfg(any(feature = "tiff", feature = "ppm", feature = "jpeg"))]
+    fn from_samples(
+        width: u32,
+        height: u32,
+        channels: usize,
+        bit_depth: BitDepth,
+        samples: &[u16],
+    ) -> Result<RgbImage, &'static str> {
+        if !(1..=4).contains(&channels) || samples.len() != (width * height) as usize * channels {
+            return Err("Image has fewer samples than it needs.");
+        }
+
+        let color_type = if channels % 2 == 0 {
+            ColorType::RGBA
+        } else {
+            ColorType::RGB
+        };
+        let mut data = Vec::with_capacity(samples.len() * 2);
+        let mut push = |sample: u16| match bit_depth {
+            BitDepth::Sixteen => data.extend_from_slice(&sample.to_be_bytes()),
+            _ => data.push(sample as u8),
+        };
+
+        for pixel in samples.chunks(channels) {
+            match channels {
+                1 | 2 => (0..3).for_each(|_| push(pixel[0])),
+                _ => pixel[..3].iter().for_each(|&sample| push(sample)),
+            }
+            if channels % 2 == 0 {
+                push(pixel[channels - 1]);
+            }
+        }
+
+        Ok(RgbImage {
+            width,
+            height,
+            data,
+            color_type,
+            bit_depth,
         })
     }
 
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Saves the image as a PNG file, keeping its color type and bit depth.
+    pub fn save_as_png<P: AsRef<Path>>(&self, path: P) -> Result<(), &'static str> {
+        let png_file = File::create(path).map_err(|_| "PNG image couldn't be created.")?;
+
+        let color_type = match self.color_type {
+            ColorType::RGB => png::ColorType::RGB,
+            ColorType::RGBA => png::ColorType::RGBA,
+        };
+
+        let bit_depth = match self.bit_depth {
+            BitDepth::One => png::BitDepth::One,
+            BitDepth::Two => png::BitDepth::Two,
+            BitDepth::Four => png::BitDepth::Four,
+            BitDepth::Eight => png::BitDepth::Eight,
+            BitDepth::Sixteen => png::BitDepth::Sixteen,
+        };
+
+        let mut encoder = png::Encoder::new(BufWriter::new(png_file), self.width, self.height);
+        encoder.set(color_type).set(bit_depth);
+
+        let mut writer = encoder
+            .write_header()
+            .map_err(|_| "PNG image couldn't be written.")?;
+        writer
+            .write_image_data(&self.data)
+            .map_err(|_| "PNG image couldn't be written.")
+    }
+
     fn even_width(&self) -> u32 {
         if self.width % 2 == 0 {
             self.width
@@ -109,63 +300,212 @@ impl RgbImage {
         self.even_width() * self.even_height()
     }
 
-    pub fn to_raw(self, bayer_pattern: BayerPattern) -> RawImage {
-        let width = self.width as usize;
-        let is_even = width % 2 == 0;
-        let color_offsets = bayer_pattern.color_offsets();
-        let shift = 16 - self.bit_depth.to_u32();
+    /// Keeps only the pixels inside the given region.
+    fn cropped(self, region: Region) -> RgbImage {
+        if region == Region::new(0, 0, self.width, self.height) {
+            return self;
+        }
 
-        let mut raw_data: Vec<u16> = vec![0; self.even_size() as usize];
-        let mut raw_index;
+        let bytes_per_pixel = self.channels() * self.bit_depth.to_u32() as usize / 8;
+        let row_bytes = self.width as usize * bytes_per_pixel;
+        let start = region.x as usize * bytes_per_pixel;
+        let end = start + region.width as usize * bytes_per_pixel;
+        let mut data = Vec::with_capacity(region.height as usize * (end - start));
+
+        for row in self
+            .data
+            .chunks(row_bytes)
+            .skip(region.y as usize)
+            .take(region.height as usize)
+        {
+            data.extend_from_slice(&row[start..end]);
+        }
 
-        let multiplier = match self.color_type {
+        RgbImage {
+            width: region.width,
+            height: region.height,
+            data,
+            color_type: self.color_type,
+            bit_depth: self.bit_depth,
+        }
+    }
+
+    pub fn to_raw<P: Into<CfaPattern>>(self, cfa_pattern: P) -> RawImage {
+        self.to_raw_with_responses(cfa_pattern, &FilterResponses::default())
+    }
+
+    /// Same as `to_raw`, synthesizing filter colors with the given responses.
+    pub fn to_raw_with_responses<P: Into<CfaPattern>>(
+        self,
+        cfa_pattern: P,
+        responses: &FilterResponses,
+    ) -> RawImage {
+        let cfa_pattern = cfa_pattern.into();
+        let width = self.even_width();
+        let height = self.even_height();
+
+        let mut raw_data: Vec<u16> = Vec::with_capacity(self.even_size() as usize);
+
+        for row in 0..height {
+            for column in 0..width {
+                let color = cfa_pattern.color_at(column, row);
+                raw_data.push(self.sample_color(column, row, color, responses));
+            }
+        }
+
+        RawImage::new(
+            width,
+            height,
+            raw_data,
+            SensorLayout::Cfa(cfa_pattern),
+            responses.clone(),
+        )
+    }
+
+    /// Takes a "picture" with a monochrome sensor, whose photosites see the
+    /// given weights of the red, green and blue channels.
+    pub fn to_monochrome(self, weights: [f32; 3]) -> RawImage {
+        let mut data = Vec::with_capacity((self.width * self.height) as usize);
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                data.push(self.sample_weighted(x, y, weights));
+            }
+        }
+
+        RawImage::new(
+            self.width,
+            self.height,
+            data,
+            SensorLayout::Monochrome,
+            FilterResponses::default().with_response(CfaColor::White, weights),
+        )
+    }
+
+    /// Keeps every channel of every pixel, as a reference of what a perfect
+    /// demosaicing algorithm would output.
+    pub fn to_linear_raw(self) -> RawImage {
+        let mut data = Vec::with_capacity((self.width * self.height * 3) as usize);
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                for channel in 0..3 {
+                    data.push(self.sample(x, y, channel));
+                }
+            }
+        }
+
+        RawImage::new(
+            self.width,
+            self.height,
+            data,
+            SensorLayout::LinearRaw,
+            FilterResponses::default(),
+        )
+    }
+
+    fn channels(&self) -> usize {
+        match self.color_type {
             ColorType::RGB => 3,
             ColorType::RGBA => 4,
-        } as usize;
-
-        for row in (0..self.even_height()).step_by(2) {
-            for column in (0..self.even_width()).step_by(2) {
-                let odd_offset = if is_even { 0 } else { row } as usize;
-
-                // Top Left.
-                raw_index = (row * self.even_width() + column) as usize;
-                raw_data[raw_index] = (self.data
-                    [((raw_index + odd_offset) * multiplier + color_offsets[0] as usize)]
-                    as u16)
-                    << shift;
-
-                // Top Right.
-                raw_index += 1;
-                raw_data[raw_index] = (self.data
-                    [((raw_index + odd_offset) * multiplier + color_offsets[1] as usize)]
-                    as u16)
-                    << shift;
-
-                // Bottom Right.
-                raw_index += self.even_width() as usize;
-                raw_data[raw_index] = (self.data
-                    [((raw_index + odd_offset) * multiplier + color_offsets[3] as usize)]
-                    as u16)
-                    << shift;
-
-                // Bottom Left.
-                raw_index -= 1;
-                raw_data[raw_index] = (self.data
-                    [((raw_index + odd_offset) * multiplier + color_offsets[2] as usize)]
-                    as u16)
-                    << shift;
+        }
+    }
+
+    /// Returns the value of a channel at the given pixel, scaled to 16 bits.
+    fn sample(&self, x: u32, y: u32, channel: usize) -> u16 {
+        let index = (y * self.width + x) as usize * self.channels() + channel;
+
+        match self.bit_depth {
+            BitDepth::Sixteen => {
+                (self.data[index * 2] as u16) << 8 | self.data[index * 2 + 1] as u16
             }
+            _ => (self.data[index] as u16) << (16 - self.bit_depth.to_u32()),
         }
+    }
 
-        RawImage {
-            width: self.even_width(),
-            height: self.even_height(),
-            data: raw_data,
-            bayer_pattern: bayer_pattern,
+    /// Returns the value seen through a filter of the given color at the given
+    /// pixel, scaled to 16 bits.
+    fn sample_color(&self, x: u32, y: u32, color: CfaColor, responses: &FilterResponses) -> u16 {
+        self.sample_weighted(x, y, responses.response(color))
+    }
+
+    /// Returns the weighted sum of the channels at the given pixel, scaled to
+    /// 16 bits and clipped at the white level.
+    fn sample_weighted(&self, x: u32, y: u32, weights: [f32; 3]) -> u16 {
+        let value: f32 = (0..3)
+            .filter(|&channel| weights[channel] != 0.0)
+            .map(|channel| weights[channel] * f32::from(self.sample(x, y, channel)))
+            .sum();
+
+        value.round().clamp(0.0, 65535.0) as u16
+    }
+
+    /// Decodes the sRGB transfer curve, returning a 16-bit image of linear
+    /// light. Captures of sRGB images should be taken from their linear light.
+    pub fn linearize_srgb(&self) -> RgbImage {
+        let pixels: Vec<[f32; 3]> = self
+            .to_float()
+            .iter()
+            .map(|pixel| {
+                [
+                    decode_srgb(pixel[0]),
+                    decode_srgb(pixel[1]),
+                    decode_srgb(pixel[2]),
+                ]
+            })
+            .collect();
+
+        RgbImage::from_float(self.width, self.height, &pixels)
+    }
+
+    /// Returns the RGB channels of every pixel, normalized to [0, 1].
+    fn to_float(&self) -> Vec<[f32; 3]> {
+        let mut pixels = Vec::with_capacity((self.width * self.height) as usize);
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                pixels.push([
+                    self.sample(x, y, 0) as f32 / 65535.0,
+                    self.sample(x, y, 1) as f32 / 65535.0,
+                    self.sample(x, y, 2) as f32 / 65535.0,
+                ]);
+            }
         }
+
+        pixels
+    }
+
+    /// Builds a 16-bit RGB image from normalized pixels, clipping values outside [0, 1].
+    fn from_float(width: u32, height: u32, pixels: &[[f32; 3]]) -> RgbImage {
+        let mut data = Vec::with_capacity(pixels.len() * 6);
+
+        for pixel in pixels {
+            for &value in pixel {
+                data.write_u16::<BigEndian>((value.clamp(0.0, 1.0) * 65535.0).round() as u16)
+                    .unwrap();
+            }
+        }
+
+        RgbImage {
+            width,
+            height,
+            data,
+            color_type: ColorType::RGB,
+            bit_depth: BitDepth::Sixteen,
+        }
+    }
+}
+
+/// Converts an sRGB-encoded value, from 0 to 1, to linear light.
+pub(crate) fn decode_srgb(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum BayerPattern {
     RGGB,
     BGGR,
@@ -187,6 +527,7 @@ impl fmt::Display for BayerPattern {
     }
 }
 impl BayerPattern {
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(bayer_pattern: &str) -> BayerPattern {
         match bayer_pattern.to_uppercase().trim() {
             "RGGB" => BayerPattern::RGGB,
@@ -197,6 +538,33 @@ impl BayerPattern {
         }
     }
 
+    /// Returns the Bayer pattern seen by a readout window whose origin lies
+    /// at (`x`, `y`) on the full sensor. Odd offsets swap the columns or the
+    /// rows of the pattern.
+    pub fn shifted(&self, x: u32, y: u32) -> BayerPattern {
+        let mut bayer_pattern = *self;
+
+        if x % 2 == 1 {
+            bayer_pattern = match bayer_pattern {
+                BayerPattern::RGGB => BayerPattern::GRBG,
+                BayerPattern::BGGR => BayerPattern::GBRG,
+                BayerPattern::GRBG => BayerPattern::RGGB,
+                BayerPattern::GBRG => BayerPattern::BGGR,
+            };
+        }
+
+        if y % 2 == 1 {
+            bayer_pattern = match bayer_pattern {
+                BayerPattern::RGGB => BayerPattern::GBRG,
+                BayerPattern::BGGR => BayerPattern::GRBG,
+                BayerPattern::GRBG => BayerPattern::BGGR,
+                BayerPattern::GBRG => BayerPattern::RGGB,
+            };
+        }
+
+        bayer_pattern
+    }
+
     fn color_offsets(&self) -> Vec<u8> {
         match self {
             BayerPattern::RGGB => vec![0, 1, 1, 2],
@@ -207,67 +575,386 @@ impl BayerPattern {
     }
 }
 
+/// How the photosites of a sensor sample color.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SensorLayout {
+    /// One sample per photosite, through a color filter array.
+    Cfa(CfaPattern),
+    /// One luminance sample per photosite, without any color filter.
+    Monochrome,
+    /// Red, green and blue samples at every photosite, as if perfectly
+    /// demosaiced.
+    LinearRaw,
+}
+impl fmt::Display for SensorLayout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SensorLayout::Cfa(cfa_pattern) => cfa_pattern.fmt(f),
+            SensorLayout::Monochrome => write!(f, "Monochrome"),
+            SensorLayout::LinearRaw => write!(f, "LinearRaw"),
+        }
+    }
+}
+impl<P: Into<CfaPattern>> From<P> for SensorLayout {
+    fn from(cfa_pattern: P) -> SensorLayout {
+        SensorLayout::Cfa(cfa_pattern.into())
+    }
+}
+impl FromStr for SensorLayout {
+    type Err = &'static str;
+
+    fn from_str(sensor_layout: &str) -> Result<SensorLayout, &'static str> {
+        match sensor_layout.trim().to_uppercase().as_str() {
+            "MONO" | "MONOCHROME" => Ok(SensorLayout::Monochrome),
+            "LINEAR" | "LINEARRAW" => Ok(SensorLayout::LinearRaw),
+            _ => Ok(SensorLayout::Cfa(sensor_layout.parse()?)),
+        }
+    }
+}
+impl SensorLayout {
+    pub fn samples_per_pixel(&self) -> u32 {
+        match self {
+            SensorLayout::LinearRaw => 3,
+            _ => 1,
+        }
+    }
+
+    fn plane_colors(&self) -> Vec<CfaColor> {
+        match self {
+            SensorLayout::Cfa(cfa_pattern) => cfa_pattern.plane_colors(),
+            SensorLayout::Monochrome => Vec::new(),
+            SensorLayout::LinearRaw => vec![CfaColor::Red, CfaColor::Green, CfaColor::Blue],
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct RawImage {
     width: u32,
     height: u32,
     data: Vec<u16>,
-    bayer_pattern: BayerPattern,
+    layout: SensorLayout,
+    filter_responses: FilterResponses,
+    black_level: u16,
+    white_level: u16,
+    default_crop: Region,
+    orientation: Orientation,
+    exposure_time: Option<f64>,
+    exposure_bias: Option<f64>,
+    sample_format: SampleFormat,
+    compression: Compression,
+    endianness: Endianness,
+    big_tiff: bool,
 }
 
 impl RawImage {
-    pub fn save_as_dng<P: AsRef<Path>>(&self, file_path: P) {
-        // Image bytes
-        let mut image_bytes = Vec::new();
+    /// Creates an uncompressed, full-range image with default metadata.
+    pub(crate) fn new(
+        width: u32,
+        height: u32,
+        data: Vec<u16>,
+        layout: SensorLayout,
+        filter_responses: FilterResponses,
+    ) -> RawImage {
+        RawImage {
+            width,
+            height,
+            data,
+            layout,
+            filter_responses,
+            black_level: 0,
+            white_level: u16::MAX,
+            default_crop: Region::new(0, 0, width, height),
+            orientation: Orientation::Normal,
+            exposure_time: None,
+            exposure_bias: None,
+            sample_format: SampleFormat::Integer,
+            compression: Compression::Uncompressed,
+            endianness: Endianness::Little,
+            big_tiff: false,
+        }
+    }
 
-        for &val in self.data.iter() {
-            image_bytes.write_u16::<LittleEndian>(val).unwrap();
+    /// Creates an image holding the given samples, with the metadata of this
+    /// one. Callers that change the geometry must also set `default_crop`.
+    pub(crate) fn with_data(&self, width: u32, height: u32, data: Vec<u16>) -> RawImage {
+        RawImage {
+            width,
+            height,
+            data,
+            layout: self.layout.clone(),
+            filter_responses: self.filter_responses.clone(),
+            ..*self
         }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Returns the sample value of a photosite that received no light.
+    pub fn black_level(&self) -> u16 {
+        self.black_level
+    }
+
+    /// Returns the sample value of a saturated photosite.
+    pub fn white_level(&self) -> u16 {
+        self.white_level
+    }
+
+    /// Returns the region of the raw data that holds the captured image.
+    pub fn default_crop(&self) -> Region {
+        self.default_crop
+    }
+
+    /// Returns how long the sensor was exposed, in seconds, if known.
+    pub fn exposure_time(&self) -> Option<f64> {
+        self.exposure_time
+    }
+
+    /// Returns the exposure compensation of the capture, in EV, if known.
+    pub fn exposure_bias(&self) -> Option<f64> {
+        self.exposure_bias
+    }
+
+    /// Returns the same raw data, recorded as exposed for the given time in
+    /// seconds.
+    pub fn with_exposure_time(mut self, exposure_time: f64) -> RawImage {
+        self.exposure_time = Some(exposure_time);
+        self
+    }
+
+    /// Returns the same raw data, recorded as exposed the given number of
+    /// stops away from the metered exposure.
+    pub fn with_exposure_bias(mut self, exposure_bias: f64) -> RawImage {
+        self.exposure_bias = Some(exposure_bias);
+        self
+    }
+
+    /// Returns how samples are stored when saved as DNG.
+    pub fn sample_format(&self) -> SampleFormat {
+        self.sample_format
+    }
+
+    /// Stores samples in the given format when saved as DNG. Float samples
+    /// are scaled to a black level of 0 and a white level of 1, keeping the
+    /// values past the white level.
+    pub fn with_sample_format(mut self, sample_format: SampleFormat) -> RawImage {
+        self.sample_format = sample_format;
+        self
+    }
+
+    /// Returns the byte order of the DNG files saved from this image.
+    pub fn endianness(&self) -> Endianness {
+        self.endianness
+    }
+
+    /// Saves DNG files in the given byte order.
+    pub fn with_endianness(mut self, endianness: Endianness) -> RawImage {
+        self.endianness = endianness;
+        self
+    }
+
+    /// Returns whether DNG files are saved as BigTIFF.
+    pub fn is_big_tiff(&self) -> bool {
+        self.big_tiff
+    }
+
+    /// Saves DNG files as BigTIFF, whose 8-byte offsets allow raw data over
+    /// 4 GB, which classic TIFF files can't hold.
+    pub fn with_big_tiff(mut self) -> RawImage {
+        self.big_tiff = true;
+        self
+    }
+
+    pub fn layout(&self) -> &SensorLayout {
+        &self.layout
+    }
+
+    pub fn cfa_pattern(&self) -> Option<&CfaPattern> {
+        match self.layout {
+            SensorLayout::Cfa(ref cfa_pattern) => Some(cfa_pattern),
+            _ => None,
+        }
+    }
+
+    /// Saves the image as a DNG file, in its byte order and as BigTIFF if
+    /// set. Classic TIFF files can't hold more than 4 GB of raw data.
+    pub fn save_as_dng<P: AsRef<Path>>(&self, file_path: P) -> Result<(), &'static str> {
+        self.dng_ifd()
+            .write_to(file_path, self.endianness == Endianness::Big, self.big_tiff)
+    }
+
+    /// Builds the IFD of a DNG file holding this raw image.
+    fn dng_ifd(&self) -> Directory {
+        let image_bytes = self.image_bytes();
+
+        // Float samples are scaled to their default levels.
+        let (black_level, white_level) = if self.sample_format.is_float() {
+            (0, 1)
+        } else {
+            (self.black_level, self.white_level)
+        };
+        let bits_per_sample = self.sample_format.bits_per_sample();
 
-        const TAG_CFAREPEARPATTERNDIM: u16 = 0x828D;
-        const TAG_CFAPATTERN2: u16 = 0x828E;
-        const TAG_DNGVERSION: u16 = 0xC612;
         const TAG_COLORMATRIX1: u16 = 0xC621;
         const TAG_ASSHOTNEUTRAL: u16 = 0xC628;
         const TAG_ASSHOTWHITEXY: u16 = 0xC629;
 
-        TiffFile::new(
-            Ifd::new()
-                .with_entry(tags::PhotometricInterpretation, SHORT![32803])
-                .with_entry(tags::NewSubfileType, LONG![0])
-                .with_entry(tags::ImageWidth, LONG![self.width])
-                .with_entry(tags::ImageLength, LONG![self.height])
-                .with_entry(tags::BitsPerSample, SHORT![16])
-                .with_entry(tags::Compression, SHORT![1])
-                .with_entry(tags::Orientation, SHORT![1])
-                .with_entry(tags::SamplesPerPixel, SHORT![1])
-                .with_entry(tags::RowsPerStrip, LONG![self.height])
-                .with_entry(tags::StripByteCounts, LONG![self.width * self.height * 2])
-                .with_entry(TAG_CFAREPEARPATTERNDIM, SHORT![2, 2])
+        let samples_per_pixel = self.layout.samples_per_pixel();
+        let plane_colors = self.layout.plane_colors();
+
+        // The color matrix maps XYZ to each plane, which sees a mix of the
+        // input's channels.
+        let mut color_matrix = Vec::with_capacity(plane_colors.len() * 3);
+        let mut neutral = Vec::with_capacity(plane_colors.len());
+        for &color in &plane_colors {
+            let weights = self.filter_responses.response(color);
+
+            color_matrix.extend((0..3).map(|column| {
+                let value: f32 = (0..3)
+                    .map(|channel| weights[channel] * COLOR_MATRIX[channel][column] as f32)
+                    .sum();
+                (value.round() as i32, 10000000)
+            }));
+
+            neutral.push(weights.iter().sum::<f32>());
+        }
+
+        let max_neutral = neutral.iter().cloned().fold(0.0, f32::max);
+        let neutral: Vec<(i32, i32)> = neutral
+            .iter()
+            .map(|value| ((value / max_neutral * 1000000.0).round() as i32, 1000000))
+            .map(|(numerator, denominator)| {
+                if numerator == denominator {
+                    (1, 1)
+                } else {
+                    (numerator, denominator)
+                }
+            })
+            .collect();
+
+        let mut ifd = Directory::new(image_bytes)
+            .with_entry(
+                tags::PhotometricInterpretation,
+                Field::Shorts(vec![match self.layout {
+                    SensorLayout::Cfa(_) => 32803,
+                    SensorLayout::Monochrome => 1,
+                    SensorLayout::LinearRaw => 34892,
+                }]),
+            )
+            .with_entry(tags::NewSubfileType, Field::Longs(vec![0]))
+            .with_entry(tags::ImageWidth, Field::Longs(vec![self.width]))
+            .with_entry(tags::ImageLength, Field::Longs(vec![self.height]))
+            .with_entry(
+                tags::BitsPerSample,
+                Field::Shorts(vec![bits_per_sample; samples_per_pixel as usize]),
+            )
+            .with_entry(
+                tags::Compression,
+                Field::Shorts(vec![match self.compression {
+                    Compression::Uncompressed => compression::COMPRESSION_NONE,
+                    Compression::Deflate(_) => compression::COMPRESSION_DEFLATE,
+                }]),
+            )
+            .with_entry(
+                tags::Orientation,
+                Field::Shorts(vec![self.orientation.tag()]),
+            )
+            .with_entry(
+                tags::SamplesPerPixel,
+                Field::Shorts(vec![samples_per_pixel as u16]),
+            )
+            .with_entry(tags::RowsPerStrip, Field::Longs(vec![self.height]))
+            .with_entry(dng::TAG_DNG_VERSION, Field::Bytes(vec![1, 4, 0, 0]))
+            .with_entry(
+                dng::TAG_BLACK_LEVEL,
+                Field::Shorts(vec![black_level; samples_per_pixel as usize]),
+            )
+            .with_entry(
+                dng::TAG_WHITE_LEVEL,
+                Field::Shorts(vec![white_level; samples_per_pixel as usize]),
+            )
+            .with_entry(
+                dng::TAG_DEFAULT_CROP_ORIGIN,
+                Field::Longs(vec![self.default_crop.x, self.default_crop.y]),
+            )
+            .with_entry(
+                dng::TAG_DEFAULT_CROP_SIZE,
+                Field::Longs(vec![self.default_crop.width, self.default_crop.height]),
+            )
+            .with_entry(
+                dng::TAG_ACTIVE_AREA,
+                Field::Longs(vec![0, 0, self.height, self.width]),
+            );
+
+        if let SensorLayout::Cfa(ref cfa_pattern) = self.layout {
+            let (cfa_rows, cfa_columns) = cfa_pattern.repeat_dim();
+
+            ifd = ifd
                 .with_entry(
-                    TAG_CFAPATTERN2,
-                    BYTE::values(self.bayer_pattern.color_offsets()),
+                    dng::TAG_CFA_REPEAT_PATTERN_DIM,
+                    Field::Shorts(vec![cfa_rows as u16, cfa_columns as u16]),
                 )
-                .with_entry(TAG_DNGVERSION, BYTE![1, 4, 0, 0])
                 .with_entry(
-                    TAG_COLORMATRIX1,
-                    SRATIONAL![
-                        (4124564, 10000000),
-                        (3575761, 10000000),
-                        (1804375, 10000000),
-                        (2126729, 10000000),
-                        (7151522, 10000000),
-                        (0721750, 10000000),
-                        (0193339, 10000000),
-                        (1191920, 10000000),
-                        (9503041, 10000000)
-                    ],
+                    dng::TAG_CFA_PATTERN,
+                    Field::Bytes(cfa_pattern.plane_indices()),
                 )
-                .with_entry(TAG_ASSHOTNEUTRAL, SRATIONAL![(1, 1), (1, 1), (1, 1)])
-                .with_entry(TAG_ASSHOTWHITEXY, SRATIONAL![(1, 1), (1, 1)])
-                .with_entry(tags::StripOffsets, ByteBlock::single(image_bytes))
-                .single(),
-        )
-        .write_to(file_path)
-        .unwrap();
+                .with_entry(
+                    dng::TAG_CFA_PLANE_COLOR,
+                    Field::Bytes(
+                        plane_colors
+                            .iter()
+                            .map(CfaColor::plane_color)
+                            .collect::<Vec<u8>>(),
+                    ),
+                )
+                .with_entry(dng::TAG_CFA_LAYOUT, Field::Shorts(vec![1]));
+        }
+
+        if self.sample_format.is_float() {
+            ifd = ifd.with_entry(
+                tags::SampleFormat,
+                Field::Shorts(vec![3; samples_per_pixel as usize]),
+            );
+        }
+
+        if let Compression::Deflate(predictor) = self.compression {
+            if predictor != Predictor::None {
+                ifd = ifd.with_entry(
+                    tags::Predictor,
+                    Field::Shorts(vec![predictor.tag(self.sample_format.is_float())]),
+                );
+            }
+        }
+
+        if let Some(exposure_time) = self.exposure_time {
+            let (numerator, denominator) = tiff::rational(exposure_time);
+            ifd = ifd.with_entry(
+                dng::TAG_EXPOSURE_TIME,
+                Field::Rationals(vec![(numerator.max(0) as u32, denominator as u32)]),
+            );
+        }
+
+        if let Some(exposure_bias) = self.exposure_bias {
+            ifd = ifd.with_entry(
+                dng::TAG_EXPOSURE_BIAS_VALUE,
+                Field::SRationals(vec![tiff::rational(exposure_bias)]),
+            );
+        }
+
+        // Monochrome images have no color planes to describe.
+        if !plane_colors.is_empty() {
+            ifd = ifd
+                .with_entry(TAG_COLORMATRIX1, Field::SRationals(color_matrix))
+                .with_entry(TAG_ASSHOTNEUTRAL, Field::SRationals(neutral))
+                .with_entry(TAG_ASSHOTWHITEXY, Field::SRationals(vec![(1, 1), (1, 1)]));
+        }
+
+        ifd
     }
 }
@@ -0,0 +1,141 @@
+// Author: Cláudio Gomes (TofuLynx)
+// Project: emubayer
+// License: GNU GPL Version 3 (https://www.gnu.org/licenses/gpl-3.0.en.html)
+
+use std::{fs, fs::File, io::Read, path::Path};
+
+use super::RgbImage;
+use exr;
+use pfm;
+use radiance;
+
+/// Linear light of a scene, in units of the white level of a capture at
+/// 0 EV. Values above 1 are highlights that only shorter exposures keep.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LinearImage {
+    width: u32,
+    height: u32,
+    pixels: Vec<[f32; 3]>,
+}
+
+impl LinearImage {
+    /// Creates an image from its red, green and blue values, row by row.
+    pub fn new(
+        width: u32,
+        height: u32,
+        pixels: Vec<[f32; 3]>,
+    ) -> Result<LinearImage, &'static str> {
+        if width == 0
+            || height == 0
+            || (width as usize).checked_mul(height as usize) != Some(pixels.len())
+        {
+            return Err("Linear image must have one pixel per position of a non-empty image.");
+        }
+
+        if pixels.iter().flatten().any(|value| !value.is_finite()) {
+            return Err("Linear image values must be finite numbers.");
+        }
+
+        Ok(LinearImage {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    /// Reads an OpenEXR, Radiance HDR or PFM image, told apart by their
+    /// first bytes, whose values go past white. Other images are read as
+    /// `RgbImage::from_file` does, taking their samples as linear light.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<LinearImage, &'static str> {
+        let data = fs::read(path).map_err(|_| "Image couldn't be opened.")?;
+
+        if is_float(&data) {
+            LinearImage::decode(&data)
+        } else {
+            RgbImage::decode(&data).map(LinearImage::from)
+        }
+    }
+
+    /// Returns whether the file holds a float image, judging by its first
+    /// bytes.
+    pub fn is_float_file<P: AsRef<Path>>(path: P) -> bool {
+        let mut magic = Vec::with_capacity(4);
+        File::open(path)
+            .and_then(|file| file.take(4).read_to_end(&mut magic))
+            .is_ok()
+            && is_float(&magic)
+    }
+
+    /// Decodes a float image of any supported format.
+    pub(crate) fn decode(data: &[u8]) -> Result<LinearImage, &'static str> {
+        if exr::is_exr(data) {
+            exr::read_exr(data)
+        } else if radiance::is_radiance(data) {
+            radiance::read_radiance(data)
+        } else if pfm::is_pfm(data) {
+            pfm::read_pfm(data)
+        } else {
+            Err("This file isn't an OpenEXR, Radiance HDR or PFM image.")
+        }
+    }
+
+    /// Same as `new`, for decoded values, replacing NaNs with 0 and
+    /// infinities with the largest finite values.
+    pub(crate) fn sanitized(
+        width: u32,
+        height: u32,
+        mut pixels: Vec<[f32; 3]>,
+    ) -> Result<LinearImage, &'static str> {
+        for value in pixels.iter_mut().flatten() {
+            if value.is_nan() {
+                *value = 0.0;
+            } else {
+                *value = value.clamp(f32::MIN, f32::MAX);
+            }
+        }
+
+        LinearImage::new(width, height, pixels)
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn pixels(&self) -> &[[f32; 3]] {
+        &self.pixels
+    }
+
+    /// Returns the light reaching the sensor when exposed `ev` stops above
+    /// the scene's own exposure, as a 16-bit image clipped at white.
+    pub fn exposed(&self, ev: f64) -> RgbImage {
+        let gain = 2f64.powf(ev) as f32;
+        let pixels: Vec<[f32; 3]> = self
+            .pixels
+            .iter()
+            .map(|pixel| [pixel[0] * gain, pixel[1] * gain, pixel[2] * gain])
+            .collect();
+
+        RgbImage::from_float(self.width, self.height, &pixels)
+    }
+}
+
+impl From<RgbImage> for LinearImage {
+    /// Takes the samples of an image as linear light, with white at 1.
+    fn from(rgb_image: RgbImage) -> LinearImage {
+        LinearImage {
+            width: rgb_image.width,
+            height: rgb_image.height,
+            pixels: rgb_image.to_float(),
+        }
+    }
+}
+
+/// Returns whether the data starts like an OpenEXR, Radiance HDR or PFM
+/// image.
+pub(crate) fn is_float(data: &[u8]) -> bool {
+    exr::is_exr(data) || radiance::is_radiance(data) || pfm::is_pfm(data)
+}
@@ -0,0 +1,343 @@
+// Author: Cláudio Gomes (TofuLynx)
+// Project: emubayer
+// License: GNU GPL Version 3 (https://www.gnu.org/licenses/gpl-3.0.en.html)
+
+use std::{f64::consts::PI, fmt::Display};
+
+use super::{decode_srgb, Region, RgbImage};
+
+/// Color difference, in CIEDE2000 units, above which a change is noticeable.
+const JUST_NOTICEABLE_DIFFERENCE: f64 = 2.3;
+
+/// Side, in pixels, of the windows over which SSIM is computed.
+const SSIM_WINDOW: u32 = 7;
+
+/// Metrics of a single color channel.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChannelMetrics {
+    /// Peak signal-to-noise ratio, in dB. Infinite for identical channels.
+    pub psnr: f64,
+    /// Structural similarity, from -1 to 1.
+    pub ssim: f64,
+}
+
+/// Differences between an original image and its reconstruction, over a region.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Metrics {
+    pub region: Region,
+    /// Red, green and blue channel metrics.
+    pub channels: [ChannelMetrics; 3],
+    /// PSNR over all three channels.
+    pub psnr: f64,
+    /// Mean SSIM of the three channels.
+    pub ssim: f64,
+    pub ciede2000_mean: f64,
+    pub ciede2000_max: f64,
+    /// Fraction of pixels whose difference to their most similar neighbour
+    /// grew noticeably, as in the zipper effect measure of Lu and Tan.
+    pub zipper: f64,
+    /// Fraction of pixels whose chroma shifted noticeably.
+    pub false_color: f64,
+}
+
+impl RgbImage {
+    /// Compares a reconstruction of this image, such as a demosaiced one,
+    /// against it over a region.
+    ///
+    /// PSNR and SSIM are computed on the stored values, while color
+    /// differences treat both images as sRGB.
+    pub fn compare(
+        &self,
+        reconstructed: &RgbImage,
+        region: Region,
+    ) -> Result<Metrics, &'static str> {
+        if (self.width, self.height) != (reconstructed.width, reconstructed.height) {
+            return Err("Images to compare must have the same size.");
+        }
+
+        if !region.lies_within(self.width, self.height) {
+            return Err("Region must be a non-empty part of the images.");
+        }
+
+        let original = self.to_float();
+        let reconstructed = reconstructed.to_float();
+        let index = |x: u32, y: u32| (y * self.width + x) as usize;
+
+        let mut channels = [ChannelMetrics {
+            psnr: 0.0,
+            ssim: 0.0,
+        }; 3];
+        let mut total_squared_error = 0.0;
+
+        for (channel, metrics) in channels.iter_mut().enumerate() {
+            let squared_error: f64 = region
+                .pixels()
+                .map(|(x, y)| {
+                    let i = index(x, y);
+                    (f64::from(original[i][channel]) - f64::from(reconstructed[i][channel])).powi(2)
+                })
+                .sum();
+            total_squared_error += squared_error;
+
+            metrics.psnr = psnr(squared_error / f64::from(region.width * region.height));
+            metrics.ssim = ssim(region, |x, y| {
+                let i = index(x, y);
+                (
+                    f64::from(original[i][channel]),
+                    f64::from(reconstructed[i][channel]),
+                )
+            });
+        }
+
+        let original_lab: Vec<[f64; 3]> = original.iter().map(srgb_to_lab).collect();
+        let reconstructed_lab: Vec<[f64; 3]> = reconstructed.iter().map(srgb_to_lab).collect();
+
+        let (mut ciede2000_sum, mut ciede2000_max) = (0.0, 0.0f64);
+        let (mut zipper_pixels, mut false_color_pixels) = (0, 0);
+
+        for (x, y) in region.pixels() {
+            let i = index(x, y);
+
+            let difference = ciede2000(&original_lab[i], &reconstructed_lab[i]);
+            ciede2000_sum += difference;
+            ciede2000_max = ciede2000_max.max(difference);
+
+            let chroma_shift = ((original_lab[i][1] - reconstructed_lab[i][1]).powi(2)
+                + (original_lab[i][2] - reconstructed_lab[i][2]).powi(2))
+            .sqrt();
+            if chroma_shift > JUST_NOTICEABLE_DIFFERENCE {
+                false_color_pixels += 1;
+            }
+
+            // The most similar neighbour in the original should stay similar.
+            let neighbours = [
+                (-1, 0),
+                (1, 0),
+                (0, -1),
+                (0, 1),
+                (-1, -1),
+                (1, -1),
+                (-1, 1),
+                (1, 1),
+            ];
+            let closest = neighbours
+                .iter()
+                .map(|&(dx, dy)| (x as i64 + dx, y as i64 + dy))
+                .filter(|&(nx, ny)| {
+                    nx >= region.x as i64
+                        && ny >= region.y as i64
+                        && nx < (region.x + region.width) as i64
+                        && ny < (region.y + region.height) as i64
+                })
+                .map(|(nx, ny)| index(nx as u32, ny as u32))
+                .map(|n| (n, distance(&original_lab[i], &original_lab[n])))
+                .fold(
+                    None,
+                    |closest: Option<(usize, f64)>, (n, d)| match closest {
+                        Some((_, closest_d)) if closest_d <= d => closest,
+                        _ => Some((n, d)),
+                    },
+                );
+
+            if let Some((n, original_distance)) = closest {
+                let reconstructed_distance = distance(&reconstructed_lab[i], &reconstructed_lab[n]);
+                if reconstructed_distance - original_distance > JUST_NOTICEABLE_DIFFERENCE {
+                    zipper_pixels += 1;
+                }
+            }
+        }
+
+        let area = f64::from(region.width * region.height);
+
+        Ok(Metrics {
+            region,
+            channels,
+            psnr: psnr(total_squared_error / (3.0 * area)),
+            ssim: channels.iter().map(|channel| channel.ssim).sum::<f64>() / 3.0,
+            ciede2000_mean: ciede2000_sum / area,
+            ciede2000_max,
+            zipper: f64::from(zipper_pixels) / area,
+            false_color: f64::from(false_color_pixels) / area,
+        })
+    }
+}
+
+impl Metrics {
+    /// Formats the metrics as a JSON object. Infinite PSNRs become null.
+    pub fn to_json(&self) -> String {
+        let channels: Vec<String> = ["red", "green", "blue"]
+            .iter()
+            .zip(self.channels.iter())
+            .map(|(name, channel)| {
+                format!(
+                    "\"{}\":{{\"psnr\":{},\"ssim\":{}}}",
+                    name,
+                    json_number(channel.psnr),
+                    json_number(channel.ssim)
+                )
+            })
+            .collect();
+
+        format!(
+            "{{\"region\":{{\"x\":{},\"y\":{},\"width\":{},\"height\":{}}},\"psnr\":{},\"ssim\":{},\"channels\":{{{}}},\"ciede2000\":{{\"mean\":{},\"max\":{}}},\"zipper\":{},\"false_color\":{}}}",
+            self.region.x,
+            self.region.y,
+            self.region.width,
+            self.region.height,
+            json_number(self.psnr),
+            json_number(self.ssim),
+            channels.join(","),
+            json_number(self.ciede2000_mean),
+            json_number(self.ciede2000_max),
+            json_number(self.zipper),
+            json_number(self.false_color)
+        )
+    }
+}
+
+fn psnr(mean_squared_error: f64) -> f64 {
+    if mean_squared_error == 0.0 {
+        f64::INFINITY
+    } else {
+        -10.0 * mean_squared_error.log10()
+    }
+}
+
+/// Mean SSIM over every window that fits in the region, for values in [0, 1].
+fn ssim<F: Fn(u32, u32) -> (f64, f64)>(region: Region, values: F) -> f64 {
+    const C1: f64 = 0.01 * 0.01;
+    const C2: f64 = 0.03 * 0.03;
+
+    let window = SSIM_WINDOW.min(region.width).min(region.height);
+    let size = f64::from(window * window);
+
+    let (mut sum, mut windows) = (0.0, 0);
+    for y in region.y..=region.y + region.height - window {
+        for x in region.x..=region.x + region.width - window {
+            let (mut a_sum, mut b_sum, mut aa_sum, mut bb_sum, mut ab_sum) =
+                (0.0, 0.0, 0.0, 0.0, 0.0);
+
+            for (wx, wy) in Region::new(x, y, window, window).pixels() {
+                let (a, b) = values(wx, wy);
+                a_sum += a;
+                b_sum += b;
+                aa_sum += a * a;
+                bb_sum += b * b;
+                ab_sum += a * b;
+            }
+
+            let (a_mean, b_mean) = (a_sum / size, b_sum / size);
+            let a_variance = aa_sum / size - a_mean * a_mean;
+            let b_variance = bb_sum / size - b_mean * b_mean;
+            let covariance = ab_sum / size - a_mean * b_mean;
+
+            sum += (2.0 * a_mean * b_mean + C1) * (2.0 * covariance + C2)
+                / ((a_mean * a_mean + b_mean * b_mean + C1) * (a_variance + b_variance + C2));
+            windows += 1;
+        }
+    }
+
+    sum / f64::from(windows)
+}
+
+fn distance(a: &[f64; 3], b: &[f64; 3]) -> f64 {
+    ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)).sqrt()
+}
+
+fn srgb_to_lab(pixel: &[f32; 3]) -> [f64; 3] {
+    let linear = |value: f32| f64::from(decode_srgb(value));
+
+    linear_to_lab([linear(pixel[0]), linear(pixel[1]), linear(pixel[2])])
+}
+
+/// Converts linear sRGB, from 0 to 1, to CIELAB under a D65 white point.
+pub(crate) fn linear_to_lab(rgb: [f64; 3]) -> [f64; 3] {
+    let [r, g, b] = rgb;
+
+    let x = (0.412_456_4 * r + 0.357_576_1 * g + 0.180_437_5 * b) / 0.950_47;
+    let y = 0.212_672_9 * r + 0.715_152_2 * g + 0.072_175 * b;
+    let z = (0.019_333_9 * r + 0.119_192 * g + 0.950_304_1 * b) / 1.088_83;
+
+    let f = |t: f64| {
+        if t > 216.0 / 24389.0 {
+            t.cbrt()
+        } else {
+            (24389.0 / 27.0 * t + 16.0) / 116.0
+        }
+    };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+/// CIEDE2000 color difference, as given by Sharma, Wu and Dalal.
+pub(crate) fn ciede2000(lab1: &[f64; 3], lab2: &[f64; 3]) -> f64 {
+    let [l1, a1, b1] = *lab1;
+    let [l2, a2, b2] = *lab2;
+
+    let c_mean = ((a1 * a1 + b1 * b1).sqrt() + (a2 * a2 + b2 * b2).sqrt()) / 2.0;
+    let g = 0.5 * (1.0 - (c_mean.powi(7) / (c_mean.powi(7) + 25f64.powi(7))).sqrt());
+    let (a1, a2) = ((1.0 + g) * a1, (1.0 + g) * a2);
+
+    let (c1, c2) = ((a1 * a1 + b1 * b1).sqrt(), (a2 * a2 + b2 * b2).sqrt());
+    let hue = |a: f64, b: f64| {
+        if a == 0.0 && b == 0.0 {
+            0.0
+        } else {
+            b.atan2(a).to_degrees().rem_euclid(360.0)
+        }
+    };
+    let (h1, h2) = (hue(a1, b1), hue(a2, b2));
+
+    let delta_l = l2 - l1;
+    let delta_c = c2 - c1;
+    let delta_h = if c1 * c2 == 0.0 {
+        0.0
+    } else if (h2 - h1).abs() <= 180.0 {
+        h2 - h1
+    } else if h2 <= h1 {
+        h2 - h1 + 360.0
+    } else {
+        h2 - h1 - 360.0
+    };
+    let delta_big_h = 2.0 * (c1 * c2).sqrt() * (delta_h.to_radians() / 2.0).sin();
+
+    let l_mean = (l1 + l2) / 2.0;
+    let c_mean = (c1 + c2) / 2.0;
+    let h_mean = if c1 * c2 == 0.0 {
+        h1 + h2
+    } else if (h1 - h2).abs() <= 180.0 {
+        (h1 + h2) / 2.0
+    } else if h1 + h2 < 360.0 {
+        (h1 + h2 + 360.0) / 2.0
+    } else {
+        (h1 + h2 - 360.0) / 2.0
+    };
+
+    let t = 1.0 - 0.17 * (h_mean - 30.0).to_radians().cos()
+        + 0.24 * (2.0 * h_mean).to_radians().cos()
+        + 0.32 * (3.0 * h_mean + 6.0).to_radians().cos()
+        - 0.20 * (4.0 * h_mean - 63.0).to_radians().cos();
+    let delta_theta = 30.0 * (-((h_mean - 275.0) / 25.0).powi(2)).exp();
+    let r_c = 2.0 * (c_mean.powi(7) / (c_mean.powi(7) + 25f64.powi(7))).sqrt();
+    let s_l = 1.0 + 0.015 * (l_mean - 50.0).powi(2) / (20.0 + (l_mean - 50.0).powi(2)).sqrt();
+    let s_c = 1.0 + 0.045 * c_mean;
+    let s_h = 1.0 + 0.015 * c_mean * t;
+    let r_t = -(2.0 * delta_theta * PI / 180.0).sin() * r_c;
+
+    ((delta_l / s_l).powi(2)
+        + (delta_c / s_c).powi(2)
+        + (delta_big_h / s_h).powi(2)
+        + r_t * (delta_c / s_c) * (delta_big_h / s_h))
+        .sqrt()
+}
+
+/// Formats a number as JSON, which has no NaN or infinities, so they
+/// become null.
+pub(crate) fn json_number<T: Copy + Display + Into<f64>>(value: T) -> String {
+    if value.into().is_finite() {
+        value.to_string()
+    } else {
+        "null".to_string()
+    }
+}
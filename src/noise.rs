@@ -0,0 +1,142 @@
+// Author: Cláudio Gomes (TofuLynx)
+// Project: emubayer
+// License: GNU GPL Version 3 (https://www.gnu.org/licenses/gpl-3.0.en.html)
+
+use std::f32::consts::PI;
+
+use super::RawImage;
+
+/// Sensor noise model. Noise is drawn from a seeded generator, so the same
+/// settings always produce the same raw data.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Noise {
+    full_well: Option<f32>,
+    read_noise: f32,
+    seed: u64,
+}
+
+impl Default for Noise {
+    fn default() -> Noise {
+        Noise {
+            full_well: None,
+            read_noise: 0.0,
+            seed: 0,
+        }
+    }
+}
+
+impl Noise {
+    /// Creates a noise model that adds no noise.
+    pub fn new() -> Noise {
+        Noise::default()
+    }
+
+    /// Adds photon shot noise, for photosites that collect `full_well`
+    /// electrons at the white level.
+    pub fn with_shot_noise(mut self, full_well: f32) -> Self {
+        self.full_well = Some(full_well);
+        self
+    }
+
+    /// Adds Gaussian read noise with the given standard deviation, in 16-bit
+    /// digital numbers.
+    pub fn with_read_noise(mut self, sigma: f32) -> Self {
+        self.read_noise = sigma;
+        self
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn full_well(&self) -> Option<f32> {
+        self.full_well
+    }
+
+    pub fn read_noise(&self) -> f32 {
+        self.read_noise
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+}
+
+impl RawImage {
+    /// Returns a copy of the raw data with sensor noise added to every
+    /// sample. Shot noise is approximated by a Gaussian, whose variance is
+    /// the electron count of the sample. Samples clip at the white level.
+    pub fn apply_noise(&self, noise: &Noise) -> Result<RawImage, &'static str> {
+        if noise.full_well.map_or(false, |full_well| {
+            !(full_well > 0.0 && full_well.is_finite())
+        }) {
+            return Err("Full well capacity must be a positive number.");
+        }
+
+        if !(noise.read_noise >= 0.0 && noise.read_noise.is_finite()) {
+            return Err("Read noise must be a non-negative number.");
+        }
+
+        let mut generator = Generator::new(noise.seed);
+        let black_level = f32::from(self.black_level);
+        let white_level = f32::from(self.white_level);
+
+        let data = self
+            .data
+            .iter()
+            .map(|&value| {
+                let value = f32::from(value);
+                let mut variance = noise.read_noise * noise.read_noise;
+
+                // Electrons are counted above the black level.
+                if let Some(full_well) = noise.full_well {
+                    variance +=
+                        (value - black_level).max(0.0) * (white_level - black_level) / full_well;
+                }
+
+                (value + variance.sqrt() * generator.gaussian())
+                    .round()
+                    .clamp(0.0, white_level) as u16
+            })
+            .collect();
+
+        Ok(self.with_data(self.width, self.height, data))
+    }
+}
+
+/// Xorshift64* generator, seeded through SplitMix64.
+struct Generator {
+    state: u64,
+}
+
+impl Generator {
+    fn new(seed: u64) -> Generator {
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+
+        Generator {
+            state: if z == 0 { 1 } else { z },
+        }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Returns a uniform value in (0, 1].
+    fn uniform(&mut self) -> f32 {
+        ((self.next_u64() >> 40) + 1) as f32 / (1u64 << 24) as f32
+    }
+
+    /// Returns a standard normal value, with the Box-Muller transform.
+    fn gaussian(&mut self) -> f32 {
+        let (u, v) = (self.uniform(), self.uniform());
+        (-2.0 * u.ln()).sqrt() * (2.0 * PI * v).cos()
+    }
+}
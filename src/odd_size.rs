@@ -0,0 +1,112 @@
+// Author: Cláudio Gomes (TofuLynx)
+// Project: emubayer
+// License: GNU GPL Version 3 (https://www.gnu.org/licenses/gpl-3.0.en.html)
+
+use std::{fmt, str::FromStr};
+
+use super::{Region, RgbImage};
+
+/// How images with an odd width or height are fitted to the 2x2 grid of a
+/// CFA capture.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum OddSizePolicy {
+    /// Drops the last row and column.
+    #[default]
+    Crop,
+    /// Repeats the last row and column.
+    Pad,
+    /// Mirrors the row and column before the last ones past the edge.
+    Mirror,
+    /// Refuses to capture the image.
+    Error,
+}
+
+impl fmt::Display for OddSizePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                OddSizePolicy::Crop => "crop",
+                OddSizePolicy::Pad => "pad",
+                OddSizePolicy::Mirror => "mirror",
+                OddSizePolicy::Error => "error",
+            }
+        )
+    }
+}
+
+impl FromStr for OddSizePolicy {
+    type Err = &'static str;
+
+    fn from_str(policy: &str) -> Result<OddSizePolicy, &'static str> {
+        match policy.trim().to_lowercase().as_str() {
+            "crop" => Ok(OddSizePolicy::Crop),
+            "pad" => Ok(OddSizePolicy::Pad),
+            "mirror" => Ok(OddSizePolicy::Mirror),
+            "error" => Ok(OddSizePolicy::Error),
+            _ => Err("Unknown odd size policy. Use crop, pad, mirror or error."),
+        }
+    }
+}
+
+impl RgbImage {
+    /// Fits the image to an even size with the given policy, returning it
+    /// along with the region of it that holds the original image.
+    pub fn to_even_size(self, policy: OddSizePolicy) -> Result<(RgbImage, Region), &'static str> {
+        let (width, height) = (self.width, self.height);
+        if width % 2 == 0 && height % 2 == 0 {
+            return Ok((self, Region::new(0, 0, width, height)));
+        }
+
+        match policy {
+            OddSizePolicy::Crop => {
+                let region = Region::new(0, 0, self.even_width(), self.even_height());
+                Ok((self.cropped(region), region))
+            }
+            OddSizePolicy::Pad | OddSizePolicy::Mirror => {
+                // Mirroring a single row or column repeats it.
+                let step = if policy == OddSizePolicy::Mirror {
+                    2
+                } else {
+                    1
+                };
+                let source = |position: u32, size: u32| {
+                    if position < size {
+                        position
+                    } else {
+                        size.saturating_sub(step)
+                    }
+                };
+
+                let (even_width, even_height) = (width + width % 2, height + height % 2);
+                let bytes_per_pixel = self.channels() * self.bit_depth.to_u32() as usize / 8;
+                let mut data =
+                    Vec::with_capacity((even_width * even_height) as usize * bytes_per_pixel);
+
+                for y in 0..even_height {
+                    for x in 0..even_width {
+                        let pixel = (source(y, height) * width + source(x, width)) as usize;
+                        data.extend_from_slice(
+                            &self.data[pixel * bytes_per_pixel..(pixel + 1) * bytes_per_pixel],
+                        );
+                    }
+                }
+
+                Ok((
+                    RgbImage {
+                        width: even_width,
+                        height: even_height,
+                        data,
+                        color_type: self.color_type,
+                        bit_depth: self.bit_depth,
+                    },
+                    Region::new(0, 0, width, height),
+                ))
+            }
+            OddSizePolicy::Error => {
+                Err("Image has an odd width or height, which a CFA can't capture whole.")
+            }
+        }
+    }
+}
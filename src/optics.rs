@@ -0,0 +1,295 @@
+// Author: Cláudio Gomes (TofuLynx)
+// Project: emubayer
+// License: GNU GPL Version 3 (https://www.gnu.org/licenses/gpl-3.0.en.html)
+
+use std::f64::consts::PI;
+
+use super::RgbImage;
+
+/// Zero of the first-order Bessel function that delimits the Airy disk.
+const AIRY_FIRST_ZERO: f64 = 3.831_705_970_207_512;
+
+/// Number of subsamples per kernel cell, on each axis, used to integrate
+/// continuous point spread functions.
+const KERNEL_SUBSAMPLES: u32 = 4;
+
+/// Optical stage placed in front of the sensor, blurring the image before
+/// the CFA is sampled. All distances are in photosites, which match input
+/// pixels unless the camera supersamples its input.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OpticalFilter {
+    /// Birefringent optical low-pass filter, splitting every point into four
+    /// dots, `separation` pixels apart horizontally and vertically.
+    Olpf { separation: f32 },
+    /// Gaussian point spread function with the given standard deviation.
+    Gaussian { sigma: f32 },
+    /// Diffraction-limited Airy pattern whose first dark ring has the given radius.
+    Airy { radius: f32 },
+    /// Out-of-focus lens, spreading every point into a uniform disc.
+    Defocus { radius: f32 },
+}
+
+impl OpticalFilter {
+    /// Returns the same filter with its distances multiplied by `factor`.
+    pub fn scaled(&self, factor: f32) -> OpticalFilter {
+        match *self {
+            OpticalFilter::Olpf { separation } => OpticalFilter::Olpf {
+                separation: separation * factor,
+            },
+            OpticalFilter::Gaussian { sigma } => OpticalFilter::Gaussian {
+                sigma: sigma * factor,
+            },
+            OpticalFilter::Airy { radius } => OpticalFilter::Airy {
+                radius: radius * factor,
+            },
+            OpticalFilter::Defocus { radius } => OpticalFilter::Defocus {
+                radius: radius * factor,
+            },
+        }
+    }
+
+    /// Checks that the filter's distance is a positive number.
+    pub fn validate(&self) -> Result<(), &'static str> {
+        let distance = match *self {
+            OpticalFilter::Olpf { separation } => separation,
+            OpticalFilter::Gaussian { sigma } => sigma,
+            OpticalFilter::Airy { radius } | OpticalFilter::Defocus { radius } => radius,
+        };
+
+        if distance.is_finite() && distance > 0.0 {
+            Ok(())
+        } else {
+            Err("Optical filter sizes must be positive numbers.")
+        }
+    }
+
+    /// Returns how far, in photosites, the filter spreads every point.
+    fn reach(&self) -> f64 {
+        match *self {
+            OpticalFilter::Olpf { separation } => f64::from(separation.abs()) / 2.0,
+            // Three standard deviations or three Airy rings hold nearly all
+            // of the pattern's energy.
+            OpticalFilter::Gaussian { sigma } => 3.0 * f64::from(sigma.abs()),
+            OpticalFilter::Airy { radius } => 3.0 * f64::from(radius.abs()),
+            OpticalFilter::Defocus { radius } => f64::from(radius.abs()),
+        }
+    }
+
+    fn kernel(&self) -> Kernel {
+        let radius = self.reach().ceil() as i32;
+
+        match *self {
+            OpticalFilter::Olpf { separation } => {
+                let offset = separation.abs() / 2.0;
+                let mut kernel = Kernel::new(radius);
+
+                for &(x, y) in &[
+                    (-offset, -offset),
+                    (offset, -offset),
+                    (-offset, offset),
+                    (offset, offset),
+                ] {
+                    kernel.splat(x, y, 0.25);
+                }
+
+                kernel.normalize();
+                kernel
+            }
+            OpticalFilter::Gaussian { sigma } => {
+                let sigma = f64::from(sigma.abs());
+
+                Kernel::from_psf(radius, |r| (-r * r / (2.0 * sigma * sigma)).exp())
+            }
+            OpticalFilter::Airy {
+                radius: ring_radius,
+            } => {
+                let ring_radius = f64::from(ring_radius.abs());
+
+                Kernel::from_psf(radius, |r| {
+                    let x = AIRY_FIRST_ZERO * r / ring_radius;
+                    if x < 1e-6 {
+                        1.0
+                    } else {
+                        (2.0 * bessel_j1(x) / x).powi(2)
+                    }
+                })
+            }
+            OpticalFilter::Defocus {
+                radius: disc_radius,
+            } => {
+                let disc_radius = f64::from(disc_radius.abs());
+
+                Kernel::from_psf(radius, |r| if r <= disc_radius { 1.0 } else { 0.0 })
+            }
+        }
+    }
+}
+
+/// Square convolution kernel of side `2 * radius + 1`, normalized to sum 1.
+struct Kernel {
+    radius: i32,
+    weights: Vec<f32>,
+}
+
+impl Kernel {
+    fn new(radius: i32) -> Kernel {
+        let side = (2 * radius + 1) as usize;
+
+        Kernel {
+            radius,
+            weights: vec![0.0; side * side],
+        }
+    }
+
+    /// Integrates a radially symmetric point spread function over every cell.
+    fn from_psf<F: Fn(f64) -> f64>(radius: i32, psf: F) -> Kernel {
+        let mut kernel = Kernel::new(radius);
+        let step = 1.0 / f64::from(KERNEL_SUBSAMPLES);
+
+        for y in -radius..=radius {
+            for x in -radius..=radius {
+                let mut sum = 0.0;
+
+                for sub_y in 0..KERNEL_SUBSAMPLES {
+                    for sub_x in 0..KERNEL_SUBSAMPLES {
+                        let px = f64::from(x) - 0.5 + (f64::from(sub_x) + 0.5) * step;
+                        let py = f64::from(y) - 0.5 + (f64::from(sub_y) + 0.5) * step;
+                        sum += psf((px * px + py * py).sqrt());
+                    }
+                }
+
+                let index = kernel.index(x, y);
+                kernel.weights[index] = sum as f32;
+            }
+        }
+
+        kernel.normalize();
+        kernel
+    }
+
+    fn index(&self, x: i32, y: i32) -> usize {
+        ((y + self.radius) * (2 * self.radius + 1) + x + self.radius) as usize
+    }
+
+    /// Distributes a weight placed at a subpixel position over its four
+    /// neighbouring cells.
+    fn splat(&mut self, x: f32, y: f32, weight: f32) {
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+
+        for &(dx, dy, w) in &[
+            (0, 0, (1.0 - fx) * (1.0 - fy)),
+            (1, 0, fx * (1.0 - fy)),
+            (0, 1, (1.0 - fx) * fy),
+            (1, 1, fx * fy),
+        ] {
+            let (cx, cy) = (x0 as i32 + dx, y0 as i32 + dy);
+            if w > 0.0 && cx.abs() <= self.radius && cy.abs() <= self.radius {
+                let index = self.index(cx, cy);
+                self.weights[index] += weight * w;
+            }
+        }
+    }
+
+    /// Scales the weights to sum 1. Point spread functions too small to be
+    /// sampled leave every point where it is.
+    fn normalize(&mut self) {
+        let sum: f32 = self.weights.iter().sum();
+
+        if sum > 0.0 && sum.is_finite() {
+            for weight in &mut self.weights {
+                *weight /= sum;
+            }
+        } else {
+            for weight in &mut self.weights {
+                *weight = 0.0;
+            }
+            let center = self.index(0, 0);
+            self.weights[center] = 1.0;
+        }
+    }
+}
+
+impl RgbImage {
+    /// Returns a copy of this image as seen through the given optical filter.
+    ///
+    /// Image borders are extended by edge replication. The result is a 16-bit
+    /// RGB image, so no precision is lost between successive filters. Filters
+    /// must not spread points further than the image is wide or tall.
+    pub fn apply_optical_filter(&self, filter: &OpticalFilter) -> Result<RgbImage, &'static str> {
+        filter.validate()?;
+        if filter.reach().ceil() > f64::from(self.width.max(self.height)) {
+            return Err("Optical filters must not spread further than the image size.");
+        }
+
+        let kernel = filter.kernel();
+        let pixels = self.to_float();
+        let (width, height) = (self.width as i32, self.height as i32);
+
+        let mut blurred = Vec::with_capacity(pixels.len());
+
+        for y in 0..height {
+            for x in 0..width {
+                let mut sum = [0.0; 3];
+
+                for ky in -kernel.radius..=kernel.radius {
+                    let sy = (y + ky).clamp(0, height - 1);
+
+                    for kx in -kernel.radius..=kernel.radius {
+                        let weight = kernel.weights[kernel.index(kx, ky)];
+                        if weight == 0.0 {
+                            continue;
+                        }
+
+                        let sx = (x + kx).clamp(0, width - 1);
+                        let pixel = pixels[(sy * width + sx) as usize];
+                        for channel in 0..3 {
+                            sum[channel] += pixel[channel] * weight;
+                        }
+                    }
+                }
+
+                blurred.push(sum);
+            }
+        }
+
+        Ok(RgbImage::from_float(self.width, self.height, &blurred))
+    }
+}
+
+/// First-order Bessel function of the first kind, using the rational
+/// approximations from Numerical Recipes.
+fn bessel_j1(x: f64) -> f64 {
+    let ax = x.abs();
+
+    if ax < 8.0 {
+        let y = x * x;
+        let numerator = x
+            * (72_362_614_232.0
+                + y * (-7_895_059_235.0
+                    + y * (242_396_853.1
+                        + y * (-2_972_611.439 + y * (15_704.482_60 + y * -30.160_366_06)))));
+        let denominator = 144_725_228_442.0
+            + y * (2_300_535_178.0
+                + y * (18_583_304.74 + y * (99_447.433_94 + y * (376.999_139_7 + y))));
+
+        numerator / denominator
+    } else {
+        let z = 8.0 / ax;
+        let y = z * z;
+        let shifted = ax - 3.0 * PI / 4.0;
+        let p = 1.0
+            + y * (0.183_105e-2
+                + y * (-0.351_639_649_6e-4 + y * (0.245_752_017_4e-5 + y * -0.240_337_019e-6)));
+        let q = 0.046_874_999_95
+            + y * (-0.200_269_087_3e-3
+                + y * (0.844_919_909_6e-5 + y * (-0.882_289_87e-6 + y * 0.105_787_412e-6)));
+        let value = (2.0 / (PI * ax)).sqrt() * (shifted.cos() * p - z * shifted.sin() * q);
+
+        if x < 0.0 {
+            -value
+        } else {
+            value
+        }
+    }
+}
@@ -0,0 +1,296 @@
+// Author: Cláudio Gomes (TofuLynx)
+// Project: emubayer
+// License: GNU GPL Version 3 (https://www.gnu.org/licenses/gpl-3.0.en.html)
+
+use std::{fmt, str::FromStr};
+
+use super::{CfaPattern, RawImage, Region, RgbImage, SensorLayout};
+use tiff::{TiffReader, TAG_ORIENTATION};
+
+/// How stored image data must be rotated or flipped to be displayed upright,
+/// as in the TIFF and EXIF Orientation tag.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Orientation {
+    #[default]
+    Normal,
+    FlipHorizontal,
+    Rotate180,
+    FlipVertical,
+    /// Mirrors the image along its main diagonal.
+    Transpose,
+    /// Rotates the image 90 degrees clockwise.
+    Rotate90,
+    /// Mirrors the image along its anti-diagonal.
+    Transverse,
+    /// Rotates the image 90 degrees counterclockwise.
+    Rotate270,
+}
+
+impl fmt::Display for Orientation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Orientation::Normal => "normal",
+                Orientation::FlipHorizontal => "flip-horizontal",
+                Orientation::Rotate180 => "rotate-180",
+                Orientation::FlipVertical => "flip-vertical",
+                Orientation::Transpose => "transpose",
+                Orientation::Rotate90 => "rotate-90",
+                Orientation::Transverse => "transverse",
+                Orientation::Rotate270 => "rotate-270",
+            }
+        )
+    }
+}
+
+impl FromStr for Orientation {
+    type Err = &'static str;
+
+    /// Parses an orientation from its name or its tag value.
+    fn from_str(orientation: &str) -> Result<Orientation, &'static str> {
+        let orientation = orientation.trim().to_lowercase();
+        if let Ok(value) = orientation.parse::<u16>() {
+            return Orientation::from_tag(value).ok_or("Orientation tag values go from 1 to 8.");
+        }
+
+        match orientation.as_str() {
+            "normal" => Ok(Orientation::Normal),
+            "flip-horizontal" => Ok(Orientation::FlipHorizontal),
+            "rotate-180" => Ok(Orientation::Rotate180),
+            "flip-vertical" => Ok(Orientation::FlipVertical),
+            "transpose" => Ok(Orientation::Transpose),
+            "rotate-90" => Ok(Orientation::Rotate90),
+            "transverse" => Ok(Orientation::Transverse),
+            "rotate-270" => Ok(Orientation::Rotate270),
+            _ => Err("Unknown orientation. Use a tag value from 1 to 8, normal, flip-horizontal, rotate-180, flip-vertical, transpose, rotate-90, transverse or rotate-270."),
+        }
+    }
+}
+
+impl Orientation {
+    pub fn from_tag(value: u16) -> Option<Orientation> {
+        match value {
+            1 => Some(Orientation::Normal),
+            2 => Some(Orientation::FlipHorizontal),
+            3 => Some(Orientation::Rotate180),
+            4 => Some(Orientation::FlipVertical),
+            5 => Some(Orientation::Transpose),
+            6 => Some(Orientation::Rotate90),
+            7 => Some(Orientation::Transverse),
+            8 => Some(Orientation::Rotate270),
+            _ => None,
+        }
+    }
+
+    pub fn tag(&self) -> u16 {
+        match self {
+            Orientation::Normal => 1,
+            Orientation::FlipHorizontal => 2,
+            Orientation::Rotate180 => 3,
+            Orientation::FlipVertical => 4,
+            Orientation::Transpose => 5,
+            Orientation::Rotate90 => 6,
+            Orientation::Transverse => 7,
+            Orientation::Rotate270 => 8,
+        }
+    }
+
+    /// Returns the orientation that undoes this one.
+    pub fn inverse(&self) -> Orientation {
+        match self {
+            Orientation::Rotate90 => Orientation::Rotate270,
+            Orientation::Rotate270 => Orientation::Rotate90,
+            orientation => *orientation,
+        }
+    }
+
+    /// Returns whether the orientation swaps width and height.
+    pub fn swaps_axes(&self) -> bool {
+        matches!(
+            self,
+            Orientation::Transpose
+                | Orientation::Rotate90
+                | Orientation::Transverse
+                | Orientation::Rotate270
+        )
+    }
+
+    /// Returns the size of a `width` x `height` image once displayed.
+    pub(crate) fn displayed_size(&self, width: u32, height: u32) -> (u32, u32) {
+        if self.swaps_axes() {
+            (height, width)
+        } else {
+            (width, height)
+        }
+    }
+
+    /// Returns the stored position of the pixel displayed at (`x`, `y`), for
+    /// an image stored as `width` x `height` pixels.
+    pub(crate) fn source(&self, x: u32, y: u32, width: u32, height: u32) -> (u32, u32) {
+        match self {
+            Orientation::Normal => (x, y),
+            Orientation::FlipHorizontal => (width - 1 - x, y),
+            Orientation::Rotate180 => (width - 1 - x, height - 1 - y),
+            Orientation::FlipVertical => (x, height - 1 - y),
+            Orientation::Transpose => (y, x),
+            Orientation::Rotate90 => (y, height - 1 - x),
+            Orientation::Transverse => (width - 1 - y, height - 1 - x),
+            Orientation::Rotate270 => (width - 1 - y, x),
+        }
+    }
+
+    /// Returns where a region of an image stored as `width` x `height`
+    /// pixels is displayed.
+    fn displayed_region(&self, region: Region, width: u32, height: u32) -> Region {
+        let (displayed_width, displayed_height) = self.displayed_size(width, height);
+        let inverse = self.inverse();
+
+        let (x0, y0) = inverse.source(region.x, region.y, displayed_width, displayed_height);
+        let (x1, y1) = inverse.source(
+            region.x + region.width - 1,
+            region.y + region.height - 1,
+            displayed_width,
+            displayed_height,
+        );
+
+        Region::new(
+            x0.min(x1),
+            y0.min(y1),
+            x0.max(x1) - x0.min(x1) + 1,
+            y0.max(y1) - y0.min(y1) + 1,
+        )
+    }
+}
+
+impl RgbImage {
+    /// Rotates and flips the image as the given orientation describes, so
+    /// that an image stored with it is displayed upright.
+    pub fn oriented(self, orientation: Orientation) -> RgbImage {
+        if orientation == Orientation::Normal {
+            return self;
+        }
+
+        let (width, height) = orientation.displayed_size(self.width, self.height);
+        let bytes_per_pixel = self.channels() * self.bit_depth.to_u32() as usize / 8;
+        let mut data = Vec::with_capacity(self.data.len());
+
+        for y in 0..height {
+            for x in 0..width {
+                let (source_x, source_y) = orientation.source(x, y, self.width, self.height);
+                let pixel = (source_y * self.width + source_x) as usize;
+                data.extend_from_slice(
+                    &self.data[pixel * bytes_per_pixel..(pixel + 1) * bytes_per_pixel],
+                );
+            }
+        }
+
+        RgbImage {
+            width,
+            height,
+            data,
+            color_type: self.color_type,
+            bit_depth: self.bit_depth,
+        }
+    }
+}
+
+impl RawImage {
+    pub fn orientation(&self) -> Orientation {
+        self.orientation
+    }
+
+    /// Returns the same raw data, stored with the given Orientation tag.
+    pub fn with_orientation(mut self, orientation: Orientation) -> RawImage {
+        self.orientation = orientation;
+        self
+    }
+
+    /// Rotates and flips the mosaic itself as its Orientation tag describes,
+    /// adjusting the CFA pattern and default crop, so that it's stored
+    /// upright with a normal orientation.
+    pub fn apply_orientation(&self) -> RawImage {
+        let orientation = self.orientation;
+        let (width, height) = orientation.displayed_size(self.width, self.height);
+        let samples_per_pixel = self.layout.samples_per_pixel() as usize;
+        let mut data = Vec::with_capacity(self.data.len());
+
+        for y in 0..height {
+            for x in 0..width {
+                let (source_x, source_y) = orientation.source(x, y, self.width, self.height);
+                let pixel = (source_y * self.width + source_x) as usize;
+                data.extend_from_slice(
+                    &self.data[pixel * samples_per_pixel..(pixel + 1) * samples_per_pixel],
+                );
+            }
+        }
+
+        // The pattern repeats, so the colors of its first tile are enough.
+        // The image is grown by whole tiles for the tile to fit in it.
+        let layout = match self.layout {
+            SensorLayout::Cfa(ref cfa_pattern) => {
+                let (rows, columns) = cfa_pattern.repeat_dim();
+                let (tiled_width, tiled_height) =
+                    (self.width + columns * 256, self.height + rows * 256);
+                let (columns, rows) = orientation.displayed_size(columns, rows);
+                let colors = (0..rows)
+                    .flat_map(|y| (0..columns).map(move |x| (x, y)))
+                    .map(|(x, y)| {
+                        let (source_x, source_y) =
+                            orientation.source(x, y, tiled_width, tiled_height);
+                        cfa_pattern.color_at(source_x, source_y)
+                    })
+                    .collect();
+
+                SensorLayout::Cfa(CfaPattern::new(rows, columns, colors).unwrap())
+            }
+            ref layout => layout.clone(),
+        };
+
+        RawImage {
+            layout,
+            default_crop: orientation.displayed_region(self.default_crop, self.width, self.height),
+            orientation: Orientation::Normal,
+            ..self.with_data(width, height, data)
+        }
+    }
+}
+
+/// Reads the orientation stored in the eXIf chunk of a PNG file, if any.
+pub(crate) fn png_exif_orientation(png: &[u8]) -> Option<Orientation> {
+    let mut position = 8;
+
+    while position + 8 <= png.len() {
+        let length = u32::from_be_bytes([
+            png[position],
+            png[position + 1],
+            png[position + 2],
+            png[position + 3],
+        ]) as usize;
+        let chunk_type = &png[position + 4..position + 8];
+        let data = png.get(position + 8..(position + 8).checked_add(length)?)?;
+
+        if chunk_type == b"eXIf" {
+            return exif_orientation(data);
+        }
+
+        if chunk_type == b"IEND" {
+            break;
+        }
+
+        // Skips the chunk's data and CRC.
+        position += 12 + length;
+    }
+
+    None
+}
+
+/// Reads the orientation stored in EXIF data, which is laid out as a TIFF
+/// file.
+pub(crate) fn exif_orientation(exif: &[u8]) -> Option<Orientation> {
+    let exif = TiffReader::new(exif.to_vec()).ok()?;
+    let ifds = exif.ifds().ok()?;
+    let value = exif.u32_value(ifds.first()?, TAG_ORIENTATION)?;
+    Orientation::from_tag(value as u16)
+}
@@ -0,0 +1,85 @@
+// Author: Cláudio Gomes (TofuLynx)
+// Project: emubayer
+// License: GNU GPL Version 3 (https://www.gnu.org/licenses/gpl-3.0.en.html)
+
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+
+use super::LinearImage;
+
+pub(crate) fn is_pfm(data: &[u8]) -> bool {
+    data.starts_with(b"PF") || data.starts_with(b"Pf")
+}
+
+/// Reads a color or grayscale Portable Float Map as linear light. Rows are
+/// stored from the bottom up, in the byte order given by the sign of the
+/// scale, whose magnitude is ignored.
+pub(crate) fn read_pfm(data: &[u8]) -> Result<LinearImage, &'static str> {
+    if !is_pfm(data) {
+        return Err("This file isn't a PFM file.");
+    }
+    let channels = if data[1] == b'F' { 3 } else { 1 };
+
+    // The header holds the format, size and scale, separated by whitespace,
+    // with a single whitespace character before the data.
+    let mut fields = Vec::with_capacity(4);
+    let mut position = 0;
+    while fields.len() < 4 {
+        while data
+            .get(position)
+            .ok_or("PFM header is truncated.")?
+            .is_ascii_whitespace()
+        {
+            position += 1;
+        }
+        let start = position;
+        while data
+            .get(position)
+            .map_or(false, |byte| !byte.is_ascii_whitespace())
+        {
+            position += 1;
+        }
+        fields.push(String::from_utf8_lossy(&data[start..position]).into_owned());
+    }
+    position += 1;
+
+    let (width, height, scale) = match (
+        fields[1].parse::<u32>(),
+        fields[2].parse::<u32>(),
+        fields[3].parse::<f32>(),
+    ) {
+        (Ok(width), Ok(height), Ok(scale)) if width > 0 && height > 0 && scale != 0.0 => {
+            (width, height, scale)
+        }
+        _ => return Err("PFM header is invalid."),
+    };
+
+    let length = (width as usize)
+        .checked_mul(height as usize)
+        .and_then(|pixels| pixels.checked_mul(channels as usize))
+        .ok_or("PFM image is too large.")?;
+    let samples = length
+        .checked_mul(4)
+        .and_then(|size| data.get(position..position.checked_add(size)?))
+        .ok_or("PFM file is truncated.")?;
+    let mut values = vec![0.0f32; length];
+    if scale < 0.0 {
+        LittleEndian::read_f32_into(samples, &mut values);
+    } else {
+        BigEndian::read_f32_into(samples, &mut values);
+    }
+
+    let pixels = values
+        .chunks((width * channels) as usize)
+        .rev()
+        .flat_map(|row| row.chunks(channels as usize))
+        .map(|sample| {
+            if channels == 3 {
+                [sample[0], sample[1], sample[2]]
+            } else {
+                [sample[0]; 3]
+            }
+        })
+        .collect();
+
+    LinearImage::sanitized(width, height, pixels)
+}
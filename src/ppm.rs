@@ -0,0 +1,98 @@
+// Author: Cláudio Gomes (TofuLynx)
+// Project: emubayer
+// License: GNU GPL Version 3 (https://www.gnu.org/licenses/gpl-3.0.en.html)
+
+use super::{BitDepth, RgbImage};
+
+const INVALID: &str = "This PPM file appears to be corrupted.";
+
+/// Returns whether the data starts like a binary or plain PPM or PGM image.
+pub(crate) fn is_ppm(data: &[u8]) -> bool {
+    matches!(
+        data.get(..2),
+        Some(b"P2") | Some(b"P3") | Some(b"P5") | Some(b"P6")
+    ) && data.get(2).map_or(false, u8::is_ascii_whitespace)
+}
+
+/// Decodes a PPM image, or a grayscale PGM one, in binary or plain form.
+/// Samples of up to 255 are read as 8 bits, and larger ones as 16 bits,
+/// scaled up when their maximum value is lower than the depth's.
+pub(crate) fn read_ppm(data: &[u8]) -> Result<RgbImage, &'static str> {
+    if !is_ppm(data) {
+        return Err("This file isn't a PPM file.");
+    }
+    let channels = if data[1] == b'2' || data[1] == b'5' {
+        1
+    } else {
+        3
+    };
+    let plain = data[1] == b'2' || data[1] == b'3';
+
+    let mut position = 2;
+    let mut header = [0u32; 3];
+    for field in header.iter_mut() {
+        *field = number(data, &mut position)?;
+    }
+    let [width, height, max_value] = header;
+    if width == 0 || height == 0 || max_value == 0 || max_value > 65535 {
+        return Err(INVALID);
+    }
+
+    let length = (width * height) as usize * channels;
+    let samples: Vec<u32> = if plain {
+        (0..length)
+            .map(|_| number(data, &mut position))
+            .collect::<Result<_, _>>()?
+    } else {
+        // A single whitespace character separates the header from the samples.
+        position += 1;
+        let sample_size = if max_value < 256 { 1 } else { 2 };
+        data.get(position..position + length * sample_size)
+            .ok_or("PPM file is truncated.")?
+            .chunks(sample_size)
+            .map(|bytes| {
+                bytes
+                    .iter()
+                    .fold(0, |value, &byte| value << 8 | u32::from(byte))
+            })
+            .collect()
+    };
+
+    let (bit_depth, white) = if max_value < 256 {
+        (BitDepth::Eight, 255)
+    } else {
+        (BitDepth::Sixteen, 65535)
+    };
+    let samples: Vec<u16> = samples
+        .iter()
+        .map(|&sample| ((sample.min(max_value) * white + max_value / 2) / max_value) as u16)
+        .collect();
+
+    RgbImage::from_samples(width, height, channels, bit_depth, &samples)
+}
+
+/// Reads a decimal number after any whitespace and comments, moving past it.
+fn number(data: &[u8], position: &mut usize) -> Result<u32, &'static str> {
+    loop {
+        match data.get(*position) {
+            Some(byte) if byte.is_ascii_whitespace() => *position += 1,
+            Some(b'#') => {
+                while data.get(*position).map_or(false, |&byte| byte != b'\n') {
+                    *position += 1;
+                }
+            }
+            Some(_) => break,
+            None => return Err("PPM file is truncated."),
+        }
+    }
+
+    let start = *position;
+    while data.get(*position).map_or(false, u8::is_ascii_digit) {
+        *position += 1;
+    }
+
+    std::str::from_utf8(&data[start..*position])
+        .ok()
+        .and_then(|digits| digits.parse().ok())
+        .ok_or(INVALID)
+}
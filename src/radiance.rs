@@ -0,0 +1,194 @@
+// Author: Cláudio Gomes (TofuLynx)
+// Project: emubayer
+// License: GNU GPL Version 3 (https://www.gnu.org/licenses/gpl-3.0.en.html)
+
+use super::LinearImage;
+
+const TRUNCATED: &str = "Radiance HDR file is truncated.";
+
+/// Most pixels that a byte of run-length encoded scanlines can hold, as
+/// runs of 127 pixels take 2 bytes for every component.
+const MAX_PIXELS_PER_BYTE: usize = 16;
+
+pub(crate) fn is_radiance(data: &[u8]) -> bool {
+    data.starts_with(b"#?")
+}
+
+/// Reads a Radiance HDR image of RGBE pixels, flat or run-length encoded,
+/// as linear light. Its EXPOSURE is undone, so that values are radiances.
+pub(crate) fn read_radiance(data: &[u8]) -> Result<LinearImage, &'static str> {
+    if !is_radiance(data) {
+        return Err("This file isn't a Radiance HDR file.");
+    }
+
+    // The header ends with an empty line, followed by the resolution.
+    let mut lines = data.split(|&byte| byte == b'\n');
+    let mut position = 0;
+    let mut exposure = 1.0f32;
+
+    loop {
+        let line = lines.next().ok_or(TRUNCATED)?;
+        position += line.len() + 1;
+        let line = String::from_utf8_lossy(line);
+        let line = line.trim();
+
+        if line.is_empty() {
+            break;
+        }
+
+        if let Some(format) = line.strip_prefix("FORMAT=") {
+            if format.trim() != "32-bit_rle_rgbe" {
+                return Err("Only Radiance HDR files of RGBE pixels are supported.");
+            }
+        } else if let Some(value) = line.strip_prefix("EXPOSURE=") {
+            exposure *= value
+                .trim()
+                .parse::<f32>()
+                .ok()
+                .filter(|&value| value > 0.0)
+                .ok_or("Radiance HDR file has an invalid exposure.")?;
+        }
+    }
+
+    let resolution = lines.next().ok_or(TRUNCATED)?;
+    position += resolution.len() + 1;
+    let resolution = String::from_utf8_lossy(resolution);
+    let (bottom_up, width, height) =
+        match resolution.split_whitespace().collect::<Vec<_>>().as_slice() {
+            &[vertical, height, "+X", width] if vertical == "-Y" || vertical == "+Y" => (
+                vertical == "+Y",
+                width.parse::<u32>().ok(),
+                height.parse::<u32>().ok(),
+            ),
+            _ => return Err("Only Radiance HDR files stored in rows are supported."),
+        };
+    let (width, height) = match (width, height) {
+        (Some(width), Some(height)) if width > 0 && height > 0 => (width, height),
+        _ => return Err("Radiance HDR file has an invalid resolution."),
+    };
+
+    // The pixels must fit in the rest of the file before anything is
+    // allocated for them.
+    let pixel_count = (width as usize)
+        .checked_mul(height as usize)
+        .filter(|&count| count / MAX_PIXELS_PER_BYTE <= data.len().saturating_sub(position))
+        .ok_or("Radiance HDR resolution is too large.")?;
+
+    let mut pixels = Vec::with_capacity(pixel_count);
+    let mut scanline = vec![[0u8; 4]; width as usize];
+
+    for _ in 0..height {
+        position = read_scanline(data, position, &mut scanline)?;
+        pixels.extend(scanline.iter().map(|&rgbe| {
+            let [red, green, blue] = rgbe_to_f32(rgbe);
+            [red / exposure, green / exposure, blue / exposure]
+        }));
+    }
+
+    if bottom_up {
+        let rows: Vec<_> = pixels.chunks(width as usize).rev().collect();
+        pixels = rows.concat();
+    }
+
+    LinearImage::sanitized(width, height, pixels)
+}
+
+/// Reads a scanline starting at `position`, returning where the next one
+/// starts.
+fn read_scanline(
+    data: &[u8],
+    mut position: usize,
+    scanline: &mut [[u8; 4]],
+) -> Result<usize, &'static str> {
+    let width = scanline.len();
+    let start = data.get(position..position + 4).ok_or(TRUNCATED)?;
+
+    // Newer files encode every component of a scanline on its own, after a
+    // marker holding the width.
+    if (8..0x8000).contains(&width) && start[0] == 2 && start[1] == 2 && start[2] & 0x80 == 0 {
+        if (usize::from(start[2]) << 8 | usize::from(start[3])) != width {
+            return Err("Radiance HDR file has a corrupted scanline.");
+        }
+        position += 4;
+
+        for component in 0..4 {
+            let mut x = 0;
+            while x < width {
+                let count = usize::from(*data.get(position).ok_or(TRUNCATED)?);
+                position += 1;
+
+                if count > 128 {
+                    let count = count - 128;
+                    let value = *data.get(position).ok_or(TRUNCATED)?;
+                    position += 1;
+                    if x + count > width {
+                        return Err("Radiance HDR file has a corrupted scanline.");
+                    }
+                    for pixel in &mut scanline[x..x + count] {
+                        pixel[component] = value;
+                    }
+                    x += count;
+                } else {
+                    if count == 0 || x + count > width {
+                        return Err("Radiance HDR file has a corrupted scanline.");
+                    }
+                    let values = data.get(position..position + count).ok_or(TRUNCATED)?;
+                    for (pixel, &value) in scanline[x..x + count].iter_mut().zip(values) {
+                        pixel[component] = value;
+                    }
+                    position += count;
+                    x += count;
+                }
+            }
+        }
+
+        return Ok(position);
+    }
+
+    // Older files store pixels as they are, where a pixel of 1, 1, 1 repeats
+    // the previous one, more times for consecutive repeats.
+    let mut x = 0;
+    let mut shift = 0;
+    while x < width {
+        let pixel = data.get(position..position + 4).ok_or(TRUNCATED)?;
+        position += 4;
+
+        if pixel[..3] == [1, 1, 1] {
+            // Four consecutive repeats make a count of 32 bits.
+            if shift > 24 {
+                return Err("Radiance HDR file has a corrupted scanline.");
+            }
+            let count = usize::from(pixel[3]) << shift;
+            if x == 0 || count == 0 || x + count > width {
+                return Err("Radiance HDR file has a corrupted scanline.");
+            }
+            let previous = scanline[x - 1];
+            for repeated in &mut scanline[x..x + count] {
+                *repeated = previous;
+            }
+            x += count;
+            shift += 8;
+        } else {
+            scanline[x].copy_from_slice(pixel);
+            x += 1;
+            shift = 0;
+        }
+    }
+
+    Ok(position)
+}
+
+/// Converts a pixel of three mantissas sharing an exponent, as Radiance
+/// does, taking the middle of every mantissa's interval.
+fn rgbe_to_f32(rgbe: [u8; 4]) -> [f32; 3] {
+    if rgbe[3] == 0 {
+        return [0.0; 3];
+    }
+
+    let scale = 2f32.powi(i32::from(rgbe[3]) - 136);
+    [
+        (f32::from(rgbe[0]) + 0.5) * scale,
+        (f32::from(rgbe[1]) + 0.5) * scale,
+        (f32::from(rgbe[2]) + 0.5) * scale,
+    ]
+}
@@ -0,0 +1,187 @@
+// Author: Cláudio Gomes (TofuLynx)
+// Project: emubayer
+// License: GNU GPL Version 3 (https://www.gnu.org/licenses/gpl-3.0.en.html)
+
+use std::{fmt, str::FromStr};
+
+use super::{RawImage, SensorLayout};
+
+/// How binned photosites are combined.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Combination {
+    /// Adds the charges up, saturating at the 16-bit white level.
+    Sum,
+    /// Averages the samples, keeping the black and white levels.
+    Average,
+}
+
+/// Reduced readout of a sensor, as used by video modes. Every mode keeps the
+/// CFA pattern, so its frames are demosaiced like full readouts.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReadoutMode {
+    /// Combines `factor` x `factor` photosites of the same color, from
+    /// neighboring tiles of the CFA pattern, into one.
+    Binning {
+        factor: u32,
+        combination: Combination,
+    },
+    /// Reads only one of every `factor` columns and rows of CFA tiles.
+    Skipping { factor: u32 },
+}
+
+impl fmt::Display for ReadoutMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReadoutMode::Binning {
+                factor,
+                combination: Combination::Sum,
+            } => write!(f, "bin-{}x{}-sum", factor, factor),
+            ReadoutMode::Binning {
+                factor,
+                combination: Combination::Average,
+            } => write!(f, "bin-{}x{}-average", factor, factor),
+            ReadoutMode::Skipping { factor } => write!(f, "skip-{}x{}", factor, factor),
+        }
+    }
+}
+
+impl FromStr for ReadoutMode {
+    type Err = &'static str;
+
+    /// Parses modes such as bin-2x2-sum, bin-2x2-average or skip-3x3.
+    /// Binning sums when no combination is given.
+    fn from_str(readout_mode: &str) -> Result<ReadoutMode, &'static str> {
+        const USAGE: &str =
+            "Readout mode must be bin-NxN-sum, bin-NxN-average or skip-NxN, with N above 0.";
+
+        let readout_mode = readout_mode.trim().to_lowercase();
+        let parts: Vec<&str> = readout_mode.split('-').collect();
+
+        let factor = |size: &str| match size.split('x').collect::<Vec<_>>().as_slice() {
+            &[columns, rows] if columns == rows => {
+                columns.parse::<u32>().ok().filter(|&factor| factor > 0)
+            }
+            _ => None,
+        };
+
+        match parts.as_slice() {
+            &["bin", size] | &["bin", size, "sum"] => Ok(ReadoutMode::Binning {
+                factor: factor(size).ok_or(USAGE)?,
+                combination: Combination::Sum,
+            }),
+            &["bin", size, "average"] => Ok(ReadoutMode::Binning {
+                factor: factor(size).ok_or(USAGE)?,
+                combination: Combination::Average,
+            }),
+            &["skip", size] => Ok(ReadoutMode::Skipping {
+                factor: factor(size).ok_or(USAGE)?,
+            }),
+            _ => Err(USAGE),
+        }
+    }
+}
+
+impl ReadoutMode {
+    pub fn factor(&self) -> u32 {
+        match self {
+            ReadoutMode::Binning { factor, .. } | ReadoutMode::Skipping { factor } => *factor,
+        }
+    }
+}
+
+impl RawImage {
+    /// Reads out a smaller frame with the given mode, as sensors do in video
+    /// modes. Binning sums or averages photosites of the same color, and
+    /// skipping drops whole tiles of the CFA pattern. Both keep the pattern,
+    /// but sample colors at uneven spacings, which causes their
+    /// characteristic aliasing.
+    ///
+    /// Trailing rows and columns that don't fill a whole group of tiles are
+    /// dropped, and the default crop shrinks with the frame. Summed binning
+    /// adds up the black and white levels, saturating at 16 bits.
+    pub fn read_out(&self, mode: ReadoutMode) -> Result<RawImage, &'static str> {
+        let factor = mode.factor();
+        if factor == 0 {
+            return Err("Readout factor must be at least 1.");
+        }
+
+        let (columns, rows) = self.layout.tile_size();
+        let width = self.width / (columns * factor) * columns;
+        let height = self.height / (rows * factor) * rows;
+        if width == 0 || height == 0 {
+            return Err("Image is smaller than a group of tiles of the readout mode.");
+        }
+
+        // Every output photosite reads the same position within the tiles of
+        // its group.
+        let source = |position: u32, period: u32, tile: u32| {
+            (position / period * factor + tile) * period + position % period
+        };
+
+        let samples_per_pixel = self.layout.samples_per_pixel();
+        let mut data = Vec::with_capacity((width * height * samples_per_pixel) as usize);
+
+        for y in 0..height {
+            for x in 0..width {
+                for sample in 0..samples_per_pixel {
+                    let value = |tile_x: u32, tile_y: u32| {
+                        let pixel =
+                            source(y, rows, tile_y) * self.width + source(x, columns, tile_x);
+                        u32::from(self.data[(pixel * samples_per_pixel + sample) as usize])
+                    };
+
+                    let value = match mode {
+                        ReadoutMode::Skipping { .. } => value(0, 0),
+                        ReadoutMode::Binning { combination, .. } => {
+                            let sum: u32 = (0..factor)
+                                .flat_map(|tile_y| (0..factor).map(move |tile_x| (tile_x, tile_y)))
+                                .map(|(tile_x, tile_y)| value(tile_x, tile_y))
+                                .sum();
+
+                            match combination {
+                                Combination::Sum => sum,
+                                Combination::Average => {
+                                    (sum + factor * factor / 2) / (factor * factor)
+                                }
+                            }
+                        }
+                    };
+
+                    data.push(value.min(u32::from(u16::MAX)) as u16);
+                }
+            }
+        }
+
+        let (black_level, white_level) = match mode {
+            ReadoutMode::Binning {
+                combination: Combination::Sum,
+                ..
+            } => (
+                (u32::from(self.black_level) * factor * factor).min(u32::from(u16::MAX)) as u16,
+                (u32::from(self.white_level) * factor * factor).min(u32::from(u16::MAX)) as u16,
+            ),
+            _ => (self.black_level, self.white_level),
+        };
+
+        Ok(RawImage {
+            black_level,
+            white_level,
+            default_crop: self.default_crop.scaled_down(factor, width, height),
+            ..self.with_data(width, height, data)
+        })
+    }
+}
+
+impl SensorLayout {
+    /// Returns the size of the repeating tile of the layout, as columns and
+    /// rows, which is a single photosite for sensors without a CFA.
+    pub(crate) fn tile_size(&self) -> (u32, u32) {
+        match self {
+            SensorLayout::Cfa(cfa_pattern) => {
+                let (rows, columns) = cfa_pattern.repeat_dim();
+                (columns, rows)
+            }
+            _ => (1, 1),
+        }
+    }
+}
@@ -0,0 +1,94 @@
+// Author: Cláudio Gomes (TofuLynx)
+// Project: emubayer
+// License: GNU GPL Version 3 (https://www.gnu.org/licenses/gpl-3.0.en.html)
+
+/// Rectangle of an image, in pixels.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Region {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Region {
+    pub fn new(x: u32, y: u32, width: u32, height: u32) -> Region {
+        Region {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// Returns whether the region is non-empty and fits in an image of the
+    /// given size.
+    pub(crate) fn lies_within(&self, width: u32, height: u32) -> bool {
+        self.width > 0
+            && self.height > 0
+            && self
+                .x
+                .checked_add(self.width)
+                .map_or(false, |end| end <= width)
+            && self
+                .y
+                .checked_add(self.height)
+                .map_or(false, |end| end <= height)
+    }
+
+    /// Returns the region covering the same part of an image shrunk by the
+    /// given factor to the given size, which has to be non-empty.
+    pub(crate) fn scaled_down(&self, factor: u32, width: u32, height: u32) -> Region {
+        let start = |position: u32, size: u32| (position / factor).min(size - 1);
+        let end = |position: u32, length: u32, size: u32| {
+            let end = position.saturating_add(length);
+            (end / factor + u32::from(end % factor != 0)).min(size)
+        };
+
+        let (x, y) = (start(self.x, width), start(self.y, height));
+        Region::new(
+            x,
+            y,
+            end(self.x, self.width, width) - x,
+            end(self.y, self.height, height) - y,
+        )
+    }
+
+    /// Splits an image into a grid of regions, from left to right and top to
+    /// bottom. The last column and row take the pixels left over.
+    pub fn grid(width: u32, height: u32, columns: u32, rows: u32) -> Vec<Region> {
+        let mut regions = Vec::new();
+
+        if columns == 0 || rows == 0 {
+            return regions;
+        }
+
+        let (region_width, region_height) = (width / columns, height / rows);
+        for row in 0..rows {
+            for column in 0..columns {
+                let (x, y) = (column * region_width, row * region_height);
+                regions.push(Region::new(
+                    x,
+                    y,
+                    if column == columns - 1 {
+                        width - x
+                    } else {
+                        region_width
+                    },
+                    if row == rows - 1 {
+                        height - y
+                    } else {
+                        region_height
+                    },
+                ));
+            }
+        }
+
+        regions
+    }
+
+    pub(crate) fn pixels(&self) -> impl Iterator<Item = (u32, u32)> {
+        let (x, y, width, height) = (self.x, self.y, self.width, self.height);
+        (y..y + height).flat_map(move |y| (x..x + width).map(move |x| (x, y)))
+    }
+}
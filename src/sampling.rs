@@ -0,0 +1,91 @@
+// Author: Cláudio Gomes (TofuLynx)
+// Project: emubayer
+// License: GNU GPL Version 3 (https://www.gnu.org/licenses/gpl-3.0.en.html)
+
+use super::RgbImage;
+
+/// Light-sensitive area of a photosite, over which the incoming image is
+/// integrated.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Aperture {
+    /// The whole photosite collects light.
+    Box,
+    /// Only a centered square covering the given fraction of the photosite
+    /// area collects light.
+    FillFactor(f32),
+}
+
+impl Aperture {
+    /// Returns how much of each of the `factor` input pixels spanned by a
+    /// photosite, along one axis, falls inside the aperture.
+    fn weights(&self, factor: u32) -> Vec<f32> {
+        let size = factor as f32;
+        let side = match *self {
+            Aperture::Box => size,
+            Aperture::FillFactor(fill_factor) => size * fill_factor.clamp(0.0, 1.0).sqrt(),
+        };
+        let (start, end) = ((size - side) / 2.0, (size + side) / 2.0);
+
+        (0..factor)
+            .map(|i| {
+                let (pixel_start, pixel_end) = (i as f32, (i + 1) as f32);
+                (end.min(pixel_end) - start.max(pixel_start)).max(0.0)
+            })
+            .collect()
+    }
+}
+
+impl RgbImage {
+    /// Returns this image downsampled by an integer factor, where every
+    /// output pixel integrates its footprint through the given aperture.
+    ///
+    /// Trailing rows and columns that don't fill a whole footprint are dropped.
+    pub fn downsample(&self, factor: u32, aperture: Aperture) -> Result<RgbImage, &'static str> {
+        if factor == 0 {
+            return Err("Supersampling factor must be at least 1.");
+        }
+
+        if let Aperture::FillFactor(fill_factor) = aperture {
+            if !(fill_factor > 0.0 && fill_factor <= 1.0) {
+                return Err("Fill factor must be greater than 0 and at most 1.");
+            }
+        }
+
+        let (width, height) = (self.width / factor, self.height / factor);
+        if width == 0 || height == 0 {
+            return Err("Image is smaller than the supersampling factor.");
+        }
+
+        let weights = aperture.weights(factor);
+        let total: f32 = weights.iter().sum::<f32>().powi(2);
+        let pixels = self.to_float();
+
+        let mut downsampled = Vec::with_capacity((width * height) as usize);
+
+        for y in 0..height {
+            for x in 0..width {
+                let mut sum = [0.0; 3];
+
+                for (dy, weight_y) in weights.iter().enumerate() {
+                    let row = (y * factor) as usize + dy;
+
+                    for (dx, weight_x) in weights.iter().enumerate() {
+                        let weight = weight_x * weight_y;
+                        if weight == 0.0 {
+                            continue;
+                        }
+
+                        let pixel = pixels[row * self.width as usize + (x * factor) as usize + dx];
+                        for channel in 0..3 {
+                            sum[channel] += pixel[channel] * weight;
+                        }
+                    }
+                }
+
+                downsampled.push([sum[0] / total, sum[1] / total, sum[2] / total]);
+            }
+        }
+
+        Ok(RgbImage::from_float(width, height, &downsampled))
+    }
+}
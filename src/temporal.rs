@@ -0,0 +1,112 @@
+// Author: Cláudio Gomes (TofuLynx)
+// Project: emubayer
+// License: GNU GPL Version 3 (https://www.gnu.org/licenses/gpl-3.0.en.html)
+
+use std::collections::BTreeMap;
+
+use super::{Camera, RgbImage};
+
+/// Tolerance of frame times, so that frames starting exactly at a scene
+/// frame aren't rounded down to the one before.
+const TIME_EPSILON: f64 = 1e-9;
+
+impl Camera {
+    /// Returns the image exposed by the frame whose first row starts its
+    /// exposure at `start` seconds into a scene, given as frames that each
+    /// last `1 / scene_rate` seconds. The scene holds its last frame past
+    /// its end.
+    ///
+    /// Every row of the sensor averages the scene over its exposure, which
+    /// starts later for later rows with a rolling shutter.
+    pub(crate) fn exposed_frame(
+        &self,
+        scene: &[RgbImage],
+        scene_rate: f64,
+        start: f64,
+    ) -> RgbImage {
+        let exposure_time = self.exposure_time().unwrap_or(0.0);
+        let readout_time = self.rolling_shutter().unwrap_or(0.0);
+        let last = scene.len() - 1;
+        let frame_at =
+            |time: f64| ((time * scene_rate + TIME_EPSILON).floor().max(0.0) as usize).min(last);
+
+        if exposure_time == 0.0 && readout_time == 0.0 {
+            return scene[frame_at(start)].clone();
+        }
+
+        // Rows are counted on the sensor, which sees the scene turned the
+        // other way.
+        let (width, height) = (scene[0].width, scene[0].height);
+        let orientation = self.orientation();
+        let (sensor_width, sensor_height) = orientation.displayed_size(width, height);
+
+        let row_weights: Vec<Vec<(usize, f32)>> = (0..sensor_height)
+            .map(|row| {
+                let row_start = if sensor_height > 1 {
+                    start + readout_time * f64::from(row) / f64::from(sensor_height - 1)
+                } else {
+                    start
+                };
+
+                if exposure_time == 0.0 {
+                    return vec![(frame_at(row_start), 1.0)];
+                }
+
+                // Scene frames weigh as much as they overlap the exposure.
+                let row_end = row_start + exposure_time;
+                let mut weights: Vec<(usize, f32)> = Vec::new();
+                let mut frame = (row_start * scene_rate + TIME_EPSILON).floor().max(0.0) as usize;
+
+                loop {
+                    let frame_start = frame as f64 / scene_rate;
+                    if frame_start >= row_end - TIME_EPSILON {
+                        break;
+                    }
+
+                    let overlap =
+                        row_end.min((frame + 1) as f64 / scene_rate) - row_start.max(frame_start);
+                    let weight = (overlap / exposure_time) as f32;
+                    match weights.last_mut() {
+                        Some(last_weight) if last_weight.0 == frame.min(last) => {
+                            last_weight.1 += weight
+                        }
+                        _ => weights.push((frame.min(last), weight)),
+                    }
+
+                    frame += 1;
+                }
+
+                weights
+            })
+            .collect();
+
+        let mut scene_pixels = BTreeMap::new();
+        for weights in &row_weights {
+            for &(frame, _) in weights {
+                scene_pixels
+                    .entry(frame)
+                    .or_insert_with(|| scene[frame].to_float());
+            }
+        }
+
+        let mut pixels = Vec::with_capacity((width * height) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let (_, row) = orientation.source(x, y, sensor_width, sensor_height);
+                let pixel = (y * width + x) as usize;
+                let mut sum = [0.0; 3];
+
+                for &(frame, weight) in &row_weights[row as usize] {
+                    let value = scene_pixels[&frame][pixel];
+                    for channel in 0..3 {
+                        sum[channel] += value[channel] * weight;
+                    }
+                }
+
+                pixels.push(sum);
+            }
+        }
+
+        RgbImage::from_float(width, height, &pixels)
+    }
+}
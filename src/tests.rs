@@ -3,6 +3,7 @@
 // License: GNU GPL Version 3 (https://www.gnu.org/licenses/gpl-3.0.en.html)
 
 use super::*;
+use byteorder::LittleEndian;
 
 fn get_input_vector_even() -> Vec<u8> {
     // R->1 G->2 B->3
@@ -697,3 +698,2176 @@ fn test_image_extract_gbrg_odd() {
         ]
     );
 }
+
+fn get_impulse_image() -> RgbImage {
+    // 5x5 black image with a single white pixel at its center.
+    let mut data = vec![0; 5 * 5 * 3];
+    for channel in 0..3 {
+        data[(2 * 5 + 2) * 3 + channel] = 255;
+    }
+
+    RgbImage {
+        width: 5,
+        height: 5,
+        data,
+        color_type: ColorType::RGB,
+        bit_depth: BitDepth::Eight,
+    }
+}
+
+#[test]
+fn test_optical_filter_olpf_four_dots() {
+    let rgb_image = get_impulse_image()
+        .apply_optical_filter(&OpticalFilter::Olpf { separation: 2.0 })
+        .unwrap();
+
+    for y in 0..5 {
+        for x in 0..5 {
+            let expected = if (x == 1 || x == 3) && (y == 1 || y == 3) {
+                (255u32 << 8) / 4
+            } else {
+                0
+            };
+            assert!((rgb_image.sample(x, y, 1) as i32 - expected as i32).abs() <= 1);
+        }
+    }
+}
+
+#[test]
+fn test_optical_filter_preserves_energy() {
+    for filter in &[
+        OpticalFilter::Olpf { separation: 1.3 },
+        OpticalFilter::Gaussian { sigma: 0.6 },
+        OpticalFilter::Airy { radius: 0.5 },
+        OpticalFilter::Defocus { radius: 1.0 },
+        // Too small to be sampled, which leaves the image as it is.
+        OpticalFilter::Gaussian { sigma: 0.001 },
+        OpticalFilter::Defocus { radius: 0.1 },
+    ] {
+        let rgb_image = get_impulse_image().apply_optical_filter(filter).unwrap();
+
+        let sum: u32 = (0..5)
+            .flat_map(|y| (0..5).map(move |x| (x, y)))
+            .map(|(x, y)| rgb_image.sample(x, y, 0) as u32)
+            .sum();
+        assert!((sum as i32 - (255 << 8)).abs() <= 25);
+    }
+}
+
+#[test]
+fn test_optical_filter_uniform_image_unchanged() {
+    let rgb_image = RgbImage {
+        width: 8,
+        height: 8,
+        data: get_input_vector_even(),
+        color_type: ColorType::RGB,
+        bit_depth: BitDepth::Eight,
+    };
+
+    let camera = Camera::new(BayerPattern::RGGB)
+        .with_optical_filter(OpticalFilter::Defocus { radius: 1.5 })
+        .with_optical_filter(OpticalFilter::Olpf { separation: 1.0 });
+    let raw_image = camera.capture(rgb_image).unwrap();
+
+    assert_eq!(&raw_image.data[0..4], &[1 << 8, 2 << 8, 1 << 8, 2 << 8]);
+    assert_eq!(&raw_image.data[8..12], &[2 << 8, 3 << 8, 2 << 8, 3 << 8]);
+
+    for &filter in &[
+        OpticalFilter::Gaussian { sigma: 0.0 },
+        OpticalFilter::Airy { radius: -1.0 },
+        OpticalFilter::Defocus { radius: f32::NAN },
+        // Wider than the 5x5 image.
+        OpticalFilter::Gaussian { sigma: 2.0 },
+        OpticalFilter::Defocus { radius: 1e30 },
+    ] {
+        assert!(get_impulse_image().apply_optical_filter(&filter).is_err());
+        assert!(Camera::new(BayerPattern::RGGB)
+            .with_optical_filter(filter)
+            .capture(get_impulse_image())
+            .is_err());
+    }
+}
+
+fn get_checkerboard_image(width: u32, height: u32) -> RgbImage {
+    // Alternating black and white pixels.
+    let mut data = Vec::with_capacity((width * height * 3) as usize);
+    for y in 0..height {
+        for x in 0..width {
+            let value = if (x + y) % 2 == 0 { 255 } else { 0 };
+            data.extend_from_slice(&[value, value, value]);
+        }
+    }
+
+    RgbImage {
+        width,
+        height,
+        data,
+        color_type: ColorType::RGB,
+        bit_depth: BitDepth::Eight,
+    }
+}
+
+#[test]
+fn test_supersampling_box_integrates_footprint() {
+    let camera = Camera::new(BayerPattern::RGGB).with_supersampling(2, Aperture::Box);
+    let raw_image = camera.capture(get_checkerboard_image(8, 8)).unwrap();
+
+    assert_eq!(raw_image.width, 4);
+    assert_eq!(raw_image.height, 4);
+    for &value in &raw_image.data {
+        assert!((value as i32 - (255 << 8) / 2).abs() <= 1);
+    }
+}
+
+#[test]
+fn test_supersampling_fill_factor() {
+    // With a 1/9 fill factor, each 3x3 photosite only sees its center pixel.
+    let rgb_image = get_checkerboard_image(6, 6)
+        .downsample(3, Aperture::FillFactor(1.0 / 9.0))
+        .unwrap();
+
+    assert_eq!(rgb_image.sample(0, 0, 0), 255 << 8);
+    assert_eq!(rgb_image.sample(1, 0, 0), 0);
+    assert_eq!(rgb_image.sample(0, 1, 0), 0);
+    assert_eq!(rgb_image.sample(1, 1, 0), 255 << 8);
+}
+
+#[test]
+fn test_supersampling_invalid_parameters() {
+    assert!(get_checkerboard_image(4, 4)
+        .downsample(0, Aperture::Box)
+        .is_err());
+    assert!(get_checkerboard_image(4, 4)
+        .downsample(5, Aperture::Box)
+        .is_err());
+    assert!(get_checkerboard_image(4, 4)
+        .downsample(2, Aperture::FillFactor(1.5))
+        .is_err());
+}
+
+#[test]
+fn test_image_extract_xtrans() {
+    let rgb_image = RgbImage {
+        width: 8,
+        height: 8,
+        data: get_input_vector_even(),
+        color_type: ColorType::RGB,
+        bit_depth: BitDepth::Eight,
+    };
+
+    let raw_image = rgb_image.to_raw(CfaPattern::xtrans());
+
+    let rows: Vec<Vec<u16>> = raw_image.data.chunks(8).map(|row| row.to_vec()).collect();
+    assert_eq!(
+        rows[0],
+        vec![
+            2 << 8,
+            2 << 8,
+            1 << 8,
+            2 << 8,
+            2 << 8,
+            3 << 8,
+            2 << 8,
+            2 << 8
+        ]
+    );
+    assert_eq!(
+        rows[2],
+        vec![
+            3 << 8,
+            1 << 8,
+            2 << 8,
+            1 << 8,
+            3 << 8,
+            2 << 8,
+            3 << 8,
+            1 << 8
+        ]
+    );
+    assert_eq!(
+        rows[5],
+        vec![
+            1 << 8,
+            3 << 8,
+            2 << 8,
+            3 << 8,
+            1 << 8,
+            2 << 8,
+            1 << 8,
+            3 << 8
+        ]
+    );
+    assert_eq!(rows[6], rows[0]);
+    assert_eq!(raw_image.cfa_pattern().unwrap().repeat_dim(), (6, 6));
+}
+
+#[test]
+fn test_cfa_pattern_from_str() {
+    assert_eq!(
+        "gbrg".parse::<CfaPattern>(),
+        Ok(CfaPattern::from(BayerPattern::GBRG))
+    );
+    assert_eq!("X-Trans".parse::<CfaPattern>(), Ok(CfaPattern::xtrans()));
+    assert_eq!(
+        "GGRGGB/GGBGGR/BRGRBG/GGBGGR/GGRGGB/RBGBRG".parse::<CfaPattern>(),
+        Ok(CfaPattern::xtrans())
+    );
+    assert!("RGB".parse::<CfaPattern>().is_err());
+    assert!("RG/G".parse::<CfaPattern>().is_err());
+    assert!("RGXB".parse::<CfaPattern>().is_err());
+    assert!("".parse::<CfaPattern>().is_err());
+}
+
+#[test]
+fn test_cfa_pattern_arbitrary_colors() {
+    let cfa_pattern: CfaPattern = "CYYM/GMCY".parse().unwrap();
+
+    assert_eq!(cfa_pattern.repeat_dim(), (2, 4));
+    assert_eq!(cfa_pattern.to_string(), "CYYM/GMCY");
+    assert_eq!(
+        cfa_pattern.plane_colors(),
+        vec![
+            CfaColor::Green,
+            CfaColor::Cyan,
+            CfaColor::Magenta,
+            CfaColor::Yellow
+        ]
+    );
+    assert_eq!(cfa_pattern.plane_indices(), vec![1, 3, 3, 2, 0, 2, 1, 3]);
+    assert_eq!(cfa_pattern.color_at(5, 3), CfaColor::Magenta);
+}
+
+#[test]
+fn test_cfa_pattern_rgb_planes() {
+    // Every RGB pattern refers to the same three planes, even if a color is missing.
+    let cfa_pattern: CfaPattern = "RG/GR".parse().unwrap();
+
+    assert_eq!(cfa_pattern.plane_indices(), vec![0, 1, 1, 0]);
+    assert_eq!(
+        CfaPattern::from(BayerPattern::BGGR).plane_indices(),
+        vec![2, 1, 1, 0]
+    );
+}
+
+#[test]
+fn test_cfa_pattern_quad_and_nona_bayer() {
+    let quad_bayer = CfaPattern::quad_bayer(BayerPattern::GRBG);
+    let nona_bayer: CfaPattern = "nona-rggb".parse().unwrap();
+
+    assert_eq!(quad_bayer, "GGRR/GGRR/BBGG/BBGG".parse().unwrap());
+    assert_eq!(quad_bayer.cell_size(), 2);
+    assert_eq!(nona_bayer.repeat_dim(), (6, 6));
+    assert_eq!(nona_bayer.cell_size(), 3);
+    assert_eq!(nona_bayer.color_at(2, 3), CfaColor::Green);
+    assert_eq!(nona_bayer.color_at(3, 3), CfaColor::Blue);
+    assert_eq!(CfaPattern::xtrans().cell_size(), 1);
+}
+
+#[test]
+fn test_bin_cells_quad_bayer() {
+    let rgb_image = RgbImage {
+        width: 8,
+        height: 8,
+        data: get_input_vector_even(),
+        color_type: ColorType::RGB,
+        bit_depth: BitDepth::Eight,
+    };
+
+    let raw_image = rgb_image.to_raw(CfaPattern::quad_bayer(BayerPattern::RGGB));
+    assert_eq!(&raw_image.data[0..4], &[1 << 8, 1 << 8, 2 << 8, 2 << 8]);
+
+    let binned = raw_image.bin_cells().unwrap();
+    assert_eq!(binned.width, 4);
+    assert_eq!(binned.height, 4);
+    assert_eq!(binned.layout, SensorLayout::from(BayerPattern::RGGB));
+    assert_eq!(&binned.data[0..4], &[4 << 8, 8 << 8, 4 << 8, 8 << 8]);
+    assert_eq!(&binned.data[4..8], &[8 << 8, 12 << 8, 8 << 8, 12 << 8]);
+
+    // Levels add up, and the default crop covers the same cells.
+    let mut raw_image = raw_image;
+    raw_image.white_level = 4095;
+    raw_image.default_crop = Region::new(1, 2, 5, 4);
+    let binned = raw_image.bin_cells().unwrap();
+    assert_eq!(binned.white_level(), 4 * 4095);
+    assert_eq!(binned.default_crop(), Region::new(0, 1, 3, 2));
+}
+
+#[test]
+fn test_bin_cells_saturates_and_rejects_bayer() {
+    let raw_image = get_checkerboard_image(6, 6).to_raw(CfaPattern::nona_bayer(BayerPattern::RGGB));
+    assert!(raw_image
+        .bin_cells()
+        .unwrap()
+        .data
+        .iter()
+        .all(|&value| value == u16::MAX));
+
+    let raw_image = get_checkerboard_image(6, 6).to_raw(BayerPattern::RGGB);
+    assert!(raw_image.bin_cells().is_err());
+}
+
+#[test]
+fn test_automotive_patterns() {
+    assert_eq!("rccb".parse::<CfaPattern>(), Ok(CfaPattern::rccb()));
+    assert_eq!(CfaPattern::rccc().to_string(), "RWWW");
+    assert_eq!(
+        CfaPattern::rccb().plane_colors(),
+        vec![CfaColor::Red, CfaColor::Blue, CfaColor::White]
+    );
+    assert_eq!(CfaPattern::rccb().plane_indices(), vec![0, 2, 2, 1]);
+    // Outside of the automotive names, C still means Cyan.
+    assert_eq!(
+        "RC/CB".parse::<CfaPattern>().unwrap().color_at(1, 0),
+        CfaColor::Cyan
+    );
+}
+
+#[test]
+fn test_white_synthesis() {
+    let rgb_image = || RgbImage {
+        width: 8,
+        height: 8,
+        data: get_input_vector_even(),
+        color_type: ColorType::RGB,
+        bit_depth: BitDepth::Eight,
+    };
+
+    // Default white response is Rec. 709 luminance: 0.2126 + 1.4304 + 0.2166 = 1.8596.
+    let raw_image = rgb_image().to_raw("RGBW".parse::<CfaPattern>().unwrap());
+    assert_eq!(&raw_image.data[0..2], &[1 << 8, 2 << 8]);
+    assert_eq!(&raw_image.data[8..10], &[3 << 8, 476]);
+
+    let responses = FilterResponses::new().with_response(CfaColor::White, [1.0, 1.0, 1.0]);
+    let raw_image = rgb_image().to_raw_with_responses(CfaPattern::rccc(), &responses);
+    assert_eq!(&raw_image.data[0..2], &[1 << 8, 6 << 8]);
+}
+
+#[test]
+fn test_spectral_response() {
+    let responses = FilterResponses::new()
+        .with_spectral_response(CfaColor::White, &[(300.0, 1.0), (800.0, 1.0)])
+        .unwrap()
+        .with_spectral_response(
+            CfaColor::Red,
+            &[(300.0, 0.0), (560.0, 0.0), (580.0, 1.0), (800.0, 1.0)],
+        )
+        .unwrap();
+
+    for &weight in &responses.response(CfaColor::White) {
+        assert!((weight - 1.0).abs() < 1e-3);
+    }
+
+    let red = responses.response(CfaColor::Red);
+    assert!(red[0] > 0.75 && red[1] < 0.3 && red[2] < 1e-3);
+
+    assert!(FilterResponses::new()
+        .with_spectral_response(CfaColor::White, &[(500.0, 1.0), (400.0, 1.0)])
+        .is_err());
+}
+
+#[test]
+fn test_complementary_patterns() {
+    let rgb_image = || RgbImage {
+        width: 8,
+        height: 8,
+        data: get_input_vector_even(),
+        color_type: ColorType::RGB,
+        bit_depth: BitDepth::Eight,
+    };
+
+    assert_eq!("cygm".parse::<CfaPattern>(), Ok(CfaPattern::cygm()));
+    assert_eq!(CfaPattern::cygm().repeat_dim(), (4, 2));
+    assert_eq!(
+        CfaPattern::cygm().plane_indices(),
+        vec![1, 3, 0, 2, 1, 3, 2, 0]
+    );
+    assert_eq!(CfaPattern::cmy().plane_indices(), vec![0, 2, 2, 1]);
+
+    // Cyan sees (G + B) / 2, Yellow sees (R + G) / 2 and Magenta sees (R + B) / 2.
+    let raw_image = rgb_image().to_raw(CfaPattern::cygm());
+    assert_eq!(&raw_image.data[0..2], &[640, 384]);
+    assert_eq!(&raw_image.data[8..10], &[2 << 8, 2 << 8]);
+    assert_eq!(&raw_image.data[24..26], &[2 << 8, 2 << 8]);
+
+    let responses = FilterResponses::new().with_complementary_gain(1.0);
+    let raw_image = rgb_image().to_raw_with_responses(CfaPattern::cmy(), &responses);
+    assert_eq!(&raw_image.data[0..2], &[5 << 8, 3 << 8]);
+    assert_eq!(&raw_image.data[8..10], &[3 << 8, 4 << 8]);
+}
+
+#[test]
+fn test_monochrome_and_linear_raw() {
+    let rgb_image = || RgbImage {
+        width: 9,
+        height: 9,
+        data: get_input_vector_odd(),
+        color_type: ColorType::RGB,
+        bit_depth: BitDepth::Eight,
+    };
+
+    let raw_image = rgb_image().to_monochrome([0.5, 0.25, 0.25]);
+    assert_eq!((raw_image.width, raw_image.height), (9, 9));
+    assert_eq!(raw_image.layout.samples_per_pixel(), 1);
+    assert!(raw_image.data.iter().all(|&value| value == 448));
+
+    let raw_image = rgb_image().to_linear_raw();
+    assert_eq!(raw_image.data.len(), 9 * 9 * 3);
+    assert_eq!(
+        &raw_image.data[0..6],
+        &[1 << 8, 2 << 8, 3 << 8, 1 << 8, 2 << 8, 3 << 8]
+    );
+    assert!(raw_image.bin_cells().is_err());
+}
+
+#[test]
+fn test_sensor_layout_from_str() {
+    assert_eq!("mono".parse::<SensorLayout>(), Ok(SensorLayout::Monochrome));
+    assert_eq!(
+        "LinearRaw".parse::<SensorLayout>(),
+        Ok(SensorLayout::LinearRaw)
+    );
+    assert_eq!(
+        "xtrans".parse::<SensorLayout>(),
+        Ok(SensorLayout::Cfa(CfaPattern::xtrans()))
+    );
+    assert!("linear-raw".parse::<SensorLayout>().is_err());
+}
+
+#[test]
+fn test_demosaic_uniform_image() {
+    let rgb_image = || RgbImage {
+        width: 8,
+        height: 8,
+        data: get_input_vector_even(),
+        color_type: ColorType::RGB,
+        bit_depth: BitDepth::Eight,
+    };
+
+    let bayer_patterns = [
+        BayerPattern::RGGB,
+        BayerPattern::BGGR,
+        BayerPattern::GRBG,
+        BayerPattern::GBRG,
+    ];
+
+    for &bayer_pattern in bayer_patterns.iter() {
+        let raw_image = rgb_image().to_raw(bayer_pattern);
+
+        for algorithm in DemosaicAlgorithm::all() {
+            let demosaiced = raw_image.demosaic(algorithm).unwrap();
+            assert_eq!((demosaiced.width, demosaiced.height), (8, 8));
+
+            for y in 0..8 {
+                for x in 0..8 {
+                    for channel in 0..3 {
+                        assert_eq!(
+                            demosaiced.sample(x, y, channel),
+                            (channel as u16 + 1) << 8,
+                            "{} on {}",
+                            algorithm,
+                            bayer_pattern
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    let raw_image = rgb_image().to_raw(CfaPattern::xtrans());
+    let demosaiced = raw_image.demosaic(DemosaicAlgorithm::Nearest).unwrap();
+    assert_eq!(demosaiced.sample(3, 5, 2), 3 << 8);
+    let demosaiced = raw_image.demosaic(DemosaicAlgorithm::Bilinear).unwrap();
+    assert_eq!(demosaiced.sample(4, 2, 0), 1 << 8);
+}
+
+#[test]
+fn test_demosaic_errors() {
+    let rgb_image = || RgbImage {
+        width: 8,
+        height: 8,
+        data: get_input_vector_even(),
+        color_type: ColorType::RGB,
+        bit_depth: BitDepth::Eight,
+    };
+
+    assert!(rgb_image()
+        .to_raw(CfaPattern::cygm())
+        .demosaic(DemosaicAlgorithm::Bilinear)
+        .is_err());
+    assert!(rgb_image()
+        .to_raw(CfaPattern::xtrans())
+        .demosaic(DemosaicAlgorithm::MalvarHeCutler)
+        .is_err());
+    assert!(rgb_image()
+        .to_raw(CfaPattern::quad_bayer(BayerPattern::RGGB))
+        .demosaic(DemosaicAlgorithm::Ahd)
+        .is_err());
+
+    // A tile without blue, and levels with no range between them.
+    let no_blue = CfaPattern::new(
+        2,
+        2,
+        vec![
+            CfaColor::Red,
+            CfaColor::Green,
+            CfaColor::Green,
+            CfaColor::Red,
+        ],
+    )
+    .unwrap();
+    for &algorithm in &[DemosaicAlgorithm::Nearest, DemosaicAlgorithm::Bilinear] {
+        assert!(rgb_image()
+            .to_raw(no_blue.clone())
+            .demosaic(algorithm)
+            .is_err());
+    }
+
+    // The top-left 2x2 photosites of an X-Trans tile are all green.
+    let tiny = get_checkerboard_image(2, 2).to_raw(CfaPattern::xtrans());
+    let small = get_checkerboard_image(6, 6).to_raw(CfaPattern::xtrans());
+    for &algorithm in &[DemosaicAlgorithm::Nearest, DemosaicAlgorithm::Bilinear] {
+        assert!(tiny.demosaic(algorithm).is_err());
+        assert!(small.demosaic(algorithm).is_ok());
+    }
+
+    let mut flat = rgb_image().to_raw(BayerPattern::RGGB);
+    flat.white_level = flat.black_level;
+    assert!(flat.demosaic(DemosaicAlgorithm::Bilinear).is_err());
+
+    let demosaiced = rgb_image()
+        .to_monochrome([0.5, 0.25, 0.25])
+        .demosaic(DemosaicAlgorithm::Vng)
+        .unwrap();
+    assert_eq!(demosaiced.sample(0, 0, 0), demosaiced.sample(0, 0, 2));
+
+    assert_eq!("MHC".parse(), Ok(DemosaicAlgorithm::MalvarHeCutler));
+    assert!("ppg".parse::<DemosaicAlgorithm>().is_err());
+}
+
+#[test]
+fn test_save_as_png() {
+    let rgb_image = get_checkerboard_image(6, 4)
+        .to_raw(BayerPattern::RGGB)
+        .demosaic(DemosaicAlgorithm::Bilinear)
+        .unwrap();
+
+    let path = std::env::temp_dir().join("emubayer_test_save_as_png.png");
+    rgb_image.save_as_png(&path).unwrap();
+    let read_image = RgbImage::from_file(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!((read_image.width, read_image.height), (6, 4));
+    assert_eq!(read_image.bit_depth.to_u32(), 16);
+    assert_eq!(read_image.data, rgb_image.data);
+}
+
+#[test]
+fn test_ciede2000() {
+    // Pairs from Sharma, Wu and Dalal's CIEDE2000 test data.
+    let pairs = [
+        ([50.0, 2.6772, -79.7751], [50.0, 0.0, -82.7485], 2.0425),
+        ([50.0, 0.0, 0.0], [50.0, -1.0, 2.0], 2.3669),
+        ([50.0, 2.5, 0.0], [73.0, 25.0, -18.0], 27.1492),
+        ([2.0776, 0.0795, -1.135], [0.9033, -0.0636, -0.5514], 0.9082),
+    ];
+
+    for &(lab1, lab2, expected) in pairs.iter() {
+        assert!((metrics::ciede2000(&lab1, &lab2) - expected).abs() < 1e-4);
+        assert!((metrics::ciede2000(&lab2, &lab1) - expected).abs() < 1e-4);
+    }
+
+    let white = metrics::linear_to_lab([1.0, 1.0, 1.0]);
+    assert!((white[0] - 100.0).abs() < 1e-3 && white[1].abs() < 1e-3 && white[2].abs() < 1e-3);
+}
+
+#[test]
+fn test_compare() {
+    let original = get_checkerboard_image(8, 8);
+    let whole = Region::new(0, 0, 8, 8);
+
+    let metrics = original.compare(&original, whole).unwrap();
+    assert!(metrics.psnr.is_infinite());
+    assert_eq!(metrics.ssim, 1.0);
+    assert_eq!((metrics.ciede2000_max, metrics.zipper), (0.0, 0.0));
+
+    // Demosaicing a checkerboard smears it into colored stripes.
+    let reconstructed = get_checkerboard_image(8, 8)
+        .to_raw(BayerPattern::RGGB)
+        .demosaic(DemosaicAlgorithm::Bilinear)
+        .unwrap();
+    let metrics = original.compare(&reconstructed, whole).unwrap();
+    assert!(metrics.psnr < 10.0);
+    assert!(metrics.ssim < 0.5);
+    assert!(metrics.false_color > 0.5);
+    assert!(metrics
+        .to_json()
+        .starts_with("{\"region\":{\"x\":0,\"y\":0,\"width\":8,"));
+
+    assert!(original
+        .compare(&original, Region::new(4, 4, 5, 4))
+        .is_err());
+    assert!(original
+        .compare(&original, Region::new(u32::MAX, 0, 2, 2))
+        .is_err());
+    assert!(get_checkerboard_image(8, 8)
+        .crop(Region::new(0, u32::MAX, 2, u32::MAX))
+        .is_err());
+    assert!(original
+        .compare(&get_checkerboard_image(6, 8), whole)
+        .is_err());
+
+    let grid = Region::grid(8, 7, 3, 2);
+    assert_eq!(grid.len(), 6);
+    assert_eq!(grid[0], Region::new(0, 0, 2, 3));
+    assert_eq!(grid[5], Region::new(4, 3, 4, 4));
+}
+
+#[test]
+fn test_noise() {
+    let rgb_image = || RgbImage {
+        width: 8,
+        height: 8,
+        data: get_input_vector_even(),
+        color_type: ColorType::RGB,
+        bit_depth: BitDepth::Eight,
+    };
+    let raw_image = rgb_image().to_monochrome([0.0, 1.0, 0.0]);
+
+    assert_eq!(raw_image.apply_noise(&Noise::new()).unwrap(), raw_image);
+
+    let noise = Noise::new().with_read_noise(16.0).with_seed(7);
+    let noisy = raw_image.apply_noise(&noise).unwrap();
+    assert_eq!(noisy, raw_image.apply_noise(&noise).unwrap());
+    assert_ne!(noisy, raw_image.apply_noise(&noise.with_seed(8)).unwrap());
+
+    let mean = noisy
+        .data
+        .iter()
+        .map(|&value| f64::from(value))
+        .sum::<f64>()
+        / 64.0;
+    let variance = noisy
+        .data
+        .iter()
+        .map(|&value| (f64::from(value) - mean).powi(2))
+        .sum::<f64>()
+        / 64.0;
+    assert!((mean - 512.0).abs() < 8.0);
+    assert!(variance.sqrt() > 8.0 && variance.sqrt() < 32.0);
+
+    // 512 of 65535 with a full well of 512 electrons is 4 electrons, whose
+    // shot noise is 2 electrons, or 256 digital numbers.
+    let noisy = raw_image
+        .apply_noise(&Noise::new().with_shot_noise(512.0))
+        .unwrap();
+    assert!(noisy.data.iter().any(|&value| value > 512 + 128));
+
+    for &full_well in &[0.0, f32::NAN, f32::INFINITY] {
+        assert!(raw_image
+            .apply_noise(&Noise::new().with_shot_noise(full_well))
+            .is_err());
+    }
+    for &sigma in &[-1.0, f32::NAN, f32::INFINITY] {
+        assert!(raw_image
+            .apply_noise(&Noise::new().with_read_noise(sigma))
+            .is_err());
+    }
+}
+
+#[test]
+fn test_ground_truth_matches_capture() {
+    let rgb_image = || RgbImage {
+        width: 9,
+        height: 9,
+        data: get_input_vector_odd(),
+        color_type: ColorType::RGB,
+        bit_depth: BitDepth::Eight,
+    };
+
+    let camera = Camera::new(BayerPattern::GRBG).with_noise(Noise::new().with_read_noise(1.0));
+    let ground_truth = camera.ground_truth(rgb_image()).unwrap();
+    let raw_image = camera.capture(rgb_image()).unwrap();
+
+    assert_eq!((ground_truth.width, ground_truth.height), (8, 8));
+    assert_eq!(ground_truth.data, &get_input_vector_even()[..]);
+    assert_ne!(raw_image, rgb_image().to_raw(BayerPattern::GRBG));
+
+    let demosaiced = raw_image.demosaic(DemosaicAlgorithm::Bilinear).unwrap();
+    let metrics = ground_truth
+        .compare(&demosaiced, Region::new(0, 0, 8, 8))
+        .unwrap();
+    assert!(metrics.psnr > 30.0 && metrics.psnr.is_finite());
+
+    let camera = Camera::new(SensorLayout::LinearRaw);
+    assert_eq!(camera.ground_truth(rgb_image()).unwrap().width, 9);
+}
+
+#[test]
+fn test_dng_round_trip() {
+    let rgb_image = || RgbImage {
+        width: 8,
+        height: 8,
+        data: get_input_vector_even(),
+        color_type: ColorType::RGB,
+        bit_depth: BitDepth::Eight,
+    };
+
+    let raw_images = vec![
+        rgb_image().to_raw(BayerPattern::GBRG),
+        rgb_image().to_raw(CfaPattern::cygm()),
+        rgb_image().to_monochrome(REC709_LUMA),
+        rgb_image().to_linear_raw(),
+        rgb_image()
+            .to_raw(CfaPattern::xtrans())
+            .apply_noise(&Noise::new().with_read_noise(100.0))
+            .unwrap(),
+    ];
+
+    let path = std::env::temp_dir().join("emubayer_test_dng_round_trip.dng");
+    for raw_image in raw_images {
+        raw_image.save_as_dng(&path).unwrap();
+        let read_image = RawImage::from_dng(&path).unwrap();
+
+        assert_eq!((read_image.width, read_image.height), (8, 8));
+        assert_eq!(read_image.layout, raw_image.layout);
+        assert_eq!(read_image.data, raw_image.data);
+        assert_eq!(
+            (read_image.black_level(), read_image.white_level()),
+            (0, 65535)
+        );
+    }
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_dng_packed_big_endian() {
+    // 4x2 RGGB image with 12-bit samples packed from the most significant bit.
+    let samples: [u16; 8] = [64, 100, 4095, 0, 1, 2, 3, 2048];
+    let mut pixel_bytes = Vec::new();
+    for pair in samples.chunks(2) {
+        pixel_bytes.push((pair[0] >> 4) as u8);
+        pixel_bytes.push(((pair[0] & 15) << 4 | pair[1] >> 8) as u8);
+        pixel_bytes.push((pair[1] & 255) as u8);
+    }
+
+    // Tag, type, count and value of every entry, with values of up to 4
+    // bytes stored in the entry.
+    let entries: Vec<(u16, u16, u32, Vec<u8>)> = vec![
+        (254, 4, 1, vec![0, 0, 0, 0]),
+        (256, 3, 1, vec![0, 4, 0, 0]),
+        (257, 3, 1, vec![0, 2, 0, 0]),
+        (258, 3, 1, vec![0, 12, 0, 0]),
+        (259, 3, 1, vec![0, 1, 0, 0]),
+        (262, 3, 1, vec![0x80, 0x23, 0, 0]),
+        (273, 4, 1, vec![0, 0, 0, 194]),
+        (277, 3, 1, vec![0, 1, 0, 0]),
+        (278, 3, 1, vec![0, 2, 0, 0]),
+        (279, 4, 1, vec![0, 0, 0, 12]),
+        (0x828D, 3, 2, vec![0, 2, 0, 2]),
+        (0x828E, 1, 4, vec![0, 1, 1, 2]),
+        (0xC612, 1, 4, vec![1, 4, 0, 0]),
+        (0xC61A, 3, 1, vec![0, 64, 0, 0]),
+        (0xC61D, 3, 1, vec![0x0F, 0xFF, 0, 0]),
+    ];
+
+    let mut file = b"MM\0*".to_vec();
+    file.write_u32::<BigEndian>(8).unwrap();
+    file.write_u16::<BigEndian>(entries.len() as u16).unwrap();
+    for (tag, field_type, count, value) in entries {
+        file.write_u16::<BigEndian>(tag).unwrap();
+        file.write_u16::<BigEndian>(field_type).unwrap();
+        file.write_u32::<BigEndian>(count).unwrap();
+        file.extend(value);
+    }
+    file.write_u32::<BigEndian>(0).unwrap();
+    assert_eq!(file.len(), 194);
+    file.extend(pixel_bytes);
+
+    let path = std::env::temp_dir().join("emubayer_test_dng_packed_big_endian.dng");
+    std::fs::write(&path, file).unwrap();
+    let raw_image = RawImage::from_dng(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(raw_image.data, samples);
+    assert_eq!(raw_image.cfa_pattern(), Some(&BayerPattern::RGGB.into()));
+    assert_eq!(
+        (raw_image.black_level(), raw_image.white_level()),
+        (64, 4095)
+    );
+
+    // The black level maps to 0 and the white level to the maximum.
+    let demosaiced = raw_image.demosaic(DemosaicAlgorithm::Nearest).unwrap();
+    assert_eq!(demosaiced.sample(0, 0, 0), 0);
+    assert_eq!(demosaiced.sample(0, 0, 2), 0);
+    assert_eq!(demosaiced.sample(2, 0, 0), 65535);
+}
+
+#[test]
+fn test_ground_truth_export() {
+    let rgb_image = RgbImage {
+        width: 2,
+        height: 1,
+        data: vec![255, 188, 0, 128, 10, 255],
+        color_type: ColorType::RGB,
+        bit_depth: BitDepth::Eight,
+    };
+
+    // sRGB 188 is about half of the linear light of 255, and 10 is in the linear segment.
+    let linear = rgb_image.linearize_srgb();
+    assert!(linear.sample(0, 0, 0) > 64900);
+    assert!((linear.sample(0, 0, 1) as i32 - 32768).abs() < 400);
+    assert_eq!(linear.sample(0, 0, 2), 0);
+    assert_eq!(linear.sample(1, 0, 1), 198);
+
+    let camera = Camera::new(CfaPattern::quad_bayer(BayerPattern::RGGB))
+        .with_optical_filter(OpticalFilter::Olpf { separation: 0.5 })
+        .with_supersampling(2, Aperture::FillFactor(0.5))
+        .with_cell_binning()
+        .with_noise(Noise::new().with_shot_noise(1000.0).with_seed(3));
+    let json = camera.to_json();
+
+    assert!(json.starts_with("{\"layout\":\"RRGG/RRGG/GGBB/GGBB\","));
+    assert!(json.contains("\"W\":[0.2126,0.7152,0.0722]"));
+    assert!(json.contains("\"optical_filters\":[{\"type\":\"olpf\",\"separation\":0.5}]"));
+    assert!(json.contains("\"supersampling\":{\"factor\":2,\"fill_factor\":0.5}"));
+    assert!(json.ends_with(
+        "\"cell_binning\":true,\"noise\":{\"full_well\":1000,\"read_noise\":0,\"seed\":3},\"readout_mode\":null,\"frame_rate\":null,\"exposure_time\":null,\"rolling_shutter\":null,\"odd_size_policy\":\"crop\",\"readout_window\":null,\"orientation\":\"normal\",\"baked_orientation\":false}"
+    ));
+
+    // JSON has no NaN or infinities.
+    let json = Camera::new(BayerPattern::RGGB)
+        .with_optical_filter(OpticalFilter::Gaussian { sigma: f32::NAN })
+        .with_filter_responses(
+            FilterResponses::new().with_response(CfaColor::White, [f32::INFINITY, 0.0, 0.0]),
+        )
+        .to_json();
+    assert!(json.contains("\"W\":[null,0,0]"));
+    assert!(json.contains("{\"type\":\"gaussian\",\"sigma\":null}"));
+}
+
+#[test]
+fn test_odd_size_policy() {
+    // 3x3 image whose red channel holds the pixel index.
+    let rgb_image = || RgbImage {
+        width: 3,
+        height: 3,
+        data: (0..9).flat_map(|i| vec![i, 100, 200]).collect(),
+        color_type: ColorType::RGB,
+        bit_depth: BitDepth::Eight,
+    };
+    let red_channel =
+        |image: &RgbImage| -> Vec<u8> { image.data.iter().step_by(3).cloned().collect() };
+
+    let (cropped, region) = rgb_image().to_even_size(OddSizePolicy::Crop).unwrap();
+    assert_eq!(red_channel(&cropped), vec![0, 1, 3, 4]);
+    assert_eq!(region, Region::new(0, 0, 2, 2));
+
+    let (padded, region) = rgb_image().to_even_size(OddSizePolicy::Pad).unwrap();
+    assert_eq!(
+        red_channel(&padded),
+        vec![0, 1, 2, 2, 3, 4, 5, 5, 6, 7, 8, 8, 6, 7, 8, 8]
+    );
+    assert_eq!(region, Region::new(0, 0, 3, 3));
+
+    let (mirrored, _) = rgb_image().to_even_size(OddSizePolicy::Mirror).unwrap();
+    assert_eq!(
+        red_channel(&mirrored),
+        vec![0, 1, 2, 1, 3, 4, 5, 4, 6, 7, 8, 7, 3, 4, 5, 4]
+    );
+
+    assert!(rgb_image().to_even_size(OddSizePolicy::Error).is_err());
+    assert_eq!("Mirror".parse::<OddSizePolicy>(), Ok(OddSizePolicy::Mirror));
+    assert!("wrap".parse::<OddSizePolicy>().is_err());
+
+    // Padded captures keep the original image area through DNG files and demosaicing.
+    let camera = Camera::new(BayerPattern::RGGB).with_odd_size_policy(OddSizePolicy::Pad);
+    let raw_image = camera.capture(rgb_image()).unwrap();
+    assert_eq!((raw_image.width(), raw_image.height()), (4, 4));
+    assert_eq!(raw_image.default_crop(), Region::new(0, 0, 3, 3));
+
+    let path = std::env::temp_dir().join("emubayer_test_odd_size_policy.dng");
+    raw_image.save_as_dng(&path).unwrap();
+    let read_image = RawImage::from_dng(&path).unwrap();
+    assert_eq!(read_image, raw_image);
+
+    // Crops running past the largest offsets are rejected.
+    let mut overflowing = raw_image.clone();
+    overflowing.default_crop = Region::new(u32::MAX, 0, 2, 2);
+    overflowing.save_as_dng(&path).unwrap();
+    assert!(RawImage::from_dng(&path).is_err());
+    std::fs::remove_file(&path).unwrap();
+
+    // Unwritable paths, here below a file, are reported rather than panicking.
+    raw_image.save_as_dng(&path).unwrap();
+    assert!(raw_image.save_as_dng(path.join("odd_size.dng")).is_err());
+    std::fs::remove_file(&path).unwrap();
+
+    let demosaiced = read_image.demosaic(DemosaicAlgorithm::Bilinear).unwrap();
+    let ground_truth = camera.ground_truth(rgb_image()).unwrap();
+    assert_eq!((demosaiced.width(), demosaiced.height()), (3, 3));
+    assert_eq!((ground_truth.width(), ground_truth.height()), (3, 3));
+
+    let camera = camera.with_odd_size_policy(OddSizePolicy::Error);
+    assert!(camera.capture(rgb_image()).is_err());
+    assert!(camera.ground_truth(rgb_image()).is_err());
+}
+
+#[test]
+fn test_readout_window() {
+    let rgb_image = || RgbImage {
+        width: 8,
+        height: 8,
+        data: get_input_vector_even(),
+        color_type: ColorType::RGB,
+        bit_depth: BitDepth::Eight,
+    };
+
+    let phases = [
+        (
+            BayerPattern::RGGB,
+            [BayerPattern::GRBG, BayerPattern::GBRG, BayerPattern::BGGR],
+        ),
+        (
+            BayerPattern::BGGR,
+            [BayerPattern::GBRG, BayerPattern::GRBG, BayerPattern::RGGB],
+        ),
+        (
+            BayerPattern::GRBG,
+            [BayerPattern::RGGB, BayerPattern::BGGR, BayerPattern::GBRG],
+        ),
+        (
+            BayerPattern::GBRG,
+            [BayerPattern::BGGR, BayerPattern::RGGB, BayerPattern::GRBG],
+        ),
+    ];
+
+    for &(bayer_pattern, [odd_x, odd_y, odd_xy]) in &phases {
+        assert_eq!(bayer_pattern.shifted(2, 4), bayer_pattern);
+        assert_eq!(bayer_pattern.shifted(1, 0), odd_x);
+        assert_eq!(bayer_pattern.shifted(3, 2), odd_x);
+        assert_eq!(bayer_pattern.shifted(0, 1), odd_y);
+        assert_eq!(bayer_pattern.shifted(1, 1), odd_xy);
+
+        let full_sensor = rgb_image().to_raw(bayer_pattern);
+        for &(x, y) in &[(0, 0), (1, 0), (0, 1), (1, 1), (3, 2)] {
+            let window = Region::new(x, y, 4, 4);
+            let shifted = bayer_pattern.shifted(x, y);
+            assert_eq!(
+                CfaPattern::from(bayer_pattern).shifted(x, y),
+                CfaPattern::from(shifted)
+            );
+
+            // Every photosite of the window sees the same color and value as
+            // on the full sensor.
+            let raw_image = rgb_image().to_raw_window(bayer_pattern, window).unwrap();
+            assert_eq!(raw_image.cfa_pattern(), Some(&shifted.into()));
+            assert_eq!(full_sensor.crop(window).unwrap(), raw_image);
+            for row in 0..4 {
+                for column in 0..4 {
+                    assert_eq!(
+                        raw_image.data[(row * 4 + column) as usize],
+                        full_sensor.data[((row + y) * 8 + column + x) as usize]
+                    );
+                }
+            }
+        }
+    }
+
+    // X-Trans windows follow the 6x6 tile.
+    let xtrans = CfaPattern::xtrans();
+    let shifted = xtrans.shifted(4, 1);
+    for (x, y) in (0..6).flat_map(|y| (0..6).map(move |x| (x, y))) {
+        assert_eq!(shifted.color_at(x, y), xtrans.color_at(x + 4, y + 1));
+    }
+
+    // Cropping keeps the part of the default crop inside the window.
+    let mut raw_image = rgb_image().to_linear_raw();
+    raw_image.default_crop = Region::new(1, 1, 5, 5);
+    let cropped = raw_image.crop(Region::new(2, 0, 6, 4)).unwrap();
+    assert_eq!(cropped.layout, SensorLayout::LinearRaw);
+    assert_eq!(cropped.default_crop(), Region::new(0, 1, 4, 3));
+    assert_eq!(&cropped.data[..3], &raw_image.data[6..9]);
+    assert!(raw_image.crop(Region::new(4, 4, 5, 4)).is_err());
+    assert!(rgb_image().crop(Region::new(0, 0, 0, 4)).is_err());
+
+    let camera = Camera::new(BayerPattern::RGGB).with_readout_window(Region::new(1, 2, 4, 4));
+    let raw_image = camera.capture(rgb_image()).unwrap();
+    assert_eq!(raw_image.cfa_pattern(), Some(&BayerPattern::GRBG.into()));
+    assert_eq!(camera.ground_truth(rgb_image()).unwrap().width(), 4);
+    assert!(camera
+        .to_json()
+        .contains("\"readout_window\":{\"x\":1,\"y\":2,\"width\":4,\"height\":4},"));
+
+    let camera = Camera::new(CfaPattern::quad_bayer(BayerPattern::RGGB))
+        .with_cell_binning()
+        .with_readout_window(Region::new(1, 0, 4, 4));
+    assert!(camera.capture(rgb_image()).is_err());
+}
+
+#[test]
+fn test_orientation() {
+    // 6x4 image whose red channel holds the pixel index.
+    let rgb_image = || RgbImage {
+        width: 6,
+        height: 4,
+        data: (0..24)
+            .flat_map(|i| vec![i * 10, 255 - i * 10, i])
+            .collect(),
+        color_type: ColorType::RGB,
+        bit_depth: BitDepth::Eight,
+    };
+
+    // A 2x1 image turned clockwise stands as a column, top pixel first.
+    let pair = RgbImage {
+        width: 2,
+        height: 1,
+        data: vec![1, 1, 1, 2, 2, 2],
+        color_type: ColorType::RGB,
+        bit_depth: BitDepth::Eight,
+    };
+    let turned = pair.oriented(Orientation::Rotate90);
+    assert_eq!((turned.width(), turned.height()), (1, 2));
+    assert_eq!(turned.data, vec![1, 1, 1, 2, 2, 2]);
+
+    let turned = rgb_image().oriented(Orientation::Rotate90);
+    assert_eq!(turned.data[0], 180);
+    assert_eq!(turned.data[3 * 3], 0);
+
+    for tag in 1..=8 {
+        let orientation = Orientation::from_tag(tag).unwrap();
+        assert_eq!(orientation.tag(), tag);
+        assert_eq!(orientation.to_string().parse(), Ok(orientation));
+        assert_eq!(tag.to_string().parse(), Ok(orientation));
+
+        let oriented = rgb_image().oriented(orientation);
+        assert_eq!(oriented.width() == 4, orientation.swaps_axes());
+        assert_eq!(
+            oriented.clone().oriented(orientation.inverse()).data,
+            rgb_image().data
+        );
+
+        // Baking the orientation into the mosaic is the same as mosaicing
+        // the upright image with the adjusted pattern.
+        for cfa_pattern in [CfaPattern::from(BayerPattern::RGGB), CfaPattern::xtrans()] {
+            let mut raw_image = rgb_image()
+                .to_raw(cfa_pattern)
+                .with_orientation(orientation);
+            raw_image.default_crop = Region::new(1, 0, 2, 3);
+
+            let baked = raw_image.apply_orientation();
+            let expected = oriented
+                .clone()
+                .to_raw(baked.cfa_pattern().unwrap().clone());
+            assert_eq!(baked.data, expected.data);
+            assert_eq!(baked.orientation(), Orientation::Normal);
+
+            let crop = baked.default_crop();
+            assert_eq!(crop.width * crop.height, 6);
+            assert_eq!(
+                (crop.width == 3, crop.x + crop.width <= baked.width()),
+                (orientation.swaps_axes(), true)
+            );
+        }
+    }
+
+    let baked = rgb_image()
+        .to_raw(BayerPattern::RGGB)
+        .with_orientation(Orientation::Rotate90)
+        .apply_orientation();
+    assert_eq!(baked.cfa_pattern(), Some(&BayerPattern::GRBG.into()));
+    assert!("rotate-45".parse::<Orientation>().is_err());
+    assert!("9".parse::<Orientation>().is_err());
+
+    // A camera turned to portrait records the orientation, and its baked
+    // captures match the recorded ones once oriented.
+    let camera = Camera::new(BayerPattern::RGGB).with_orientation(Orientation::Rotate270);
+    let raw_image = camera.capture(rgb_image()).unwrap();
+    assert_eq!((raw_image.width(), raw_image.height()), (4, 6));
+    assert_eq!(raw_image.orientation(), Orientation::Rotate270);
+    assert_eq!(
+        camera
+            .ground_truth(rgb_image())
+            .unwrap()
+            .oriented(Orientation::Rotate270)
+            .data,
+        rgb_image().data
+    );
+
+    let baked_camera = Camera::new(BayerPattern::RGGB)
+        .with_orientation(Orientation::Rotate270)
+        .with_baked_orientation();
+    assert_eq!(
+        baked_camera.capture(rgb_image()).unwrap(),
+        raw_image.apply_orientation()
+    );
+    assert_eq!(
+        baked_camera.ground_truth(rgb_image()).unwrap().data,
+        rgb_image().data
+    );
+
+    let path = std::env::temp_dir().join("emubayer_test_orientation.dng");
+    raw_image.save_as_dng(&path).unwrap();
+    let read_image = RawImage::from_dng(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(read_image.orientation(), Orientation::Rotate270);
+}
+
+#[test]
+fn test_png_exif_orientation() {
+    let rgb_image = RgbImage {
+        width: 2,
+        height: 1,
+        data: vec![1, 1, 1, 2, 2, 2],
+        color_type: ColorType::RGB,
+        bit_depth: BitDepth::Eight,
+    };
+
+    let path = std::env::temp_dir().join("emubayer_test_png_exif_orientation.png");
+    rgb_image.save_as_png(&path).unwrap();
+    let png_file = std::fs::read(&path).unwrap();
+
+    // Big-endian EXIF data with a single Orientation entry of 8, inserted
+    // after the IHDR chunk.
+    let exif: Vec<u8> = vec![
+        b'M', b'M', 0, 42, 0, 0, 0, 8, 0, 1, 1, 18, 0, 3, 0, 0, 0, 1, 0, 8, 0, 0, 0, 0, 0, 0, 0, 0,
+    ];
+    let mut chunk = Vec::new();
+    chunk.write_u32::<BigEndian>(exif.len() as u32).unwrap();
+    chunk.extend(b"eXIf");
+    chunk.extend(&exif);
+    let crc = burst::crc32(&chunk[4..]);
+    chunk.write_u32::<BigEndian>(crc).unwrap();
+
+    let mut tagged_file = png_file[..33].to_vec();
+    tagged_file.extend(chunk);
+    tagged_file.extend(&png_file[33..]);
+    std::fs::write(&path, tagged_file).unwrap();
+
+    let read_image = RgbImage::from_file(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!((read_image.width(), read_image.height()), (1, 2));
+    assert_eq!(read_image.data, vec![2, 2, 2, 1, 1, 1]);
+}
+
+#[test]
+fn test_readout_mode() {
+    let rgb_image = || RgbImage {
+        width: 8,
+        height: 8,
+        data: get_input_vector_even(),
+        color_type: ColorType::RGB,
+        bit_depth: BitDepth::Eight,
+    };
+
+    for mode in &["bin-2x2-sum", "bin-3x3-average", "skip-2x2"] {
+        assert_eq!(mode.parse::<ReadoutMode>().unwrap().to_string(), *mode);
+    }
+    assert_eq!(
+        "BIN-2x2".parse(),
+        Ok(ReadoutMode::Binning {
+            factor: 2,
+            combination: Combination::Sum
+        })
+    );
+    for mode in &["bin-2x3", "skip-0x0", "skip-2x2-sum", "bin"] {
+        assert!(mode.parse::<ReadoutMode>().is_err());
+    }
+
+    let mut raw_image = rgb_image().to_raw(BayerPattern::RGGB);
+    raw_image.black_level = 100;
+    let sample = |x: u32, y: u32| u32::from(raw_image.data[(y * 8 + x) as usize]);
+
+    // Binning combines the photosites of the same color in neighboring tiles.
+    let summed = raw_image.read_out("bin-2x2-sum".parse().unwrap()).unwrap();
+    assert_eq!((summed.width(), summed.height()), (4, 4));
+    assert_eq!(summed.cfa_pattern(), Some(&BayerPattern::RGGB.into()));
+    assert_eq!(summed.black_level(), 400);
+    assert_eq!(summed.white_level(), u16::MAX);
+    assert_eq!(
+        u32::from(summed.data[1]),
+        sample(1, 0) + sample(3, 0) + sample(1, 2) + sample(3, 2)
+    );
+    assert_eq!(
+        u32::from(summed.data[4 * 3 + 2]),
+        sample(4, 5) + sample(6, 5) + sample(4, 7) + sample(6, 7)
+    );
+
+    let averaged = raw_image
+        .read_out("bin-2x2-average".parse().unwrap())
+        .unwrap();
+    assert_eq!(averaged.black_level(), 100);
+    assert_eq!(
+        u32::from(averaged.data[0]),
+        (sample(0, 0) + sample(2, 0) + sample(0, 2) + sample(2, 2) + 2) / 4
+    );
+
+    let mut twelve_bit = raw_image.clone();
+    twelve_bit.white_level = 4095;
+    twelve_bit.default_crop = Region::new(2, 0, 6, 7);
+    let summed = twelve_bit.read_out("bin-2x2-sum".parse().unwrap()).unwrap();
+    assert_eq!(summed.white_level(), 4 * 4095);
+    assert_eq!(summed.default_crop(), Region::new(1, 0, 3, 4));
+
+    // Skipping reads the first of every two tiles.
+    let skipped = raw_image.read_out("skip-2x2".parse().unwrap()).unwrap();
+    assert_eq!((skipped.width(), skipped.height()), (4, 4));
+    assert_eq!(u32::from(skipped.data[0]), sample(0, 0));
+    assert_eq!(u32::from(skipped.data[4 + 1]), sample(1, 1));
+    assert_eq!(u32::from(skipped.data[4 * 3 + 2]), sample(4, 5));
+
+    // Partial groups of tiles are dropped.
+    assert_eq!(
+        raw_image
+            .crop(Region::new(0, 0, 8, 6))
+            .unwrap()
+            .read_out("skip-3x3".parse().unwrap())
+            .map(|frame| (frame.width(), frame.height())),
+        Ok((2, 2))
+    );
+    assert!(raw_image.read_out("skip-5x5".parse().unwrap()).is_err());
+
+    // Sensors without a CFA combine neighboring pixels.
+    let linear = rgb_image()
+        .to_linear_raw()
+        .read_out("skip-2x2".parse().unwrap())
+        .unwrap();
+    assert_eq!((linear.width(), linear.height()), (4, 4));
+    assert_eq!(&linear.data[3..6], &rgb_image().to_linear_raw().data[6..9]);
+
+    let camera =
+        Camera::new(BayerPattern::GBRG).with_readout_mode(ReadoutMode::Skipping { factor: 3 });
+    let raw_image = camera.capture(rgb_image()).unwrap();
+    let ground_truth = camera.ground_truth(rgb_image()).unwrap();
+    assert_eq!((raw_image.width(), raw_image.height()), (2, 2));
+    assert_eq!((ground_truth.width(), ground_truth.height()), (2, 2));
+    assert!(camera.to_json().contains("\"readout_mode\":\"skip-3x3\""));
+}
+
+#[test]
+fn test_burst() {
+    fn write_chunk(file: &mut Vec<u8>, chunk_type: &[u8], data: &[u8]) {
+        file.write_u32::<BigEndian>(data.len() as u32).unwrap();
+        let start = file.len();
+        file.extend(chunk_type);
+        file.extend(data);
+        let crc = burst::crc32(&file[start..]);
+        file.write_u32::<BigEndian>(crc).unwrap();
+    }
+
+    // Returns the IHDR and IDAT data of an 8-bit RGBA PNG file.
+    let encode = |width: u32, height: u32, data: Vec<u8>| {
+        let path = std::env::temp_dir().join("emubayer_test_burst_frame.png");
+        RgbImage {
+            width,
+            height,
+            data,
+            color_type: ColorType::RGBA,
+            bit_depth: BitDepth::Eight,
+        }
+        .save_as_png(&path)
+        .unwrap();
+
+        let png_file = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let chunks = burst::png_chunks(&png_file).unwrap();
+        let data = |chunk_type: &[u8]| -> Vec<u8> {
+            chunks
+                .iter()
+                .filter(|&&(found, _)| found == chunk_type)
+                .flat_map(|&(_, data)| data.to_vec())
+                .collect()
+        };
+        (data(b"IHDR"), data(b"IDAT"))
+    };
+
+    let frame_control = |sequence: u32, region: Region, delay: [u16; 2], dispose: u8, blend: u8| {
+        let mut data = Vec::new();
+        for value in &[sequence, region.width, region.height, region.x, region.y] {
+            data.write_u32::<BigEndian>(*value).unwrap();
+        }
+        data.write_u16::<BigEndian>(delay[0]).unwrap();
+        data.write_u16::<BigEndian>(delay[1]).unwrap();
+        data.extend(&[dispose, blend]);
+        data
+    };
+
+    // Opaque gray 4x2 frame, a half-transparent white 2x1 frame blended over
+    // it and disposed to the background, and an opaque red 1x1 frame.
+    let (header, first) = encode(4, 2, [100, 100, 100, 255].repeat(8));
+    let (_, second) = encode(2, 1, [200, 200, 200, 128].repeat(2));
+    let (_, third) = encode(1, 1, vec![255, 0, 0, 255]);
+
+    let mut apng = b"\x89PNG\r\n\x1a\n".to_vec();
+    write_chunk(&mut apng, b"IHDR", &header);
+    write_chunk(&mut apng, b"acTL", &[0, 0, 0, 3, 0, 0, 0, 0]);
+    write_chunk(
+        &mut apng,
+        b"fcTL",
+        &frame_control(0, Region::new(0, 0, 4, 2), [1, 10], 0, 0),
+    );
+    write_chunk(&mut apng, b"IDAT", &first);
+    write_chunk(
+        &mut apng,
+        b"fcTL",
+        &frame_control(1, Region::new(1, 1, 2, 1), [1, 10], 1, 1),
+    );
+    write_chunk(&mut apng, b"fdAT", &[&[0, 0, 0, 2][..], &second].concat());
+    write_chunk(
+        &mut apng,
+        b"fcTL",
+        &frame_control(3, Region::new(0, 0, 1, 1), [2, 10], 0, 0),
+    );
+    write_chunk(&mut apng, b"fdAT", &[&[0, 0, 0, 4][..], &third].concat());
+    write_chunk(&mut apng, b"IEND", &[]);
+
+    let path = std::env::temp_dir().join("emubayer_test_burst.png");
+    std::fs::write(&path, &apng).unwrap();
+    let burst = Burst::from_file(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let frames = burst.frames();
+    assert_eq!(frames.len(), 3);
+    assert!((burst.frame_rate() - 7.5).abs() < 1e-9);
+    assert_eq!(frames[0].data, [100, 100, 100, 255].repeat(8));
+    assert_eq!(&frames[1].data[16..20], &[100, 100, 100, 255]);
+    assert_eq!(&frames[1].data[20..24], &[150, 150, 150, 255]);
+    assert_eq!(&frames[2].data[..4], &[255, 0, 0, 255]);
+    assert_eq!(&frames[2].data[20..28], &[0; 8]);
+    assert_eq!(&frames[2].data[28..], &[100, 100, 100, 255]);
+
+    // A corrupted CRC is caught.
+    let last = apng.len() - 1;
+    apng[last] ^= 1;
+    std::fs::write(&path, &apng).unwrap();
+    assert!(Burst::from_file(&path).is_err());
+    std::fs::remove_file(&path).unwrap();
+
+    // Numbered sequences run up to the first missing file.
+    let directory = std::env::temp_dir().join("emubayer_test_burst_sequence");
+    std::fs::create_dir_all(&directory).unwrap();
+    for number in &[8, 9, 10, 12] {
+        RgbImage {
+            width: 4,
+            height: 2,
+            data: vec![60; 24],
+            color_type: ColorType::RGB,
+            bit_depth: BitDepth::Eight,
+        }
+        .save_as_png(directory.join(format!("frame_{:02}.png", number)))
+        .unwrap();
+    }
+    let burst = Burst::from_file(directory.join("frame_08.png")).unwrap();
+    assert_eq!(burst.frames().len(), 3);
+    assert_eq!(burst.frame_rate(), 24.0);
+    assert!(Burst::from_sequence(directory.join("frame_11.png")).is_err());
+
+    // Identical frames draw different noise.
+    let camera = Camera::new(BayerPattern::RGGB).with_noise(Noise::new().with_read_noise(500.0));
+    let raw_burst = camera.capture_burst(&burst).unwrap();
+    assert_eq!(
+        raw_burst.frames()[0],
+        camera.capture(burst.frames()[0].clone()).unwrap()
+    );
+    assert_ne!(raw_burst.frames()[0].data, raw_burst.frames()[1].data);
+
+    let clip = directory.join("clip");
+    let paths = raw_burst.save_as_cinema_dng(&clip).unwrap();
+    assert_eq!(paths[2], clip.join("clip_000002.dng"));
+
+    let dng = tiff::TiffReader::new(std::fs::read(&paths[2]).unwrap()).unwrap();
+    let ifd = &dng.ifds().unwrap()[0];
+    assert_eq!(dng.bytes(ifd, 0xC763), Some(&[2, 0, 0, 0, 0, 0, 0, 0][..]));
+    assert_eq!(dng.f64_values(ifd, 0xC764), Some(vec![24.0]));
+    assert_eq!(
+        RawImage::from_dng(&paths[2]).unwrap(),
+        raw_burst.frames()[2]
+    );
+    std::fs::remove_dir_all(&directory).unwrap();
+
+    assert_eq!(
+        burst::time_code(24 * 3661 + 17, 24.0),
+        [0x17, 0x01, 0x01, 0x01, 0, 0, 0, 0]
+    );
+}
+
+#[test]
+fn test_temporal() {
+    // Four uniform 4x4 frames at 4 frames per second, each brighter than the
+    // one before.
+    let scene: Vec<RgbImage> = (0..4u8)
+        .map(|frame| RgbImage {
+            width: 4,
+            height: 4,
+            data: vec![frame * 40; 48],
+            color_type: ColorType::RGB,
+            bit_depth: BitDepth::Eight,
+        })
+        .collect();
+    let value = |frame: u32| (frame * 40) << 8;
+    let close = |image: &RgbImage, x: u32, y: u32, expected: u32| {
+        (i64::from(image.sample(x, y, 0)) - i64::from(expected)).abs() <= 1
+    };
+
+    // Without exposure time or rolling shutter, frames are taken as they are.
+    let camera = Camera::new(BayerPattern::RGGB);
+    assert!(camera.exposed_frame(&scene, 4.0, 0.5).data == scene[2].data);
+
+    // Exposures average the frames they overlap, and the last frame is held.
+    let camera = camera.with_exposure_time(0.5);
+    let frame = camera.exposed_frame(&scene, 4.0, 0.0);
+    assert!(close(&frame, 0, 0, (value(0) + value(1)) / 2));
+    let frame = camera.exposed_frame(&scene, 4.0, 0.125);
+    assert!(close(
+        &frame,
+        3,
+        3,
+        (value(0) + 2 * value(1) + value(2)) / 4
+    ));
+    let frame = camera.exposed_frame(&scene, 4.0, 0.75);
+    assert!(close(&frame, 2, 1, value(3)));
+
+    // The rolling shutter reads later rows from later frames.
+    let camera = Camera::new(BayerPattern::RGGB).with_rolling_shutter(0.75);
+    let frame = camera.exposed_frame(&scene, 4.0, 0.0);
+    for row in 0..4 {
+        assert!(close(&frame, 1, row, value(row)));
+    }
+
+    // Rows are counted on the sensor, which runs along the displayed columns
+    // when it's rotated.
+    let camera = camera.with_orientation(Orientation::Rotate90);
+    let frame = camera.exposed_frame(&scene, 4.0, 0.0);
+    for column in 0..4 {
+        assert!(close(&frame, column, 2, value(3 - column)));
+    }
+
+    // The camera's own frame rate sets how many frames it records.
+    let burst = Burst::new(scene, 4.0).unwrap();
+    let camera = Camera::new(BayerPattern::RGGB)
+        .with_frame_rate(2.0)
+        .with_exposure_time(0.5);
+    let raw_burst = camera.capture_burst(&burst).unwrap();
+    assert_eq!(raw_burst.frames().len(), 2);
+    assert_eq!(raw_burst.frame_rate(), 2.0);
+    assert!(camera
+        .to_json()
+        .contains("\"frame_rate\":2,\"exposure_time\":0.5,\"rolling_shutter\":null"));
+
+    assert!(Camera::new(BayerPattern::RGGB)
+        .with_exposure_time(-0.1)
+        .capture_burst(&burst)
+        .is_err());
+    assert!(Camera::new(BayerPattern::RGGB)
+        .with_rolling_shutter(f64::INFINITY)
+        .capture_burst(&burst)
+        .is_err());
+    assert!(Camera::new(BayerPattern::RGGB)
+        .with_frame_rate(0.0)
+        .capture_burst(&burst)
+        .is_err());
+    assert!(Camera::new(BayerPattern::RGGB)
+        .with_frame_rate(1e9)
+        .capture_burst(&burst)
+        .is_err());
+    let slow_burst = Burst::new(burst.frames().to_vec(), 1e-9).unwrap();
+    assert!(Camera::new(BayerPattern::RGGB)
+        .with_frame_rate(24.0)
+        .capture_burst(&slow_burst)
+        .is_err());
+    assert_eq!(
+        burst.clone().with_frame_rate(8.0).unwrap().frame_rate(),
+        8.0
+    );
+    assert!(burst.clone().with_frame_rate(0.0).is_err());
+    assert!(burst.with_frame_rate(f64::NAN).is_err());
+}
+
+#[test]
+fn test_bracketing() {
+    // Red, green and blue at a quarter, half and one and a half of white.
+    let scene = LinearImage::new(4, 4, vec![[0.25, 0.5, 1.5]; 16]).unwrap();
+    let camera = Camera::new(BayerPattern::RGGB).with_exposure_time(0.01);
+    let bracket = camera.capture_bracket(&scene, &[-1.0, 0.0, 1.0]).unwrap();
+    assert_eq!(bracket.len(), 3);
+
+    // Photosites of RGGB at (0, 0), (1, 0) and (1, 1).
+    let samples = |raw_image: &RawImage| {
+        [
+            raw_image.data[0],
+            raw_image.data[1],
+            raw_image.data[raw_image.width as usize + 1],
+        ]
+    };
+    assert_eq!(samples(&bracket[0]), [8192, 16384, 49151]);
+    assert_eq!(samples(&bracket[1]), [16384, 32768, 65535]);
+    assert_eq!(samples(&bracket[2]), [32768, 65535, 65535]);
+
+    assert_eq!(bracket[0].exposure_time(), Some(0.005));
+    assert_eq!(bracket[2].exposure_time(), Some(0.02));
+    assert_eq!(bracket[0].exposure_bias(), Some(-1.0));
+
+    // The ground truth is the scene at 0 EV, clipped at white.
+    let ground_truth = camera.bracket_ground_truth(&scene).unwrap();
+    assert_eq!((ground_truth.width, ground_truth.height), (4, 4));
+    assert_eq!(
+        (0..3)
+            .map(|channel| ground_truth.sample(0, 0, channel))
+            .collect::<Vec<_>>(),
+        [16384, 32768, 65535]
+    );
+
+    // Exposure metadata survives DNG files.
+    let path = std::env::temp_dir().join("emubayer_test_bracketing.dng");
+    bracket[0].save_as_dng(&path).unwrap();
+    assert_eq!(RawImage::from_dng(&path).unwrap(), bracket[0]);
+    std::fs::remove_file(&path).unwrap();
+
+    // Brackets around the default exposure time.
+    let camera = Camera::new(BayerPattern::RGGB).with_noise(Noise::new().with_read_noise(500.0));
+    let bracket = camera.capture_bracket(&scene, &[0.0, 0.0]).unwrap();
+    assert_eq!(bracket[0].exposure_time(), Some(1.0 / 60.0));
+    assert_ne!(bracket[0].data, bracket[1].data);
+
+    assert!(camera.capture_bracket(&scene, &[]).is_err());
+    assert!(camera.capture_bracket(&scene, &[f64::NAN]).is_err());
+    assert!(LinearImage::new(2, 2, vec![[0.0; 3]; 3]).is_err());
+
+    assert_eq!(tiff::rational(1.0 / 60.0), (1, 60));
+    assert_eq!(tiff::rational(23.976), (2997, 125));
+    assert_eq!(tiff::rational(-0.5), (-1, 2));
+    assert_eq!(tiff::rational(0.0), (0, 1));
+}
+
+#[test]
+fn test_float_dng() {
+    // Half and 24-bit floats round to the nearest value, ties to even.
+    assert_eq!(tiff::float_to_bits(1.0, 5, 10), 0x3C00);
+    assert_eq!(tiff::float_to_bits(-2.0, 5, 10), 0xC000);
+    assert_eq!(tiff::float_to_bits(65504.0, 5, 10), 0x7BFF);
+    assert_eq!(tiff::float_to_bits(65520.0, 5, 10), 0x7C00);
+    assert_eq!(tiff::float_to_bits(2f32.powi(-24), 5, 10), 0x0001);
+    assert_eq!(tiff::float_to_bits(1.0, 7, 16), 0x3F_0000);
+    assert_eq!(tiff::float_to_bits(1.0 + 2f32.powi(-17), 7, 16), 0x3F_0000);
+    assert_eq!(
+        tiff::float_to_bits(1.0 + 3.0 * 2f32.powi(-17), 7, 16),
+        0x3F_0002
+    );
+    assert_eq!(tiff::float_from_bits(0x0001, 5, 10), 2f32.powi(-24));
+    assert_eq!(tiff::float_from_bits(0xC000, 5, 10), -2.0);
+    assert_eq!(tiff::float_from_bits(0x3F_8000, 7, 16), 1.5);
+    assert!(tiff::float_from_bits(0x7C01, 5, 10).is_nan());
+
+    // Red, green and blue at a quarter, half and one and a half of white,
+    // captured with two stops of headroom.
+    let scene = LinearImage::new(4, 4, vec![[0.25, 0.5, 1.5]; 16]).unwrap();
+    let camera = Camera::new(BayerPattern::RGGB);
+    let raw_image = camera.capture_scene(&scene, 2.0).unwrap();
+    assert_eq!(raw_image.white_level(), 16384);
+    assert_eq!(
+        [
+            raw_image.data[0],
+            raw_image.data[1],
+            raw_image.data[raw_image.width as usize + 1]
+        ],
+        [4096, 8192, 24576]
+    );
+
+    let path = std::env::temp_dir().join("emubayer_test_float.dng");
+    raw_image.save_as_dng(&path).unwrap();
+    assert_eq!(RawImage::from_dng(&path).unwrap(), raw_image);
+
+    // Float samples keep the blue photosites past white, which are read
+    // back with the white level lowered below them.
+    for &sample_format in &[
+        SampleFormat::Float16,
+        SampleFormat::Float24,
+        SampleFormat::Float32,
+    ] {
+        let float_image = raw_image.clone().with_sample_format(sample_format);
+        float_image.save_as_dng(&path).unwrap();
+
+        let read_image = RawImage::from_dng(&path).unwrap();
+        assert_eq!(read_image.sample_format(), sample_format);
+        assert_eq!(
+            (read_image.black_level(), read_image.white_level()),
+            (0, 43690)
+        );
+        assert_eq!(read_image.data[1], 21845);
+        assert_eq!(read_image.data[read_image.width as usize + 1], 65535);
+    }
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!("float24".parse(), Ok(SampleFormat::Float24));
+    assert!("double".parse::<SampleFormat>().is_err());
+    assert!(camera.capture_scene(&scene, -1.0).is_err());
+    assert!(camera.capture_scene(&scene, 20.0).is_err());
+}
+
+#[test]
+fn test_dng_compression() {
+    let rgb_image = || RgbImage {
+        width: 9,
+        height: 9,
+        data: get_input_vector_odd(),
+        color_type: ColorType::RGB,
+        bit_depth: BitDepth::Eight,
+    };
+
+    // Samples are differenced from the previous pixel, or from the pixel two
+    // or four before, then deflated.
+    let mut raw_image = rgb_image().to_monochrome(REC709_LUMA);
+    raw_image.width = 6;
+    raw_image.height = 1;
+    raw_image.data = vec![1, 3, 6, 10, 15, 21];
+
+    let inflated = |raw_image: &RawImage| inflate::inflate_bytes_zlib(&raw_image.image_bytes());
+    let deflated = |raw_image: &RawImage, predictor| {
+        raw_image
+            .clone()
+            .with_compression(Compression::Deflate(predictor))
+    };
+    assert_eq!(
+        inflated(&deflated(&raw_image, Predictor::Horizontal)).unwrap(),
+        [1, 0, 2, 0, 3, 0, 4, 0, 5, 0, 6, 0]
+    );
+    assert_eq!(
+        inflated(&deflated(&raw_image, Predictor::HorizontalX2)).unwrap(),
+        [1, 0, 3, 0, 5, 0, 7, 0, 9, 0, 11, 0]
+    );
+    assert_eq!(
+        inflated(&deflated(&raw_image, Predictor::HorizontalX4)).unwrap(),
+        [1, 0, 3, 0, 6, 0, 10, 0, 14, 0, 18, 0]
+    );
+
+    // The floating point predictor differences the bytes of half floats of
+    // 1, 2 and 0, from the most significant ones.
+    raw_image.width = 3;
+    raw_image.data = vec![16384, 32768, 0];
+    raw_image.white_level = 16384;
+    let float_image =
+        deflated(&raw_image, Predictor::Horizontal).with_sample_format(SampleFormat::Float16);
+    assert_eq!(
+        inflated(&float_image).unwrap(),
+        [0x3C, 0x04, 0xC0, 0x00, 0x00, 0x00]
+    );
+
+    // Every predictor is read back, for every layout and sample format.
+    let path = std::env::temp_dir().join("emubayer_test_compression.dng");
+    let uncompressed_path = std::env::temp_dir().join("emubayer_test_uncompressed.dng");
+
+    for raw_image in [
+        rgb_image().to_raw(BayerPattern::RGGB),
+        rgb_image().to_monochrome(REC709_LUMA),
+        rgb_image().to_linear_raw(),
+    ] {
+        raw_image.save_as_dng(&uncompressed_path).unwrap();
+        let uncompressed_size = std::fs::metadata(&uncompressed_path).unwrap().len();
+
+        for &predictor in &[
+            Predictor::None,
+            Predictor::Horizontal,
+            Predictor::HorizontalX2,
+            Predictor::HorizontalX4,
+        ] {
+            let compressed = raw_image
+                .clone()
+                .with_compression(Compression::Deflate(predictor));
+            compressed.save_as_dng(&path).unwrap();
+            assert_eq!(RawImage::from_dng(&path).unwrap(), compressed);
+            assert!(std::fs::metadata(&path).unwrap().len() < uncompressed_size);
+
+            for &sample_format in &[
+                SampleFormat::Float16,
+                SampleFormat::Float24,
+                SampleFormat::Float32,
+            ] {
+                raw_image
+                    .clone()
+                    .with_sample_format(sample_format)
+                    .save_as_dng(&uncompressed_path)
+                    .unwrap();
+                compressed
+                    .clone()
+                    .with_sample_format(sample_format)
+                    .save_as_dng(&path)
+                    .unwrap();
+
+                assert_eq!(
+                    RawImage::from_dng(&path).unwrap().data,
+                    RawImage::from_dng(&uncompressed_path).unwrap().data
+                );
+            }
+        }
+    }
+    std::fs::remove_file(&path).unwrap();
+    std::fs::remove_file(&uncompressed_path).unwrap();
+
+    assert_eq!("horizontal-x2".parse(), Ok(Predictor::HorizontalX2));
+    assert!("vertical".parse::<Predictor>().is_err());
+}
+
+#[test]
+fn test_byte_order_and_bigtiff() {
+    let rgb_image = || RgbImage {
+        width: 9,
+        height: 9,
+        data: get_input_vector_odd(),
+        color_type: ColorType::RGB,
+        bit_depth: BitDepth::Eight,
+    };
+
+    // Integer samples are stored most significant byte first in "MM" files,
+    // and so are float samples unless predicted.
+    let mut raw_image = rgb_image().to_monochrome(REC709_LUMA);
+    raw_image.width = 2;
+    raw_image.height = 1;
+    raw_image.data = vec![0x0102, 16384];
+    raw_image.white_level = 16384;
+    let big_endian = raw_image.clone().with_endianness(Endianness::Big);
+    assert_eq!(big_endian.image_bytes(), [0x01, 0x02, 0x40, 0x00]);
+    assert_eq!(
+        big_endian
+            .with_sample_format(SampleFormat::Float16)
+            .image_bytes(),
+        [0x24, 0x08, 0x3C, 0x00]
+    );
+
+    // Every combination of byte order and file format is read back as
+    // saved, whatever the samples and compression.
+    let path = std::env::temp_dir().join("emubayer_test_byte_order.dng");
+
+    for raw_image in [
+        rgb_image().to_raw(BayerPattern::RGGB),
+        rgb_image().to_monochrome(REC709_LUMA),
+        rgb_image().to_linear_raw(),
+    ] {
+        for &endianness in &[Endianness::Little, Endianness::Big] {
+            for &big_tiff in &[false, true] {
+                let mut saved = raw_image.clone().with_endianness(endianness);
+                if big_tiff {
+                    saved = saved.with_big_tiff();
+                }
+                saved.save_as_dng(&path).unwrap();
+
+                let header = std::fs::read(&path).unwrap()[..4].to_vec();
+                assert_eq!(
+                    header,
+                    match (endianness, big_tiff) {
+                        (Endianness::Little, false) => b"II*\0",
+                        (Endianness::Big, false) => b"MM\0*",
+                        (Endianness::Little, true) => b"II+\0",
+                        (Endianness::Big, true) => b"MM\0+",
+                    }
+                );
+                assert_eq!(RawImage::from_dng(&path).unwrap(), saved);
+                assert!(RawImage::is_dng_file(&path));
+
+                let deflated = saved
+                    .clone()
+                    .with_compression(Compression::Deflate(Predictor::Horizontal));
+                deflated.save_as_dng(&path).unwrap();
+                assert_eq!(RawImage::from_dng(&path).unwrap(), deflated);
+
+                for &sample_format in &[SampleFormat::Float16, SampleFormat::Float32] {
+                    saved
+                        .clone()
+                        .with_sample_format(sample_format)
+                        .save_as_dng(&path)
+                        .unwrap();
+                    assert_eq!(RawImage::from_dng(&path).unwrap().data, raw_image.data);
+                }
+            }
+        }
+    }
+
+    // Files are told apart by their contents, whatever their extension.
+    rgb_image().save_as_png(&path).unwrap();
+    assert!(!RawImage::is_dng_file(&path));
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!("MM".parse(), Ok(Endianness::Big));
+    assert!("middle".parse::<Endianness>().is_err());
+}
+
+#[test]
+fn test_float_inputs() {
+    // A 2x2 image whose B and R channels are halves and G channel is floats,
+    // with a data window away from the origin.
+    let blue = [0x3C00u16, 0x3C00, 0x4200, 0x0001];
+    let green = [0.1f32, 0.2, 0.3, 0.4];
+    let red = [0x4000u16, 0x3800, 0x4400, 0x3400];
+
+    let mut scanlines = Vec::new();
+    for y in 0..2 {
+        for x in 0..2 {
+            scanlines
+                .write_u16::<LittleEndian>(blue[y * 2 + x])
+                .unwrap();
+        }
+        for x in 0..2 {
+            scanlines
+                .write_f32::<LittleEndian>(green[y * 2 + x])
+                .unwrap();
+        }
+        for x in 0..2 {
+            scanlines.write_u16::<LittleEndian>(red[y * 2 + x]).unwrap();
+        }
+    }
+
+    // Splits the bytes in halves and stores their differences, as done
+    // before RLE and ZIP compression.
+    let predict = |data: &[u8]| {
+        let mut predicted: Vec<u8> = data
+            .iter()
+            .step_by(2)
+            .chain(data.iter().skip(1).step_by(2))
+            .cloned()
+            .collect();
+        for i in (1..predicted.len()).rev() {
+            predicted[i] = predicted[i]
+                .wrapping_sub(predicted[i - 1])
+                .wrapping_add(128);
+        }
+        predicted
+    };
+
+    let exr = |compression: u8, blocks: Vec<Vec<u8>>| {
+        let mut file = vec![0x76, 0x2F, 0x31, 0x01, 2, 0, 0, 0];
+        let mut attribute = |name: &str, attribute_type: &str, value: &[u8]| {
+            file.extend(name.as_bytes());
+            file.push(0);
+            file.extend(attribute_type.as_bytes());
+            file.push(0);
+            file.write_i32::<LittleEndian>(value.len() as i32).unwrap();
+            file.extend(value);
+        };
+
+        let mut channels = Vec::new();
+        for &(name, pixel_type) in &[("B", 1), ("G", 2), ("R", 1)] {
+            channels.extend(name.as_bytes());
+            channels.push(0);
+            channels.write_i32::<LittleEndian>(pixel_type).unwrap();
+            channels.extend(&[0, 0, 0, 0]);
+            channels.write_i32::<LittleEndian>(1).unwrap();
+            channels.write_i32::<LittleEndian>(1).unwrap();
+        }
+        channels.push(0);
+
+        let mut window = Vec::new();
+        for &value in &[5, 10, 6, 11] {
+            window.write_i32::<LittleEndian>(value).unwrap();
+        }
+
+        attribute("channels", "chlist", &channels);
+        attribute("compression", "compression", &[compression]);
+        attribute("dataWindow", "box2i", &window);
+        file.push(0);
+
+        let lines = 2 / blocks.len() as i32;
+        let mut offset = file.len() + blocks.len() * 8;
+        for block in &blocks {
+            file.write_u64::<LittleEndian>(offset as u64).unwrap();
+            offset += 8 + block.len();
+        }
+        for (index, block) in blocks.iter().enumerate() {
+            file.write_i32::<LittleEndian>(10 + index as i32 * lines)
+                .unwrap();
+            file.write_i32::<LittleEndian>(block.len() as i32).unwrap();
+            file.extend(block);
+        }
+        file
+    };
+
+    let read = |name: &str, file: &[u8]| {
+        let path = std::env::temp_dir().join(name);
+        std::fs::write(&path, file).unwrap();
+        let image = LinearImage::from_file(&path);
+        std::fs::remove_file(&path).unwrap();
+        image
+    };
+
+    let uncompressed = read(
+        "emubayer_test_none.exr",
+        &exr(0, scanlines.chunks(16).map(<[u8]>::to_vec).collect()),
+    )
+    .unwrap();
+    assert_eq!(uncompressed.width(), 2);
+    assert_eq!(uncompressed.height(), 2);
+    assert_eq!(uncompressed.pixels()[0], [2.0, 0.1, 1.0]);
+    assert_eq!(uncompressed.pixels()[3], [0.25, 0.4, 1.0 / 16777216.0]);
+
+    let zip = deflate::deflate_bytes_zlib(&predict(&scanlines));
+    assert_eq!(
+        read("emubayer_test_zip.exr", &exr(3, vec![zip])).unwrap(),
+        uncompressed
+    );
+
+    // RLE blocks hold single scanlines, stored here as a run of their first
+    // byte followed by the rest as literals.
+    let rle = scanlines
+        .chunks(16)
+        .map(|scanline| {
+            let predicted = predict(scanline);
+            let mut rle = vec![0, predicted[0], (-(predicted.len() as i8 - 1)) as u8];
+            rle.extend(&predicted[1..]);
+            rle
+        })
+        .collect();
+    assert_eq!(
+        read("emubayer_test_rle.exr", &exr(1, rle)).unwrap(),
+        uncompressed
+    );
+    assert!(read("emubayer_test_piz.exr", &exr(4, vec![vec![0; 8]])).is_err());
+
+    // Highlights past white clip, unless exposed down.
+    assert_eq!(uncompressed.exposed(0.0).sample(0, 0, 0), 65535);
+    assert_eq!(uncompressed.exposed(-2.0).sample(0, 0, 0), 32768);
+
+    // Radiance HDR files of two 8-pixel rows, run-length encoded and flat.
+    let mut hdr = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\nEXPOSURE=2\n\n-Y 2 +X 8\n".to_vec();
+    hdr.extend(&[2, 2, 0, 8]);
+    for &value in &[128, 64, 32, 129] {
+        hdr.extend(&[128 + 8, value]);
+    }
+    hdr.extend(&[128, 128, 128, 130, 1, 1, 1, 7]);
+    let radiance = read("emubayer_test.hdr", &hdr).unwrap();
+    assert_eq!(
+        radiance.pixels()[7],
+        [257.0 / 512.0, 129.0 / 512.0, 65.0 / 512.0]
+    );
+    assert_eq!(radiance.pixels()[15], [257.0 / 256.0; 3]);
+    assert!(read("emubayer_test_truncated.hdr", &hdr[..hdr.len() - 1]).is_err());
+
+    // Grayscale PFM files store rows from the bottom up.
+    let mut pfm = b"Pf\n2 2\n-1.0\n".to_vec();
+    for &value in &[1.0f32, 2.0, 3.0, 4.0] {
+        pfm.write_f32::<LittleEndian>(value).unwrap();
+    }
+    let pfm = read("emubayer_test.pfm", &pfm).unwrap();
+    assert_eq!(pfm.pixels()[0], [3.0; 3]);
+    assert_eq!(pfm.pixels()[3], [2.0; 3]);
+
+    // Sizes that overflow or don't fit in the file, and runs of no pixels.
+    assert!(LinearImage::decode(b"PF\n70000 70000\n-1\n").is_err());
+    assert!(LinearImage::decode(b"#?RADIANCE\n\n-Y 100000 +X 100000\n\0\0\0\0").is_err());
+    let mut zero_run = b"#?RADIANCE\n\n-Y 1 +X 2\n".to_vec();
+    zero_run.extend(&[128, 128, 128, 130, 1, 1, 1, 0]);
+    assert!(LinearImage::decode(&zero_run).is_err());
+
+    let mut huge_window = exr(0, scanlines.chunks(16).map(<[u8]>::to_vec).collect());
+    let window = huge_window
+        .windows(10)
+        .position(|name| name == b"dataWindow")
+        .unwrap()
+        + 21;
+    huge_window[window..window + 4].copy_from_slice(&i32::MIN.to_le_bytes());
+    huge_window[window + 8..window + 12].copy_from_slice(&i32::MAX.to_le_bytes());
+    assert!(LinearImage::decode(&huge_window).is_err());
+}
+
+/// Builds a classic TIFF file holding a single strip, from entries of SHORT
+/// (3) or LONG (4) values.
+#[cfg(feature = "tiff")]
+fn tiff_file(big_endian: bool, entries: &[(u16, u16, &[u32])], strip: &[u8]) -> Vec<u8> {
+    let strip_length = [strip.len() as u32];
+    let mut entries = entries.to_vec();
+    entries.push((tiff::TAG_STRIP_OFFSETS, 4, &[0]));
+    entries.push((tiff::TAG_STRIP_BYTE_COUNTS, 4, &strip_length));
+    entries.sort_by_key(|&(tag, _, _)| tag);
+
+    let value_size = |field_type| if field_type == 3 { 2 } else { 4 };
+
+    let mut file = Vec::new();
+    let mut values = Vec::new();
+    let values_offset = 8 + 2 + entries.len() * 12 + 4;
+    let strip_offset = values_offset
+        + entries
+            .iter()
+            .map(|&(_, field_type, field)| field.len() * value_size(field_type))
+            .filter(|&size| size > 4)
+            .sum::<usize>();
+
+    let push = |bytes: &mut Vec<u8>, value: u32, size: usize| {
+        let value = &value.to_le_bytes()[..size];
+        if big_endian {
+            bytes.extend(value.iter().rev());
+        } else {
+            bytes.extend(value);
+        }
+    };
+
+    file.extend(if big_endian { b"MM" } else { b"II" });
+    push(&mut file, 42, 2);
+    push(&mut file, 8, 4);
+    push(&mut file, entries.len() as u32, 2);
+    for (tag, field_type, field) in entries {
+        let size = value_size(field_type);
+        push(&mut file, u32::from(tag), 2);
+        push(&mut file, u32::from(field_type), 2);
+        push(&mut file, field.len() as u32, 4);
+
+        let mut bytes = Vec::new();
+        if tag == tiff::TAG_STRIP_OFFSETS {
+            push(&mut bytes, strip_offset as u32, 4);
+        } else {
+            field
+                .iter()
+                .for_each(|&value| push(&mut bytes, value, size));
+        }
+
+        if bytes.len() > 4 {
+            push(&mut file, (values_offset + values.len()) as u32, 4);
+            values.extend(bytes);
+        } else {
+            bytes.resize(4, 0);
+            file.extend(bytes);
+        }
+    }
+    push(&mut file, 0, 4);
+
+    file.extend(values);
+    file.extend(strip);
+    file
+}
+
+#[cfg(feature = "tiff")]
+#[test]
+fn test_tiff_inputs() {
+    // A grayscale LZW strip of "abababa", whose last codes are taken from
+    // the table and defined as they are used.
+    let mut lzw = Vec::new();
+    let (mut accumulator, mut bits) = (0u32, 0);
+    for &code in &[256, 97, 98, 258, 260, 257] {
+        accumulator = accumulator << 9 | code;
+        bits += 9;
+        while bits >= 8 {
+            bits -= 8;
+            lzw.push((accumulator >> bits) as u8);
+        }
+    }
+    lzw.push((accumulator << (8 - bits)) as u8);
+
+    let gray = RgbImage::decode(&tiff_file(
+        false,
+        &[
+            (tags::ImageWidth, 4, &[7]),
+            (tags::ImageLength, 4, &[1]),
+            (tags::BitsPerSample, 3, &[8]),
+            (tags::Compression, 3, &[5]),
+            (tags::PhotometricInterpretation, 3, &[1]),
+            (tags::RowsPerStrip, 4, &[1]),
+        ],
+        &lzw,
+    ))
+    .unwrap();
+    assert_eq!((gray.width, gray.height), (7, 1));
+    assert_eq!(&gray.data[..9], &[97, 97, 97, 98, 98, 98, 97, 97, 97]);
+    assert_eq!(&gray.data[18..], &[97, 97, 97]);
+
+    // Big-endian 16-bit RGB, stored as horizontal differences in a single
+    // PackBits literal run, and turned upside down.
+    let packbits = [
+        11, 0x03, 0xE8, 0x07, 0xD0, 0x0B, 0xB8, 0x01, 0xF4, 0x01, 0xF4, 0xF4, 0x47,
+    ];
+    let rgb = RgbImage::decode(&tiff_file(
+        true,
+        &[
+            (tags::ImageWidth, 4, &[2]),
+            (tags::ImageLength, 4, &[1]),
+            (tags::BitsPerSample, 3, &[16, 16, 16]),
+            (tags::Compression, 3, &[32773]),
+            (tags::PhotometricInterpretation, 3, &[2]),
+            (tags::Orientation, 3, &[3]),
+            (tags::SamplesPerPixel, 3, &[3]),
+            (tiff::TAG_PREDICTOR, 3, &[2]),
+        ],
+        &packbits,
+    ))
+    .unwrap();
+    assert_eq!(
+        [
+            rgb.sample(0, 0, 0),
+            rgb.sample(0, 0, 1),
+            rgb.sample(0, 0, 2)
+        ],
+        [1500, 2500, 65535]
+    );
+    assert_eq!(
+        [
+            rgb.sample(1, 0, 0),
+            rgb.sample(1, 0, 1),
+            rgb.sample(1, 0, 2)
+        ],
+        [1000, 2000, 3000]
+    );
+}
+
+#[cfg(feature = "ppm")]
+#[test]
+fn test_ppm_inputs() {
+    // Binary PPM files with comments, plain ones scaled to 16 bits, and
+    // grayscale PGM files.
+    let mut ppm = b"P6\n# Comment\n2 1\n255\n".to_vec();
+    ppm.extend(&[1, 2, 3, 4, 5, 6]);
+    assert_eq!(RgbImage::decode(&ppm).unwrap().data, [1, 2, 3, 4, 5, 6]);
+
+    let plain = RgbImage::decode(b"P3 1 1 1023 1023 0 512").unwrap();
+    assert_eq!(
+        [
+            plain.sample(0, 0, 0),
+            plain.sample(0, 0, 1),
+            plain.sample(0, 0, 2)
+        ],
+        [65535, 0, 32800]
+    );
+
+    let pgm = RgbImage::decode(b"P5 2 1 255\n\x10\x20").unwrap();
+    assert_eq!(pgm.data, [16, 16, 16, 32, 32, 32]);
+    assert!(RgbImage::decode(b"P6 2 1 255\n\x10\x20").is_err());
+}
+
+#[cfg(feature = "jpeg")]
+#[test]
+fn test_jpeg_inputs() {
+    // A 16x8 grayscale baseline JPEG of two blocks, with DC values of 200
+    // and no AC coefficients, turned to portrait by its EXIF orientation.
+    let mut jpeg = vec![0xFF, 0xD8];
+    let mut segment = |marker: u8, data: &[u8]| {
+        jpeg.extend(&[0xFF, marker]);
+        jpeg.write_u16::<BigEndian>(data.len() as u16 + 2).unwrap();
+        jpeg.extend(data);
+    };
+    let mut exif = b"Exif\0\0II*\0\x08\0\0\0\x01\0".to_vec();
+    exif.extend(&[0x12, 0x01, 3, 0, 1, 0, 0, 0, 6, 0, 0, 0, 0, 0, 0, 0]);
+    segment(0xE1, &exif);
+    segment(0xDB, &[&[0][..], &[1; 64]].concat());
+    segment(0xC0, &[8, 0, 8, 0, 16, 1, 1, 0x11, 0]);
+    segment(0xC4, &[&[0x00, 0, 2][..], &[0; 14], &[0, 10]].concat());
+    segment(0xC4, &[&[0x10, 1][..], &[0; 15], &[0]].concat());
+    segment(0xDA, &[1, 1, 0x00, 0, 63, 0]);
+    jpeg.extend(&[0x64, 0x00, 0xFF, 0xD9]);
+
+    let jpeg = RgbImage::decode(&jpeg).unwrap();
+    assert_eq!((jpeg.width, jpeg.height), (8, 16));
+    assert!(jpeg.data.iter().all(|&sample| sample == 200));
+}
+
+#[test]
+fn test_image_formats() {
+    // Float images are exposed at 0 EV.
+    let mut pfm = b"PF\n1 1\n-1.0\n".to_vec();
+    for &value in &[0.5f32, 2.0, 0.0] {
+        pfm.write_f32::<LittleEndian>(value).unwrap();
+    }
+    let pfm = RgbImage::decode(&pfm).unwrap();
+    assert_eq!(
+        [
+            pfm.sample(0, 0, 0),
+            pfm.sample(0, 0, 1),
+            pfm.sample(0, 0, 2)
+        ],
+        [32768, 65535, 0]
+    );
+
+    assert!(RgbImage::decode(b"unknown").is_err());
+}
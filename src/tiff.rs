@@ -0,0 +1,1021 @@
+// Author: Cláudio Gomes (TofuLynx)
+// Project: emubayer
+// License: GNU GPL Version 3 (https://www.gnu.org/licenses/gpl-3.0.en.html)
+
+use std::{borrow::Cow, collections::BTreeMap, convert::TryFrom, fs, path::Path};
+
+use compression::Predictor;
+use inflate;
+use tiff_encoder::ifd::types::{BYTE, LONG, RATIONAL, SHORT, SRATIONAL};
+use tiff_encoder::prelude::{ByteBlock, TiffFile};
+use tiff_encoder::{ifd, write::Endianness};
+
+#[cfg(feature = "tiff")]
+use super::{BitDepth, Orientation, RgbImage};
+
+pub(crate) const TAG_NEW_SUBFILE_TYPE: u16 = 254;
+pub(crate) const TAG_IMAGE_WIDTH: u16 = 256;
+pub(crate) const TAG_IMAGE_LENGTH: u16 = 257;
+pub(crate) const TAG_BITS_PER_SAMPLE: u16 = 258;
+pub(crate) const TAG_COMPRESSION: u16 = 259;
+pub(crate) const TAG_PHOTOMETRIC_INTERPRETATION: u16 = 262;
+pub(crate) const TAG_STRIP_OFFSETS: u16 = 273;
+pub(crate) const TAG_ORIENTATION: u16 = 274;
+pub(crate) const TAG_SAMPLES_PER_PIXEL: u16 = 277;
+pub(crate) const TAG_ROWS_PER_STRIP: u16 = 278;
+pub(crate) const TAG_STRIP_BYTE_COUNTS: u16 = 279;
+pub(crate) const TAG_PLANAR_CONFIGURATION: u16 = 284;
+pub(crate) const TAG_PREDICTOR: u16 = 317;
+pub(crate) const TAG_TILE_WIDTH: u16 = 322;
+pub(crate) const TAG_TILE_LENGTH: u16 = 323;
+pub(crate) const TAG_TILE_OFFSETS: u16 = 324;
+pub(crate) const TAG_TILE_BYTE_COUNTS: u16 = 325;
+pub(crate) const TAG_SUB_IFDS: u16 = 330;
+pub(crate) const TAG_SAMPLE_FORMAT: u16 = 339;
+
+const COMPRESSION_NONE: u32 = 1;
+const COMPRESSION_LZW: u32 = 5;
+const COMPRESSION_DEFLATE: u32 = 8;
+const COMPRESSION_PACKBITS: u32 = 32773;
+const COMPRESSION_OLD_DEFLATE: u32 = 32946;
+
+const SAMPLE_FORMAT_FLOAT: u32 = 3;
+
+const TRUNCATED: &str = "This TIFF file appears to be truncated.";
+
+/// Entry of an image file directory, pointing at its values in the file.
+struct Entry {
+    field_type: u16,
+    count: usize,
+    offset: usize,
+}
+
+/// Image file directory, mapping tags to their entries.
+pub(crate) struct Ifd {
+    entries: BTreeMap<u16, Entry>,
+}
+
+impl Ifd {
+    pub(crate) fn contains(&self, tag: u16) -> bool {
+        self.entries.contains_key(&tag)
+    }
+}
+
+/// Values of an entry of a directory being written.
+pub(crate) enum Field {
+    Bytes(Vec<u8>),
+    Shorts(Vec<u16>),
+    Longs(Vec<u32>),
+    Rationals(Vec<(u32, u32)>),
+    SRationals(Vec<(i32, i32)>),
+}
+
+impl Field {
+    fn field_type(&self) -> u16 {
+        match self {
+            Field::Bytes(_) => 1,
+            Field::Shorts(_) => 3,
+            Field::Longs(_) => 4,
+            Field::Rationals(_) => 5,
+            Field::SRationals(_) => 10,
+        }
+    }
+
+    fn count(&self) -> usize {
+        match self {
+            Field::Bytes(values) => values.len(),
+            Field::Shorts(values) => values.len(),
+            Field::Longs(values) => values.len(),
+            Field::Rationals(values) => values.len(),
+            Field::SRationals(values) => values.len(),
+        }
+    }
+
+    /// Returns the bytes of the values, in the given byte order.
+    fn to_bytes(&self, big_endian: bool) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let mut push = |value: &[u8]| {
+            if big_endian {
+                bytes.extend(value.iter().rev());
+            } else {
+                bytes.extend(value);
+            }
+        };
+
+        match self {
+            Field::Bytes(values) => values.iter().for_each(|value| push(&[*value])),
+            Field::Shorts(values) => values.iter().for_each(|value| push(&value.to_le_bytes())),
+            Field::Longs(values) => values.iter().for_each(|value| push(&value.to_le_bytes())),
+            Field::Rationals(values) => values.iter().for_each(|&(numerator, denominator)| {
+                push(&numerator.to_le_bytes());
+                push(&denominator.to_le_bytes());
+            }),
+            Field::SRationals(values) => values.iter().for_each(|&(numerator, denominator)| {
+                push(&numerator.to_le_bytes());
+                push(&denominator.to_le_bytes());
+            }),
+        }
+
+        bytes
+    }
+}
+
+/// Directory of a TIFF file being written, holding a single image stored
+/// in a single strip. Its StripOffsets and StripByteCounts are added when
+/// written.
+pub(crate) struct Directory {
+    fields: BTreeMap<u16, Field>,
+    strip: Vec<u8>,
+}
+
+impl Directory {
+    pub(crate) fn new(strip: Vec<u8>) -> Directory {
+        Directory {
+            fields: BTreeMap::new(),
+            strip,
+        }
+    }
+
+    pub(crate) fn with_entry(mut self, tag: u16, field: Field) -> Directory {
+        self.fields.insert(tag, field);
+        self
+    }
+
+    /// Writes a TIFF file in the given byte order, or a BigTIFF file, whose
+    /// 8-byte offsets reach past 4 GB.
+    pub(crate) fn write_to<P: AsRef<Path>>(
+        self,
+        path: P,
+        big_endian: bool,
+        big_tiff: bool,
+    ) -> Result<(), &'static str> {
+        if big_tiff {
+            return fs::write(path, self.big_tiff_bytes(big_endian))
+                .map_err(|_| "BigTIFF file couldn't be written.");
+        }
+
+        let strip_size =
+            u32::try_from(self.strip.len()).map_err(|_| "Images over 4 GB need a BigTIFF file.")?;
+
+        let mut ifd = ifd::Ifd::new();
+        for (tag, field) in self.fields {
+            ifd = match field {
+                Field::Bytes(values) => ifd.with_entry(tag, BYTE::values(values)),
+                Field::Shorts(values) => ifd.with_entry(tag, SHORT::values(values)),
+                Field::Longs(values) => ifd.with_entry(tag, LONG::values(values)),
+                Field::Rationals(values) => ifd.with_entry(tag, RATIONAL::values(values)),
+                Field::SRationals(values) => ifd.with_entry(tag, SRATIONAL::values(values)),
+            };
+        }
+
+        TiffFile::new(
+            ifd.with_entry(TAG_STRIP_BYTE_COUNTS, LONG![strip_size])
+                .with_entry(TAG_STRIP_OFFSETS, ByteBlock::single(self.strip))
+                .single(),
+        )
+        .with_endianness(if big_endian {
+            Endianness::MM
+        } else {
+            Endianness::II
+        })
+        .write_to(path)
+        .map(|_| ())
+        .map_err(|_| "TIFF file couldn't be written.")
+    }
+
+    /// Lays out a BigTIFF file: its header, its directory, the values that
+    /// don't fit in their entries, and its strip.
+    fn big_tiff_bytes(self, big_endian: bool) -> Vec<u8> {
+        let u16_bytes = |value: u16| Field::Shorts(vec![value]).to_bytes(big_endian);
+        let u64_bytes = |value: u64| {
+            let bytes = value.to_le_bytes();
+            if big_endian {
+                bytes.iter().rev().cloned().collect()
+            } else {
+                bytes.to_vec()
+            }
+        };
+
+        let entry_count = self.fields.len() + 2;
+        let values_offset = 16 + 8 + entry_count * 20 + 8;
+
+        // Values of more than 8 bytes start on even offsets.
+        let mut values = Vec::new();
+        let mut entries: BTreeMap<u16, (u16, u64, Vec<u8>)> = BTreeMap::new();
+        for (&tag, field) in &self.fields {
+            let mut bytes = field.to_bytes(big_endian);
+            if bytes.len() > 8 {
+                let offset = (values_offset + values.len()) as u64;
+                values.append(&mut bytes);
+                if values.len() % 2 == 1 {
+                    values.push(0);
+                }
+                bytes = u64_bytes(offset);
+            }
+
+            entries.insert(tag, (field.field_type(), field.count() as u64, bytes));
+        }
+
+        let strip_offset = (values_offset + values.len()) as u64;
+        entries.insert(TAG_STRIP_OFFSETS, (16, 1, u64_bytes(strip_offset)));
+        entries.insert(
+            TAG_STRIP_BYTE_COUNTS,
+            (16, 1, u64_bytes(self.strip.len() as u64)),
+        );
+
+        let mut file = Vec::with_capacity(strip_offset as usize + self.strip.len());
+        file.extend(if big_endian { b"MM" } else { b"II" });
+        file.extend(u16_bytes(43));
+        file.extend(u16_bytes(8));
+        file.extend(u16_bytes(0));
+        file.extend(u64_bytes(16));
+
+        file.extend(u64_bytes(entry_count as u64));
+        for (tag, (field_type, count, mut bytes)) in entries {
+            bytes.resize(8, 0);
+            file.extend(u16_bytes(tag));
+            file.extend(u16_bytes(field_type));
+            file.extend(u64_bytes(count));
+            file.extend(bytes);
+        }
+        file.extend(u64_bytes(0));
+
+        file.extend(values);
+        file.extend(self.strip);
+        file
+    }
+}
+
+/// Reader of the directories and values of a TIFF or BigTIFF file, such as
+/// a DNG.
+pub(crate) struct TiffReader {
+    data: Vec<u8>,
+    big_endian: bool,
+    big_tiff: bool,
+}
+
+impl TiffReader {
+    pub(crate) fn new(data: Vec<u8>) -> Result<TiffReader, &'static str> {
+        let (big_endian, big_tiff) = match data.get(0..4) {
+            Some(b"II*\0") => (false, false),
+            Some(b"MM\0*") => (true, false),
+            Some(b"II+\0") => (false, true),
+            Some(b"MM\0+") => (true, true),
+            _ => return Err("This file isn't a TIFF file."),
+        };
+
+        let reader = TiffReader {
+            data,
+            big_endian,
+            big_tiff,
+        };
+
+        // BigTIFF files give the size of their offsets, which is always 8.
+        if big_tiff && (reader.u16_at(4)? != 8 || reader.u16_at(6)? != 0) {
+            return Err("This BigTIFF file has an unsupported offset size.");
+        }
+
+        Ok(reader)
+    }
+
+    pub(crate) fn is_big_endian(&self) -> bool {
+        self.big_endian
+    }
+
+    pub(crate) fn is_big_tiff(&self) -> bool {
+        self.big_tiff
+    }
+
+    /// Reads the directories chained from the header, in order.
+    pub(crate) fn ifds(&self) -> Result<Vec<Ifd>, &'static str> {
+        let mut ifds = Vec::new();
+        let mut offset = if self.big_tiff {
+            self.offset_at(8)?
+        } else {
+            self.u32_at(4)? as usize
+        };
+
+        while offset != 0 {
+            if ifds.len() > 64 {
+                return Err("This TIFF file has too many directories.");
+            }
+
+            let (ifd, next_offset) = self.ifd_at(offset)?;
+            ifds.push(ifd);
+            offset = next_offset;
+        }
+
+        Ok(ifds)
+    }
+
+    /// Reads the directories pointed at by the SubIFDs tag of a directory.
+    pub(crate) fn sub_ifds(&self, ifd: &Ifd) -> Result<Vec<Ifd>, &'static str> {
+        self.u64_values(ifd, TAG_SUB_IFDS)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|offset| self.ifd_at(offset as usize).map(|(ifd, _)| ifd))
+            .collect()
+    }
+
+    /// Reads a directory, returning it along with the offset of the next
+    /// one. BigTIFF directories count their entries, and the values of
+    /// their entries, with 8 bytes instead of 2 and 4, and keep values of up
+    /// to 8 bytes in their entries instead of 4.
+    fn ifd_at(&self, offset: usize) -> Result<(Ifd, usize), &'static str> {
+        let (count, header_size, entry_size, inline_size) = if self.big_tiff {
+            (self.offset_at(offset)?, 8, 20, 8)
+        } else {
+            (self.u16_at(offset)? as usize, 2, 12, 4)
+        };
+        let mut entries = BTreeMap::new();
+
+        for i in 0..count {
+            let position = offset + header_size + i * entry_size;
+            let tag = self.u16_at(position)?;
+            let field_type = self.u16_at(position + 2)?;
+            let (count, value_position) = if self.big_tiff {
+                (self.offset_at(position + 4)?, position + 12)
+            } else {
+                (self.u32_at(position + 4)? as usize, position + 8)
+            };
+
+            // Unknown types can't be sized, so they are skipped.
+            let size = match type_size(field_type) {
+                Some(size) => size,
+                None => continue,
+            };
+            let length = size.checked_mul(count).ok_or(TRUNCATED)?;
+
+            let offset = if length <= inline_size {
+                value_position
+            } else if self.big_tiff {
+                self.offset_at(value_position)?
+            } else {
+                self.u32_at(value_position)? as usize
+            };
+
+            if offset
+                .checked_add(length)
+                .map_or(true, |end| end > self.data.len())
+            {
+                return Err(TRUNCATED);
+            }
+
+            entries.insert(
+                tag,
+                Entry {
+                    field_type,
+                    count,
+                    offset,
+                },
+            );
+        }
+
+        let next_position = offset + header_size + count * entry_size;
+        let next_offset = if self.big_tiff {
+            self.offset_at(next_position)?
+        } else {
+            self.u32_at(next_position)? as usize
+        };
+
+        Ok((Ifd { entries }, next_offset))
+    }
+
+    /// Returns the values of an integer tag.
+    pub(crate) fn u32_values(&self, ifd: &Ifd, tag: u16) -> Option<Vec<u32>> {
+        self.u64_values(ifd, tag)?
+            .into_iter()
+            .map(|value| u32::try_from(value).ok())
+            .collect()
+    }
+
+    /// Returns the values of an integer tag, including 8-byte ones such as
+    /// the offsets of BigTIFF files.
+    pub(crate) fn u64_values(&self, ifd: &Ifd, tag: u16) -> Option<Vec<u64>> {
+        let entry = ifd.entries.get(&tag)?;
+
+        (0..entry.count)
+            .map(|i| match entry.field_type {
+                1 | 7 => Some(u64::from(self.data[entry.offset + i])),
+                3 => self.u16_at(entry.offset + i * 2).ok().map(u64::from),
+                4 | 13 => self.u32_at(entry.offset + i * 4).ok().map(u64::from),
+                16 | 18 => self.u64_at(entry.offset + i * 8).ok(),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub(crate) fn u32_value(&self, ifd: &Ifd, tag: u16) -> Option<u32> {
+        self.u32_values(ifd, tag)
+            .and_then(|values| values.first().cloned())
+    }
+
+    /// Returns the values of a numeric tag of any type.
+    pub(crate) fn f64_values(&self, ifd: &Ifd, tag: u16) -> Option<Vec<f64>> {
+        let entry = ifd.entries.get(&tag)?;
+
+        if let 1 | 3 | 4 | 7 | 13 | 16 | 18 = entry.field_type {
+            return self
+                .u64_values(ifd, tag)
+                .map(|values| values.into_iter().map(|value| value as f64).collect());
+        }
+
+        (0..entry.count)
+            .map(|i| match entry.field_type {
+                6 => Some(f64::from(self.data[entry.offset + i] as i8)),
+                8 => self
+                    .u16_at(entry.offset + i * 2)
+                    .ok()
+                    .map(|value| f64::from(value as i16)),
+                9 => self
+                    .u32_at(entry.offset + i * 4)
+                    .ok()
+                    .map(|value| f64::from(value as i32)),
+                5 | 10 => {
+                    let numerator = self.u32_at(entry.offset + i * 8).ok()?;
+                    let denominator = self.u32_at(entry.offset + i * 8 + 4).ok()?;
+                    let (numerator, denominator) = if entry.field_type == 5 {
+                        (f64::from(numerator), f64::from(denominator))
+                    } else {
+                        (f64::from(numerator as i32), f64::from(denominator as i32))
+                    };
+
+                    Some(numerator / denominator)
+                }
+                11 => self
+                    .u32_at(entry.offset + i * 4)
+                    .ok()
+                    .map(|bits| f64::from(f32::from_bits(bits))),
+                12 => {
+                    let first = u64::from(self.u32_at(entry.offset + i * 8).ok()?);
+                    let second = u64::from(self.u32_at(entry.offset + i * 8 + 4).ok()?);
+                    let bits = if self.big_endian {
+                        first << 32 | second
+                    } else {
+                        second << 32 | first
+                    };
+
+                    Some(f64::from_bits(bits))
+                }
+                17 => self
+                    .u64_at(entry.offset + i * 8)
+                    .ok()
+                    .map(|value| value as i64 as f64),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Returns the raw bytes of a tag, such as an ASCII or UNDEFINED one.
+    pub(crate) fn bytes(&self, ifd: &Ifd, tag: u16) -> Option<&[u8]> {
+        let entry = ifd.entries.get(&tag)?;
+        let size = type_size(entry.field_type)?;
+
+        Some(&self.data[entry.offset..entry.offset + size * entry.count])
+    }
+
+    /// Reads the samples of an uncompressed, LZW, PackBits or Deflate image,
+    /// stored in strips or tiles, as integers of up to 16 bits.
+    pub(crate) fn image_samples(&self, ifd: &Ifd) -> Result<Vec<u16>, &'static str> {
+        let bits = self.u32_value(ifd, TAG_BITS_PER_SAMPLE).unwrap_or(1);
+
+        let predictor = match Predictor::from_tag(self.u32_value(ifd, TAG_PREDICTOR).unwrap_or(1)) {
+            Some((Predictor::None, _)) => Predictor::None,
+            Some((predictor, false)) if bits == 8 || bits == 16 => predictor,
+            _ => return Err("Only horizontal differencing of 8 and 16-bit samples is supported."),
+        };
+
+        if self.u32_value(ifd, TAG_SAMPLE_FORMAT).unwrap_or(1) != 1 || bits == 0 || bits > 16 {
+            return Err("Only unsigned integer samples of up to 16 bits are supported.");
+        }
+
+        let samples_per_pixel = self.u32_value(ifd, TAG_SAMPLES_PER_PIXEL).unwrap_or(1) as usize;
+        let distance = predictor.distance() * samples_per_pixel;
+
+        self.read_rows(ifd, bits, |row_data, row_samples: &mut [u16]| {
+            self.unpack_row(row_data, bits, row_samples);
+
+            // Samples were stored as differences from the ones of a previous
+            // pixel, wrapping around.
+            if predictor != Predictor::None {
+                let mask = ((1u32 << bits) - 1) as u16;
+                for i in distance..row_samples.len() {
+                    row_samples[i] = row_samples[i].wrapping_add(row_samples[i - distance]) & mask;
+                }
+            }
+        })
+    }
+
+    /// Reads the samples of an uncompressed, LZW, PackBits or Deflate image
+    /// of 16, 24 or 32-bit floats, stored in strips or tiles.
+    pub(crate) fn float_samples(&self, ifd: &Ifd) -> Result<Vec<f32>, &'static str> {
+        let bits = self.u32_value(ifd, TAG_BITS_PER_SAMPLE).unwrap_or(1);
+
+        if self.u32_value(ifd, TAG_SAMPLE_FORMAT) != Some(SAMPLE_FORMAT_FLOAT)
+            || ![16, 24, 32].contains(&bits)
+        {
+            return Err("Only float samples of 16, 24 or 32 bits are supported.");
+        }
+
+        let predictor = match Predictor::from_tag(self.u32_value(ifd, TAG_PREDICTOR).unwrap_or(1)) {
+            Some((Predictor::None, _)) => Predictor::None,
+            Some((predictor, true)) => predictor,
+            _ => return Err("Float samples only support floating point predictors."),
+        };
+
+        let samples_per_pixel = self.u32_value(ifd, TAG_SAMPLES_PER_PIXEL).unwrap_or(1) as usize;
+        let distance = predictor.distance() * samples_per_pixel;
+        let bytes_per_sample = bits as usize / 8;
+        let decode = |value| match bits {
+            16 => float_from_bits(value, 5, 10),
+            24 => float_from_bits(value, 7, 16),
+            _ => f32::from_bits(value),
+        };
+
+        self.read_rows(ifd, bits, |row_data, row_samples: &mut [f32]| {
+            if predictor == Predictor::None {
+                for (sample, bytes) in row_samples
+                    .iter_mut()
+                    .zip(row_data.chunks(bytes_per_sample))
+                {
+                    let value = bytes
+                        .iter()
+                        .fold(0u32, |value, &byte| value << 8 | u32::from(byte));
+                    *sample = decode(if self.big_endian {
+                        value
+                    } else {
+                        value.swap_bytes() >> (32 - bits)
+                    });
+                }
+                return;
+            }
+
+            // Bytes were differenced across the row, where the bytes of
+            // every sample are spread from the most significant ones of every
+            // sample to the least, whatever the byte order of the file.
+            let mut bytes = row_data.to_vec();
+            for i in distance..bytes.len() {
+                bytes[i] = bytes[i].wrapping_add(bytes[i - distance]);
+            }
+
+            let count = bytes.len() / bytes_per_sample;
+            for (index, sample) in row_samples.iter_mut().enumerate() {
+                let value = (0..bytes_per_sample).fold(0u32, |value, byte| {
+                    value << 8 | u32::from(bytes[byte * count + index])
+                });
+                *sample = decode(value);
+            }
+        })
+    }
+
+    /// Decompresses every strip or tile of an image, handing each of its
+    /// rows to `read_row` along with the samples it fills in.
+    fn read_rows<T: Clone + Default, F: FnMut(&[u8], &mut [T])>(
+        &self,
+        ifd: &Ifd,
+        bits: u32,
+        mut read_row: F,
+    ) -> Result<Vec<T>, &'static str> {
+        let width = self
+            .u32_value(ifd, TAG_IMAGE_WIDTH)
+            .ok_or("Image has no width.")? as usize;
+        let height = self
+            .u32_value(ifd, TAG_IMAGE_LENGTH)
+            .ok_or("Image has no height.")? as usize;
+        let samples_per_pixel = self.u32_value(ifd, TAG_SAMPLES_PER_PIXEL).unwrap_or(1) as usize;
+
+        let compression = self
+            .u32_value(ifd, TAG_COMPRESSION)
+            .unwrap_or(COMPRESSION_NONE);
+        if ![
+            COMPRESSION_NONE,
+            COMPRESSION_LZW,
+            COMPRESSION_DEFLATE,
+            COMPRESSION_PACKBITS,
+            COMPRESSION_OLD_DEFLATE,
+        ]
+        .contains(&compression)
+        {
+            return Err("Only uncompressed, LZW, PackBits and Deflate images are supported.");
+        }
+
+        if samples_per_pixel > 1 && self.u32_value(ifd, TAG_PLANAR_CONFIGURATION).unwrap_or(1) != 1
+        {
+            return Err("Only images with interleaved samples are supported.");
+        }
+
+        // Strips are tiles as wide as the image.
+        let (block_width, block_height, offsets, byte_counts) = if ifd.contains(TAG_TILE_OFFSETS) {
+            (
+                self.u32_value(ifd, TAG_TILE_WIDTH)
+                    .ok_or("Tiled image has no tile width.")? as usize,
+                self.u32_value(ifd, TAG_TILE_LENGTH)
+                    .ok_or("Tiled image has no tile length.")? as usize,
+                self.u64_values(ifd, TAG_TILE_OFFSETS).unwrap_or_default(),
+                self.u64_values(ifd, TAG_TILE_BYTE_COUNTS)
+                    .ok_or("Tiled image has no tile byte counts.")?,
+            )
+        } else {
+            (
+                width,
+                self.u32_value(ifd, TAG_ROWS_PER_STRIP)
+                    .map_or(height, |rows| (rows as usize).min(height)),
+                self.u64_values(ifd, TAG_STRIP_OFFSETS)
+                    .ok_or("Image has no strip offsets.")?,
+                self.u64_values(ifd, TAG_STRIP_BYTE_COUNTS)
+                    .ok_or("Image has no strip byte counts.")?,
+            )
+        };
+
+        if block_width == 0 || block_height == 0 {
+            return Err("Image has empty strips or tiles.");
+        }
+
+        let blocks_across = width / block_width + usize::from(width % block_width != 0);
+        let blocks_down = height / block_height + usize::from(height % block_height != 0);
+        if offsets.len() < blocks_across * blocks_down || byte_counts.len() < offsets.len() {
+            return Err("Image has fewer strips or tiles than it needs.");
+        }
+
+        let row_samples = block_width * samples_per_pixel;
+        let row_bytes = (row_samples * bits as usize + 7) / 8;
+        let mut samples = vec![T::default(); width * height * samples_per_pixel];
+
+        for block_y in 0..blocks_down {
+            for block_x in 0..blocks_across {
+                let block = block_y * blocks_across + block_x;
+                let start = offsets[block] as usize;
+                let end = start
+                    .checked_add(byte_counts[block] as usize)
+                    .ok_or(TRUNCATED)?;
+                let bytes = self.data.get(start..end).ok_or(TRUNCATED)?;
+                let bytes = match compression {
+                    COMPRESSION_LZW => Cow::Owned(decode_lzw(bytes)?),
+                    COMPRESSION_PACKBITS => Cow::Owned(decode_packbits(bytes)?),
+                    COMPRESSION_DEFLATE | COMPRESSION_OLD_DEFLATE => Cow::Owned(
+                        inflate::inflate_bytes_zlib(bytes)
+                            .map_err(|_| "Image has a corrupted Deflate strip or tile.")?,
+                    ),
+                    _ => Cow::Borrowed(bytes),
+                };
+
+                let (x0, y0) = (block_x * block_width, block_y * block_height);
+                let columns = block_width.min(width - x0) * samples_per_pixel;
+
+                for row in 0..block_height.min(height - y0) {
+                    let row_start = row * row_bytes;
+                    let row_data = bytes
+                        .get(row_start..row_start + row_bytes)
+                        .ok_or("Image has fewer samples than it needs.")?;
+                    let destination = ((y0 + row) * width + x0) * samples_per_pixel;
+
+                    read_row(row_data, &mut samples[destination..destination + columns]);
+                }
+            }
+        }
+
+        Ok(samples)
+    }
+
+    /// Unpacks a row of samples. Samples of 8 and 16 bits are byte aligned,
+    /// while other depths are packed from the most significant bit.
+    fn unpack_row(&self, row: &[u8], bits: u32, samples: &mut [u16]) {
+        match bits {
+            8 => {
+                for (sample, &byte) in samples.iter_mut().zip(row) {
+                    *sample = u16::from(byte);
+                }
+            }
+            16 => {
+                for (sample, bytes) in samples.iter_mut().zip(row.chunks(2)) {
+                    *sample = if self.big_endian {
+                        u16::from(bytes[0]) << 8 | u16::from(bytes[1])
+                    } else {
+                        u16::from(bytes[1]) << 8 | u16::from(bytes[0])
+                    };
+                }
+            }
+            _ => {
+                let mut bit = 0;
+                for sample in samples.iter_mut() {
+                    let mut value = 0u32;
+                    for _ in 0..bits {
+                        let byte = row[bit / 8];
+                        value = value << 1 | u32::from(byte >> (7 - bit % 8) & 1);
+                        bit += 1;
+                    }
+                    *sample = value as u16;
+                }
+            }
+        }
+    }
+
+    pub(crate) fn u16_at(&self, offset: usize) -> Result<u16, &'static str> {
+        let bytes = self.data.get(offset..offset + 2).ok_or(TRUNCATED)?;
+
+        Ok(if self.big_endian {
+            u16::from(bytes[0]) << 8 | u16::from(bytes[1])
+        } else {
+            u16::from(bytes[1]) << 8 | u16::from(bytes[0])
+        })
+    }
+
+    pub(crate) fn u32_at(&self, offset: usize) -> Result<u32, &'static str> {
+        let first = u32::from(self.u16_at(offset)?);
+        let second = u32::from(self.u16_at(offset + 2)?);
+
+        Ok(if self.big_endian {
+            first << 16 | second
+        } else {
+            second << 16 | first
+        })
+    }
+
+    pub(crate) fn u64_at(&self, offset: usize) -> Result<u64, &'static str> {
+        let first = u64::from(self.u32_at(offset)?);
+        let second = u64::from(self.u32_at(offset + 4)?);
+
+        Ok(if self.big_endian {
+            first << 32 | second
+        } else {
+            second << 32 | first
+        })
+    }
+
+    /// Reads an 8-byte offset or count of a BigTIFF file.
+    fn offset_at(&self, offset: usize) -> Result<usize, &'static str> {
+        usize::try_from(self.u64_at(offset)?).map_err(|_| TRUNCATED)
+    }
+}
+
+#[cfg(feature = "tiff")]
+pub(crate) fn is_tiff(data: &[u8]) -> bool {
+    [b"II*\0", b"MM\0*", b"II+\0", b"MM\0+"]
+        .iter()
+        .any(|signature| data.starts_with(*signature))
+}
+
+/// Decodes the first image of an RGB or grayscale TIFF file of 8 or 16-bit
+/// samples, with an optional alpha channel, rotating and flipping it upright
+/// as its Orientation tag describes.
+#[cfg(feature = "tiff")]
+pub(crate) fn read_tiff(data: Vec<u8>) -> Result<RgbImage, &'static str> {
+    const PHOTOMETRIC_WHITE_IS_ZERO: u32 = 0;
+    const PHOTOMETRIC_BLACK_IS_ZERO: u32 = 1;
+    const PHOTOMETRIC_RGB: u32 = 2;
+
+    let tiff = TiffReader::new(data)?;
+    let ifds = tiff.ifds()?;
+    let ifd = ifds.first().ok_or("This TIFF file has no images.")?;
+
+    let width = tiff
+        .u32_value(ifd, TAG_IMAGE_WIDTH)
+        .ok_or("Image has no width.")?;
+    let height = tiff
+        .u32_value(ifd, TAG_IMAGE_LENGTH)
+        .ok_or("Image has no height.")?;
+    let channels = tiff.u32_value(ifd, TAG_SAMPLES_PER_PIXEL).unwrap_or(1) as usize;
+    let bit_depth = match tiff.u32_value(ifd, TAG_BITS_PER_SAMPLE).unwrap_or(1) {
+        8 => BitDepth::Eight,
+        16 => BitDepth::Sixteen,
+        _ => return Err("Only TIFF images of 8 or 16-bit samples are supported."),
+    };
+
+    let photometric = tiff.u32_value(ifd, TAG_PHOTOMETRIC_INTERPRETATION);
+    let mut samples = match (photometric, channels) {
+        (Some(PHOTOMETRIC_WHITE_IS_ZERO), 1..=2)
+        | (Some(PHOTOMETRIC_BLACK_IS_ZERO), 1..=2)
+        | (Some(PHOTOMETRIC_RGB), 3..=4) => tiff.image_samples(ifd)?,
+        _ => return Err("Only RGB and grayscale TIFF images are supported."),
+    };
+
+    if photometric == Some(PHOTOMETRIC_WHITE_IS_ZERO) {
+        let white = ((1u32 << bit_depth.to_u32()) - 1) as u16;
+        for sample in samples.iter_mut().step_by(channels) {
+            *sample = white - *sample;
+        }
+    }
+
+    let orientation = tiff
+        .u32_value(ifd, TAG_ORIENTATION)
+        .and_then(|value| Orientation::from_tag(value as u16))
+        .unwrap_or_default();
+
+    Ok(RgbImage::from_samples(width, height, channels, bit_depth, &samples)?.oriented(orientation))
+}
+
+/// Decodes LZW data as TIFF stores it, with codes of 9 to 12 bits packed
+/// from the most significant bit, growing one code early.
+fn decode_lzw(data: &[u8]) -> Result<Vec<u8>, &'static str> {
+    const CLEAR: usize = 256;
+    const END: usize = 257;
+    const CORRUPTED: &str = "TIFF file has corrupted LZW data.";
+
+    let mut table: Vec<Vec<u8>> = (0..=255).map(|byte| vec![byte]).collect();
+    table.extend([Vec::new(), Vec::new()]);
+
+    let mut decoded = Vec::with_capacity(data.len() * 2);
+    let mut previous: Option<usize> = None;
+    let mut code_bits = 9;
+    let mut bit = 0;
+
+    while bit + code_bits <= data.len() * 8 {
+        let code = (bit..bit + code_bits).fold(0, |code, bit| {
+            code << 1 | usize::from(data[bit / 8] >> (7 - bit % 8) & 1)
+        });
+        bit += code_bits;
+
+        if code == CLEAR {
+            table.truncate(END + 1);
+            code_bits = 9;
+            previous = None;
+            continue;
+        }
+        if code == END {
+            break;
+        }
+
+        let entry = match previous {
+            _ if code < table.len() => table[code].clone(),
+            // The code being defined is its previous entry and that entry's
+            // first byte.
+            Some(previous) if code == table.len() => {
+                let mut entry = table[previous].clone();
+                entry.push(entry[0]);
+                entry
+            }
+            _ => return Err(CORRUPTED),
+        };
+        decoded.extend_from_slice(&entry);
+
+        if let Some(previous) = previous {
+            if table.len() < 4096 {
+                let mut new_entry = table[previous].clone();
+                new_entry.push(entry[0]);
+                table.push(new_entry);
+            }
+        }
+        previous = Some(code);
+
+        if table.len() + 1 >= 1 << code_bits && code_bits < 12 {
+            code_bits += 1;
+        }
+    }
+
+    Ok(decoded)
+}
+
+/// Decodes PackBits data, where a count from 0 to 127 precedes one more
+/// literal byte, and a count from -1 to -127 precedes a byte repeated one
+/// more time than its negation.
+fn decode_packbits(data: &[u8]) -> Result<Vec<u8>, &'static str> {
+    const CORRUPTED: &str = "TIFF file has corrupted PackBits data.";
+
+    let mut decoded = Vec::with_capacity(data.len() * 2);
+    let mut position = 0;
+
+    while position < data.len() {
+        let count = data[position] as i8;
+        position += 1;
+
+        match count {
+            0..=127 => {
+                let length = count as usize + 1;
+                decoded.extend_from_slice(data.get(position..position + length).ok_or(CORRUPTED)?);
+                position += length;
+            }
+            -127..=-1 => {
+                let byte = *data.get(position).ok_or(CORRUPTED)?;
+                decoded.extend(std::iter::repeat(byte).take((1 - i32::from(count)) as usize));
+                position += 1;
+            }
+            _ => {}
+        }
+    }
+
+    Ok(decoded)
+}
+
+/// Approximates a value with the simplest fraction within a billionth of it,
+/// as stored in RATIONAL and SRATIONAL fields, using its continued fraction.
+pub(crate) fn rational(value: f64) -> (i32, i32) {
+    let target = value.abs();
+    if !target.is_finite() || target >= f64::from(i32::MAX) {
+        return (if value < 0.0 { -i32::MAX } else { i32::MAX }, 1);
+    }
+
+    // Consecutive convergents of the continued fraction.
+    let (mut numerator, mut denominator) = (1i64, 0i64);
+    let (mut previous_numerator, mut previous_denominator) = (0i64, 1i64);
+    let mut rest = target;
+
+    loop {
+        let term = rest.floor();
+        let next_numerator = term as i64 * numerator + previous_numerator;
+        let next_denominator = term as i64 * denominator + previous_denominator;
+        if next_numerator > i64::from(i32::MAX) || next_denominator > i64::from(i32::MAX) {
+            break;
+        }
+
+        previous_numerator = numerator;
+        previous_denominator = denominator;
+        numerator = next_numerator;
+        denominator = next_denominator;
+
+        let error = (numerator as f64 / denominator as f64 - target).abs();
+        if error <= target * 1e-9 || rest == term {
+            break;
+        }
+        rest = 1.0 / (rest - term);
+    }
+
+    let numerator = numerator as i32;
+    (
+        if value < 0.0 { -numerator } else { numerator },
+        denominator as i32,
+    )
+}
+
+/// Decodes a float of the given number of exponent and mantissa bits, with
+/// the same layout as IEEE 754 ones.
+pub(crate) fn float_from_bits(bits: u32, exponent_bits: u32, mantissa_bits: u32) -> f32 {
+    let bias = (1 << (exponent_bits - 1)) - 1;
+    let sign = (bits >> (exponent_bits + mantissa_bits)) & 1;
+    let exponent = (bits >> mantissa_bits) & ((1 << exponent_bits) - 1);
+    let mantissa = bits & ((1 << mantissa_bits) - 1);
+
+    let magnitude = if exponent == 0 {
+        // Zeros and subnormals.
+        mantissa as f32 * 2f32.powi(1 - bias as i32 - mantissa_bits as i32)
+    } else if exponent == (1 << exponent_bits) - 1 {
+        if mantissa == 0 {
+            f32::INFINITY
+        } else {
+            f32::NAN
+        }
+    } else {
+        f32::from_bits(((exponent + 127 - bias) << 23) | (mantissa << (23 - mantissa_bits)))
+    };
+
+    if sign == 1 {
+        -magnitude
+    } else {
+        magnitude
+    }
+}
+
+/// Encodes a float with the given number of exponent and mantissa bits,
+/// rounding to the nearest value, ties to even. Values too large for it
+/// become infinite.
+pub(crate) fn float_to_bits(value: f32, exponent_bits: u32, mantissa_bits: u32) -> u32 {
+    let bits = value.to_bits();
+    let sign = (bits >> 31) << (exponent_bits + mantissa_bits);
+    let infinity = ((1 << exponent_bits) - 1) << mantissa_bits;
+    let mantissa = bits & 0x7F_FFFF;
+
+    if value.is_nan() {
+        return sign | infinity | (1 << (mantissa_bits - 1));
+    }
+
+    let bias = (1 << (exponent_bits - 1)) - 1;
+    let exponent = ((bits >> 23) & 0xFF) as i32 - 127 + bias;
+    let shift = 23 - mantissa_bits;
+
+    if exponent >= (1 << exponent_bits) - 1 {
+        sign | infinity
+    } else if exponent <= 0 {
+        // Subnormals lose their implicit leading bit, and more of their
+        // mantissa the smaller they are.
+        if exponent < -(mantissa_bits as i32) {
+            sign
+        } else {
+            sign | round_shift(mantissa | 0x80_0000, (shift as i32 + 1 - exponent) as u32)
+        }
+    } else {
+        // Rounding may carry into the exponent, up to infinity.
+        sign | round_shift((exponent as u32) << 23 | mantissa, shift)
+    }
+}
+
+/// Shifts a value right, rounding to the nearest value, ties to even.
+fn round_shift(value: u32, shift: u32) -> u32 {
+    let half = 1 << (shift - 1);
+    let remainder = value & ((1 << shift) - 1);
+    let shifted = value >> shift;
+
+    if remainder > half || (remainder == half && shifted & 1 == 1) {
+        shifted + 1
+    } else {
+        shifted
+    }
+}
+
+fn type_size(field_type: u16) -> Option<usize> {
+    match field_type {
+        1 | 2 | 6 | 7 => Some(1),
+        3 | 8 => Some(2),
+        4 | 9 | 11 | 13 => Some(4),
+        5 | 10 | 12 | 16 | 17 | 18 => Some(8),
+        _ => None,
+    }
+}
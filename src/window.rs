@@ -0,0 +1,78 @@
+// Author: Cláudio Gomes (TofuLynx)
+// Project: emubayer
+// License: GNU GPL Version 3 (https://www.gnu.org/licenses/gpl-3.0.en.html)
+
+use super::{CfaPattern, RawImage, Region, RgbImage, SensorLayout};
+
+impl RgbImage {
+    /// Keeps only the pixels inside the given region.
+    pub fn crop(self, region: Region) -> Result<RgbImage, &'static str> {
+        if !region.lies_within(self.width, self.height) {
+            return Err("Crop region must be a non-empty part of the image.");
+        }
+
+        Ok(self.cropped(region))
+    }
+
+    /// Takes a "picture" through a readout window of a sensor whose CFA
+    /// pattern starts at the top left corner of the image. The pattern of
+    /// the raw data follows the window's origin, so an odd horizontal offset
+    /// turns RGGB into GRBG.
+    pub fn to_raw_window<P: Into<CfaPattern>>(
+        self,
+        cfa_pattern: P,
+        window: Region,
+    ) -> Result<RawImage, &'static str> {
+        let cfa_pattern = cfa_pattern.into().shifted(window.x, window.y);
+        Ok(self.crop(window)?.to_raw(cfa_pattern))
+    }
+}
+
+impl RawImage {
+    /// Keeps only the photosites inside the given region, shifting the CFA
+    /// pattern to the region's origin. The default crop is clipped to the
+    /// region, or covers all of it if they don't overlap.
+    pub fn crop(&self, region: Region) -> Result<RawImage, &'static str> {
+        if !region.lies_within(self.width, self.height) {
+            return Err("Crop region must be a non-empty part of the raw image.");
+        }
+
+        let samples_per_pixel = self.layout.samples_per_pixel() as usize;
+        let row_samples = self.width as usize * samples_per_pixel;
+        let start = region.x as usize * samples_per_pixel;
+        let end = start + region.width as usize * samples_per_pixel;
+
+        let mut data = Vec::with_capacity(region.height as usize * (end - start));
+        for row in self
+            .data
+            .chunks(row_samples)
+            .skip(region.y as usize)
+            .take(region.height as usize)
+        {
+            data.extend_from_slice(&row[start..end]);
+        }
+
+        let layout = match self.layout {
+            SensorLayout::Cfa(ref cfa_pattern) => {
+                SensorLayout::Cfa(cfa_pattern.shifted(region.x, region.y))
+            }
+            ref layout => layout.clone(),
+        };
+
+        let crop = self.default_crop;
+        let (left, top) = (crop.x.max(region.x), crop.y.max(region.y));
+        let right = (crop.x + crop.width).min(region.x + region.width);
+        let bottom = (crop.y + crop.height).min(region.y + region.height);
+        let default_crop = if left < right && top < bottom {
+            Region::new(left - region.x, top - region.y, right - left, bottom - top)
+        } else {
+            Region::new(0, 0, region.width, region.height)
+        };
+
+        Ok(RawImage {
+            layout,
+            default_crop,
+            ..self.with_data(region.width, region.height, data)
+        })
+    }
+}